@@ -0,0 +1,300 @@
+use crate::actions::ClientActions;
+use crate::consts::MAX_CANCEL_ON_DISCONNECT_GRACE_SECS;
+use crate::errors::{ClientError, Error};
+use crate::session::Sequence;
+use crate::utils::split_at_colon;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub type AccountId = String;
+
+/// Disconnect policy values in the credentials file.
+const KEEP_ORDERS: &str = "KEEP";
+const CANCEL_ON_DISCONNECT_FLAG: &str = "COD";
+
+/// Login request, the first message a trader has to send:
+/// `LOGIN:<Name>:<Secret>[:<LastSeenSequence>]`. A trader coming back with the last
+/// sequence number it received gets everything sent after it replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub name: AccountId,
+    pub secret: String,
+    pub last_seen: Option<Sequence>,
+}
+
+impl Credentials {
+    pub fn new_from(message: &str) -> Result<Credentials, ClientError> {
+        let (action, details) = split_at_colon(message).ok_or(ClientError::NotLoggedIn)?;
+        match ClientActions::from_str(&action.to_uppercase()) {
+            Ok(ClientActions::Login) => {}
+            _ => return Err(ClientError::NotLoggedIn),
+        }
+        let fields: Vec<&str> = details.split(':').map(str::trim).collect();
+        let (name, secret, last_seen) = match fields[..] {
+            [name, secret] => (name, secret, None),
+            [name, secret, last_seen] => (name, secret, Some(last_seen)),
+            _ => return Err(ClientError::InvalidTransactionMessage),
+        };
+        if name.is_empty() || secret.is_empty() {
+            return Err(ClientError::InvalidTransactionMessage);
+        }
+        let last_seen = match last_seen {
+            Some(last_seen) => Some(
+                last_seen
+                    .parse::<Sequence>()
+                    .map_err(|_| ClientError::InvalidSequence)?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            name: name.to_string(),
+            secret: secret.to_string(),
+            last_seen,
+        })
+    }
+}
+
+/// What happens to a trader's resting orders when its connection drops.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// Orders keep resting and can still be filled.
+    #[default]
+    Keep,
+    /// Orders are cancelled unless the trader is back within the grace period.
+    Cancel { grace: Duration },
+}
+
+/// Accounts allowed to trade, read from a file with one
+/// `<Name>:<Secret>[:KEEP|COD[:<GraceSeconds>]]` per line, where the optional part
+/// overrides the server-wide cancel-on-disconnect policy for that account.
+/// Empty lines and lines starting with `#` are skipped.
+#[derive(Debug, Default)]
+pub struct Accounts {
+    secrets: HashMap<AccountId, String>,
+    policies: HashMap<AccountId, DisconnectPolicy>,
+}
+
+impl Accounts {
+    pub fn load(path: &str) -> Result<Accounts, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidCredentialsFile(format!("cannot read '{}': {}", path, e)))?;
+        Self::from_str(&content)
+    }
+
+    pub fn authenticate(&self, credentials: &Credentials) -> Result<AccountId, ClientError> {
+        match self.secrets.get(&credentials.name) {
+            Some(secret) if *secret == credentials.secret => Ok(credentials.name.clone()),
+            _ => Err(ClientError::InvalidCredentials),
+        }
+    }
+
+    /// The account's own policy, `None` when it follows the server-wide one.
+    pub fn disconnect_policy(&self, account_id: &str) -> Option<DisconnectPolicy> {
+        self.policies.get(account_id).copied()
+    }
+}
+
+fn parse_policy(policy: &str, grace: Option<&str>) -> Option<DisconnectPolicy> {
+    match (policy.to_uppercase().as_str(), grace) {
+        (KEEP_ORDERS, None) => Some(DisconnectPolicy::Keep),
+        (CANCEL_ON_DISCONNECT_FLAG, grace) => {
+            let grace = match grace {
+                Some(grace) => grace.parse::<u64>().ok()?,
+                None => 0,
+            };
+            Some(DisconnectPolicy::Cancel {
+                grace: Duration::from_secs(grace),
+            })
+        }
+        _ => None,
+    }
+}
+
+impl FromStr for Accounts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut accounts = Accounts::default();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(':').map(str::trim).collect();
+            let account = match fields[..] {
+                [name, secret] => Some((name, secret, None)),
+                [name, secret, policy] => {
+                    parse_policy(policy, None).map(|policy| (name, secret, Some(policy)))
+                }
+                [name, secret, policy, grace] => {
+                    parse_policy(policy, Some(grace)).map(|policy| (name, secret, Some(policy)))
+                }
+                _ => None,
+            };
+            if let Some((_, _, Some(DisconnectPolicy::Cancel { grace }))) = account {
+                if grace > Duration::from_secs(MAX_CANCEL_ON_DISCONNECT_GRACE_SECS) {
+                    return Err(Error::InvalidCredentialsFile(format!(
+                        "line {} should have a grace period of at most {} seconds",
+                        number + 1,
+                        MAX_CANCEL_ON_DISCONNECT_GRACE_SECS
+                    )));
+                }
+            }
+            match account {
+                Some((name, secret, policy)) if !name.is_empty() && !secret.is_empty() => {
+                    accounts
+                        .secrets
+                        .insert(name.to_string(), secret.to_string());
+                    if let Some(policy) = policy {
+                        accounts.policies.insert(name.to_string(), policy);
+                    }
+                }
+                _ => {
+                    return Err(Error::InvalidCredentialsFile(format!(
+                        "line {} should be <Name>:<Secret>[:KEEP|COD[:<GraceSeconds>]]",
+                        number + 1
+                    )))
+                }
+            }
+        }
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> Accounts {
+        Accounts::from_str("# demo accounts\nalice:wonderland\n\nbob : builder\n").unwrap()
+    }
+
+    #[test]
+    fn test_credentials_from_str() {
+        let result = Credentials::new_from("login:alice:wonderland");
+        assert_eq!(
+            result.ok(),
+            Some(Credentials {
+                name: "alice".to_string(),
+                secret: "wonderland".to_string(),
+                last_seen: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_credentials_with_last_seen() {
+        let result = Credentials::new_from("login:alice:wonderland:42");
+        assert_eq!(
+            result.ok().and_then(|credentials| credentials.last_seen),
+            Some(42)
+        );
+        let result = Credentials::new_from("login:alice:wonderland:latest");
+        assert!(matches!(result, Err(ClientError::InvalidSequence)));
+    }
+
+    #[test]
+    fn test_credentials_before_login() {
+        let result = Credentials::new_from("buy:apple:1");
+        assert!(matches!(result, Err(ClientError::NotLoggedIn)));
+    }
+
+    #[test]
+    fn test_credentials_missing_secret() {
+        for message in ["login:alice", "login:alice:", "login::secret"] {
+            let result = Credentials::new_from(message);
+            assert!(matches!(
+                result,
+                Err(ClientError::InvalidTransactionMessage)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let accounts = accounts();
+        for (name, secret) in [("alice", "wonderland"), ("bob", "builder")] {
+            let credentials = Credentials {
+                name: name.to_string(),
+                secret: secret.to_string(),
+                last_seen: None,
+            };
+            assert_eq!(
+                accounts.authenticate(&credentials).ok(),
+                Some(name.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_authenticate_failed() {
+        let accounts = accounts();
+        for (name, secret) in [("alice", "builder"), ("carol", "wonderland")] {
+            let credentials = Credentials {
+                name: name.to_string(),
+                secret: secret.to_string(),
+                last_seen: None,
+            };
+            assert!(matches!(
+                accounts.authenticate(&credentials),
+                Err(ClientError::InvalidCredentials)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_accounts_invalid_line() {
+        let result = Accounts::from_str("alice:wonderland\nbob\n");
+        assert!(matches!(result, Err(Error::InvalidCredentialsFile(_))));
+    }
+
+    #[test]
+    fn test_disconnect_policy() {
+        let accounts = Accounts::from_str(
+            "alice:wonderland:cod\nbob:builder:COD:30\ncarol:singer:keep\ndan:man",
+        )
+        .unwrap();
+        assert_eq!(
+            accounts.disconnect_policy("alice"),
+            Some(DisconnectPolicy::Cancel {
+                grace: Duration::ZERO
+            })
+        );
+        assert_eq!(
+            accounts.disconnect_policy("bob"),
+            Some(DisconnectPolicy::Cancel {
+                grace: Duration::from_secs(30)
+            })
+        );
+        assert_eq!(
+            accounts.disconnect_policy("carol"),
+            Some(DisconnectPolicy::Keep)
+        );
+        assert_eq!(accounts.disconnect_policy("dan"), None);
+    }
+
+    #[test]
+    fn test_accounts_invalid_policy() {
+        for line in [
+            "alice:wonderland:never",
+            "alice:wonderland:cod:soon",
+            "alice:wonderland:keep:30",
+        ] {
+            let result = Accounts::from_str(line);
+            assert!(matches!(result, Err(Error::InvalidCredentialsFile(_))));
+        }
+        let result = Accounts::from_str(
+            "alice:wonderland:cod:86400
+bob:builder:cod:86401",
+        );
+        match result {
+            Err(Error::InvalidCredentialsFile(reason)) => assert_eq!(
+                reason,
+                "line 2 should have a grace period of at most 86400 seconds"
+            ),
+            result => panic!("not an invalid credentials file: {:?}", result),
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use crate::errors::ClientError;
+use crate::price::Price;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ClientActions {
+    Login,
     Buy,
     Sell,
+    Cancel,
+    Amend,
+    Heartbeat,
+    Subscribe,
+    Unsubscribe,
 }
 
 impl FromStr for ClientActions {
@@ -13,8 +21,14 @@ impl FromStr for ClientActions {
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
+            "LOGIN" => Ok(ClientActions::Login),
             "BUY" => Ok(ClientActions::Buy),
             "SELL" => Ok(ClientActions::Sell),
+            "CANCEL" => Ok(ClientActions::Cancel),
+            "AMEND" => Ok(ClientActions::Amend),
+            "HEARTBEAT" => Ok(ClientActions::Heartbeat),
+            "SUBSCRIBE" => Ok(ClientActions::Subscribe),
+            "UNSUBSCRIBE" => Ok(ClientActions::Unsubscribe),
             _ => Err(ClientError::UnknownAction),
         }
     }
@@ -23,23 +37,95 @@ impl FromStr for ClientActions {
 impl Display for ClientActions {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
+            ClientActions::Login => write!(f, "LOGIN"),
             ClientActions::Buy => write!(f, "BUY"),
             ClientActions::Sell => write!(f, "SELL"),
+            ClientActions::Cancel => write!(f, "CANCEL"),
+            ClientActions::Amend => write!(f, "AMEND"),
+            ClientActions::Heartbeat => write!(f, "HEARTBEAT"),
+            ClientActions::Subscribe => write!(f, "SUBSCRIBE"),
+            ClientActions::Unsubscribe => write!(f, "UNSUBSCRIBE"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match &self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    /// Whether an incoming order of this side limited at `limit`
+    /// can execute against a resting order priced at `resting`.
+    /// Market orders (without a limit) cross any price.
+    pub fn crosses(&self, limit: Option<Price>, resting: Price) -> bool {
+        match (&self, limit) {
+            (_, None) => true,
+            (Side::Buy, Some(limit)) => resting <= limit,
+            (Side::Sell, Some(limit)) => resting >= limit,
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "BUY" => Ok(Side::Buy),
+            "SELL" => Ok(Side::Sell),
+            _ => Err(ClientError::UnknownSide),
+        }
+    }
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Side::Buy => write!(f, "BUY"),
+            Side::Sell => write!(f, "SELL"),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum ServerActions {
+    LoggedIn,
     Trade,
+    Execution,
     Ack,
+    Canceled,
+    Replaced,
+    Reject,
+    Heartbeat,
+    Book,
+    BookUpdate,
+    Unsubscribed,
 }
 
 impl Display for ServerActions {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
+            ServerActions::LoggedIn => write!(f, "LOGGED_IN"),
             ServerActions::Trade => write!(f, "TRADE"),
+            ServerActions::Execution => write!(f, "EXEC"),
             ServerActions::Ack => write!(f, "ACK"),
+            ServerActions::Canceled => write!(f, "CANCELED"),
+            ServerActions::Replaced => write!(f, "REPLACED"),
+            ServerActions::Reject => write!(f, "REJECT"),
+            ServerActions::Heartbeat => write!(f, "HEARTBEAT"),
+            ServerActions::Book => write!(f, "BOOK"),
+            ServerActions::BookUpdate => write!(f, "BOOK_UPDATE"),
+            ServerActions::Unsubscribed => write!(f, "UNSUBSCRIBED"),
         }
     }
 }
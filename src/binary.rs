@@ -0,0 +1,646 @@
+use crate::accounts::Credentials;
+use crate::actions::Side;
+use crate::errors::{ClientError, Error};
+use crate::instruments::Instruments;
+use crate::market_data::LevelChange;
+use crate::messages::{Envelope, ServerMessage};
+use crate::order_book::{Level, OrderId};
+use crate::price::Price;
+use crate::products::Product;
+use crate::protocol::OrderEntryCodec;
+use crate::time_in_force::TimeInForce;
+use crate::trader::{check_quantity, ClientRequest, Quantity, Transaction};
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_util::codec::{Decoder, Encoder};
+
+// Every frame starts with the length of the rest as a big-endian u16,
+// followed by a one byte message type. Integers are big-endian, prices are
+// fixed-point units, products are ASCII symbols padded with zeros.
+const LENGTH_SIZE: usize = 2;
+const MAX_FRAME_SIZE: usize = 1024;
+const PRODUCT_SIZE: usize = 8;
+
+// Client messages
+const LOGIN: u8 = 0x01;
+const NEW_ORDER: u8 = 0x02;
+const CANCEL: u8 = 0x03;
+const AMEND: u8 = 0x04;
+const HEARTBEAT: u8 = 0x05;
+const SUBSCRIBE: u8 = 0x06;
+const UNSUBSCRIBE: u8 = 0x07;
+
+// Server messages, all of them followed by the sequence number (0 if none)
+const LOGGED_IN: u8 = 0x81;
+const ACK: u8 = 0x82;
+const CANCELED: u8 = 0x83;
+const REPLACED: u8 = 0x84;
+const FILL: u8 = 0x85;
+const TRADE: u8 = 0x86;
+const REJECT: u8 = 0x87;
+const SERVER_HEARTBEAT: u8 = 0x88;
+const BOOK: u8 = 0x89;
+const BOOK_UPDATE: u8 = 0x8a;
+const UNSUBSCRIBED: u8 = 0x8b;
+
+/// Levels per side of a book snapshot, so it fits in a frame.
+const MAX_BOOK_LEVELS: usize = 1024;
+
+/// Side (1) + product (8) + price (8) + quantity (8) + time in force (1).
+const NEW_ORDER_SIZE: usize = 26;
+/// Order id (8).
+const CANCEL_SIZE: usize = 8;
+/// Order id (8) + price (8) + quantity (8).
+const AMEND_SIZE: usize = 24;
+
+/// A decoded client frame. Order fields are already typed, so no text is parsed
+/// on the way to the book.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryRequest {
+    /// Name length (1), name, secret length (1), secret,
+    /// has last seen (1), last seen sequence (8).
+    Login(Credentials),
+    NewOrder {
+        side: Side,
+        product: Product,
+        /// Zero on the wire for market orders.
+        price: Option<Price>,
+        quantity: Quantity,
+        /// Zero on the wire for the default.
+        time_in_force: Option<TimeInForce>,
+    },
+    Cancel(OrderId),
+    Amend {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    /// No body.
+    Heartbeat,
+    /// Product (8).
+    Subscribe(Product),
+    /// Product (8).
+    Unsubscribe(Product),
+}
+
+impl BinaryRequest {
+    pub fn into_request(self, trader_id: &str) -> Result<ClientRequest, ClientError> {
+        match self {
+            BinaryRequest::Login(_) => Err(ClientError::AlreadyLoggedIn),
+            BinaryRequest::NewOrder {
+                side,
+                product,
+                price,
+                quantity,
+                time_in_force,
+            } => Transaction::new(
+                trader_id,
+                side,
+                product,
+                price,
+                Some(quantity),
+                time_in_force,
+            )
+            .map(ClientRequest::Order),
+            BinaryRequest::Cancel(order_id) => Ok(ClientRequest::Cancel(order_id)),
+            BinaryRequest::Heartbeat => Ok(ClientRequest::Heartbeat),
+            BinaryRequest::Subscribe(product) => Ok(ClientRequest::Subscribe(product)),
+            BinaryRequest::Unsubscribe(product) => Ok(ClientRequest::Unsubscribe(product)),
+            BinaryRequest::Amend {
+                order_id,
+                price,
+                quantity,
+            } => Ok(ClientRequest::Amend {
+                order_id,
+                price,
+                quantity: check_quantity(quantity)?,
+            }),
+        }
+    }
+}
+
+/// Length-prefixed binary order entry. A frame that does not make sense is
+/// decoded into an error for the trader; only broken framing ends the connection.
+#[derive(Debug, Default)]
+pub struct BinaryCodec {
+    /// Products orders can be entered for.
+    instruments: Arc<Instruments>,
+}
+
+impl BinaryCodec {
+    pub fn new(instruments: Arc<Instruments>) -> Self {
+        Self { instruments }
+    }
+}
+
+impl Decoder for BinaryCodec {
+    type Item = Result<BinaryRequest, ClientError>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Error> {
+        if src.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([src[0], src[1]]) as usize;
+        if length == 0 || length > MAX_FRAME_SIZE {
+            let message = format!("invalid frame length {}", length);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        if src.len() < LENGTH_SIZE + length {
+            src.reserve(LENGTH_SIZE + length - src.len());
+            return Ok(None);
+        }
+        src.advance(LENGTH_SIZE);
+        let mut frame = src.split_to(length);
+        Ok(Some(decode_request(&mut frame, &self.instruments)))
+    }
+}
+
+fn decode_request(
+    frame: &mut BytesMut,
+    instruments: &Instruments,
+) -> Result<BinaryRequest, ClientError> {
+    match (frame.get_u8(), frame.len()) {
+        (LOGIN, _) => decode_login(frame),
+        (NEW_ORDER, NEW_ORDER_SIZE) => Ok(BinaryRequest::NewOrder {
+            side: decode_side(frame.get_u8())?,
+            product: decode_product(&frame.split_to(PRODUCT_SIZE))
+                .and_then(|product| instruments.get(product))?
+                .product,
+            price: match frame.get_u64() {
+                0 => None,
+                units => Some(Price::from_units(units)?),
+            },
+            quantity: frame.get_u64(),
+            time_in_force: decode_time_in_force(frame.get_u8())?,
+        }),
+        (CANCEL, CANCEL_SIZE) => Ok(BinaryRequest::Cancel(frame.get_u64())),
+        (AMEND, AMEND_SIZE) => Ok(BinaryRequest::Amend {
+            order_id: frame.get_u64(),
+            price: Price::from_units(frame.get_u64())?,
+            quantity: frame.get_u64(),
+        }),
+        (HEARTBEAT, 0) => Ok(BinaryRequest::Heartbeat),
+        (SUBSCRIBE, PRODUCT_SIZE) => decode_product(frame).map(BinaryRequest::Subscribe),
+        (UNSUBSCRIBE, PRODUCT_SIZE) => decode_product(frame).map(BinaryRequest::Unsubscribe),
+        (NEW_ORDER | CANCEL | AMEND | HEARTBEAT | SUBSCRIBE | UNSUBSCRIBE, _) => {
+            Err(ClientError::InvalidTransactionMessage)
+        }
+        _ => Err(ClientError::UnknownAction),
+    }
+}
+
+fn decode_login(frame: &mut BytesMut) -> Result<BinaryRequest, ClientError> {
+    let name = decode_string(frame).ok_or(ClientError::InvalidTransactionMessage)?;
+    let secret = decode_string(frame).ok_or(ClientError::InvalidTransactionMessage)?;
+    if frame.len() != 9 || name.is_empty() || secret.is_empty() {
+        return Err(ClientError::InvalidTransactionMessage);
+    }
+    let has_last_seen = frame.get_u8() != 0;
+    let last_seen = frame.get_u64();
+    Ok(BinaryRequest::Login(Credentials {
+        name,
+        secret,
+        last_seen: has_last_seen.then_some(last_seen),
+    }))
+}
+
+/// One length byte followed by UTF-8 text.
+fn decode_string(frame: &mut BytesMut) -> Option<String> {
+    let length = *frame.first()? as usize;
+    if frame.len() < 1 + length {
+        return None;
+    }
+    frame.advance(1);
+    String::from_utf8(frame.split_to(length).to_vec()).ok()
+}
+
+fn decode_side(side: u8) -> Result<Side, ClientError> {
+    match side {
+        b'B' => Ok(Side::Buy),
+        b'S' => Ok(Side::Sell),
+        _ => Err(ClientError::UnknownSide),
+    }
+}
+
+fn encode_side(side: Side) -> u8 {
+    match side {
+        Side::Buy => b'B',
+        Side::Sell => b'S',
+    }
+}
+
+fn decode_product(product: &[u8]) -> Result<Product, ClientError> {
+    let end = product
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(product.len());
+    let symbol = std::str::from_utf8(&product[..end]).map_err(|_| ClientError::UnknownProduct)?;
+    Product::from_str(symbol)
+}
+
+fn encode_product(product: Product, dst: &mut BytesMut) {
+    let mut symbol = [0u8; PRODUCT_SIZE];
+    let name = product.to_string();
+    let length = name.len().min(PRODUCT_SIZE);
+    symbol[..length].copy_from_slice(&name.as_bytes()[..length]);
+    dst.put_slice(&symbol);
+}
+
+/// Count (2) followed by price (8) and quantity (8) per level, best first.
+fn encode_levels(levels: &[Level], dst: &mut BytesMut) {
+    let levels = &levels[..levels.len().min(MAX_BOOK_LEVELS)];
+    dst.put_u16(levels.len() as u16);
+    for level in levels {
+        dst.put_u64(level.price.units());
+        dst.put_u64(level.quantity);
+    }
+}
+
+fn decode_time_in_force(time_in_force: u8) -> Result<Option<TimeInForce>, ClientError> {
+    match time_in_force {
+        0 => Ok(None),
+        b'G' => Ok(Some(TimeInForce::Gtc)),
+        b'I' => Ok(Some(TimeInForce::Ioc)),
+        b'F' => Ok(Some(TimeInForce::Fok)),
+        b'D' => Ok(Some(TimeInForce::Day)),
+        _ => Err(ClientError::UnknownTimeInForce),
+    }
+}
+
+fn encode_time_in_force(time_in_force: TimeInForce) -> u8 {
+    match time_in_force {
+        TimeInForce::Gtc => b'G',
+        TimeInForce::Ioc => b'I',
+        TimeInForce::Fok => b'F',
+        TimeInForce::Day => b'D',
+    }
+}
+
+impl Encoder<Envelope> for BinaryCodec {
+    type Error = Error;
+
+    fn encode(&mut self, envelope: Envelope, dst: &mut BytesMut) -> Result<(), Error> {
+        let start = dst.len();
+        dst.put_u16(0);
+        let sequence = envelope.sequence.unwrap_or(0);
+        match envelope.message {
+            ServerMessage::LoggedIn {
+                account_id,
+                last_sequence,
+            } => {
+                dst.put_u8(LOGGED_IN);
+                dst.put_u64(sequence);
+                dst.put_u64(last_sequence);
+                dst.put_u8(account_id.len() as u8);
+                dst.put_slice(account_id.as_bytes());
+            }
+            ServerMessage::Ack { product, order_id } => {
+                dst.put_u8(ACK);
+                dst.put_u64(sequence);
+                encode_product(product, dst);
+                dst.put_u64(order_id);
+            }
+            ServerMessage::Canceled { order_id } => {
+                dst.put_u8(CANCELED);
+                dst.put_u64(sequence);
+                dst.put_u64(order_id);
+                dst.put_u64(0);
+                dst.put_u8(0);
+            }
+            ServerMessage::Expired {
+                order_id,
+                quantity,
+                time_in_force,
+            } => {
+                dst.put_u8(CANCELED);
+                dst.put_u64(sequence);
+                dst.put_u64(order_id);
+                dst.put_u64(quantity);
+                dst.put_u8(encode_time_in_force(time_in_force));
+            }
+            ServerMessage::Replaced(order) => {
+                dst.put_u8(REPLACED);
+                dst.put_u64(sequence);
+                dst.put_u64(order.order_id);
+                encode_product(order.product, dst);
+                dst.put_u8(encode_side(order.side));
+                dst.put_u64(order.price.units());
+                dst.put_u64(order.quantity);
+            }
+            ServerMessage::Execution {
+                trade_id,
+                order_id,
+                side,
+                product,
+                price,
+                quantity,
+                leaves,
+            } => {
+                dst.put_u8(FILL);
+                dst.put_u64(sequence);
+                dst.put_u64(trade_id);
+                dst.put_u64(order_id);
+                dst.put_u8(encode_side(side));
+                encode_product(product, dst);
+                dst.put_u64(price.units());
+                dst.put_u64(quantity);
+                dst.put_u64(leaves);
+            }
+            ServerMessage::Trade {
+                product,
+                price,
+                quantity,
+            } => {
+                dst.put_u8(TRADE);
+                dst.put_u64(sequence);
+                encode_product(product, dst);
+                dst.put_u64(price.units());
+                dst.put_u64(quantity);
+            }
+            ServerMessage::Reject { error, input: _ } => {
+                let code = error.code();
+                let detail = error.to_string();
+                dst.put_u8(REJECT);
+                dst.put_u64(sequence);
+                dst.put_u8(code.len() as u8);
+                dst.put_slice(code.as_bytes());
+                dst.put_u16(detail.len() as u16);
+                dst.put_slice(detail.as_bytes());
+            }
+            ServerMessage::Heartbeat => {
+                dst.put_u8(SERVER_HEARTBEAT);
+                dst.put_u64(sequence);
+            }
+            ServerMessage::BookSnapshot {
+                product,
+                sequence: book_sequence,
+                bids,
+                asks,
+            } => {
+                dst.put_u8(BOOK);
+                dst.put_u64(sequence);
+                encode_product(product, dst);
+                dst.put_u64(book_sequence);
+                encode_levels(&bids, dst);
+                encode_levels(&asks, dst);
+            }
+            ServerMessage::BookUpdate {
+                product,
+                sequence: book_sequence,
+                update,
+            } => {
+                dst.put_u8(BOOK_UPDATE);
+                dst.put_u64(sequence);
+                encode_product(product, dst);
+                dst.put_u64(book_sequence);
+                dst.put_u8(match update.change {
+                    LevelChange::Add => b'A',
+                    LevelChange::Modify => b'M',
+                    LevelChange::Delete => b'D',
+                });
+                dst.put_u8(encode_side(update.side));
+                dst.put_u64(update.price.units());
+                dst.put_u64(update.quantity);
+            }
+            ServerMessage::Unsubscribed { product } => {
+                dst.put_u8(UNSUBSCRIBED);
+                dst.put_u64(sequence);
+                encode_product(product, dst);
+            }
+        }
+        let length = (dst.len() - start - LENGTH_SIZE) as u16;
+        dst[start..start + LENGTH_SIZE].copy_from_slice(&length.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl OrderEntryCodec for BinaryCodec {
+    fn decode_login(&mut self, frame: &Self::Item) -> Result<Credentials, ClientError> {
+        match frame {
+            Ok(BinaryRequest::Login(credentials)) => Ok(credentials.clone()),
+            Ok(_) => Err(ClientError::NotLoggedIn),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    fn decode_request(
+        &mut self,
+        trader_id: &str,
+        frame: &Self::Item,
+    ) -> Result<ClientRequest, ClientError> {
+        frame.clone()?.into_request(trader_id)
+    }
+
+    fn echo(_frame: &Self::Item) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(body: &[u8]) -> BytesMut {
+        let mut frame = BytesMut::new();
+        frame.put_u16(body.len() as u16);
+        frame.put_slice(body);
+        frame
+    }
+
+    fn new_order(side: u8, product: &[u8], price: u64, quantity: u64, tif: u8) -> Vec<u8> {
+        let mut body = vec![NEW_ORDER, side];
+        let mut symbol = [0u8; PRODUCT_SIZE];
+        symbol[..product.len()].copy_from_slice(product);
+        body.extend_from_slice(&symbol);
+        body.extend_from_slice(&price.to_be_bytes());
+        body.extend_from_slice(&quantity.to_be_bytes());
+        body.push(tif);
+        body
+    }
+
+    #[test]
+    fn test_decode_new_order() {
+        let mut src = frame(&new_order(b'B', b"APPLE", 12_500, 10, b'I'));
+        let result = BinaryCodec::default().decode(&mut src).unwrap();
+        assert_eq!(
+            result,
+            Some(Ok(BinaryRequest::NewOrder {
+                side: Side::Buy,
+                product: Product::APPLE,
+                price: Some(Price::from_str("1.25").unwrap()),
+                quantity: 10,
+                time_in_force: Some(TimeInForce::Ioc),
+            }))
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_waits_for_whole_frame() {
+        let whole = frame(&[CANCEL, 0, 0, 0, 0, 0, 0, 0, 7]);
+        let mut src = BytesMut::from(&whole[..5]);
+        assert_eq!(BinaryCodec::default().decode(&mut src).unwrap(), None);
+        src.extend_from_slice(&whole[5..]);
+        assert_eq!(
+            BinaryCodec::default().decode(&mut src).unwrap(),
+            Some(Ok(BinaryRequest::Cancel(7)))
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_frames() {
+        for (body, expected) in [
+            (vec![0x7f], ClientError::UnknownAction),
+            (vec![CANCEL, 1, 2], ClientError::InvalidTransactionMessage),
+            (new_order(b'X', b"APPLE", 1, 1, 0), ClientError::UnknownSide),
+            (
+                new_order(b'B', b"GME!", 1, 1, 0),
+                ClientError::UnknownProduct,
+            ),
+            (
+                new_order(b'B', b"GME", 1, 1, 0),
+                ClientError::UnknownProduct,
+            ),
+            (
+                new_order(b'B', b"APPLE", 1, 1, b'?'),
+                ClientError::UnknownTimeInForce,
+            ),
+        ] {
+            let result = BinaryCodec::default().decode(&mut frame(&body)).unwrap();
+            assert_eq!(result, Some(Err(expected)));
+        }
+        let mut src = frame(&[]);
+        assert!(BinaryCodec::default().decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_decode_login() {
+        let mut body = vec![LOGIN, 5];
+        body.extend_from_slice(b"alice");
+        body.push(10);
+        body.extend_from_slice(b"wonderland");
+        body.push(1);
+        body.extend_from_slice(&42u64.to_be_bytes());
+        let result = BinaryCodec::default().decode(&mut frame(&body)).unwrap();
+        assert_eq!(
+            result,
+            Some(Ok(BinaryRequest::Login(Credentials {
+                name: "alice".to_string(),
+                secret: "wonderland".to_string(),
+                last_seen: Some(42),
+            })))
+        );
+    }
+
+    #[test]
+    fn test_into_request_validates_like_text() {
+        let market = BinaryRequest::NewOrder {
+            side: Side::Buy,
+            product: Product::APPLE,
+            price: None,
+            quantity: 10,
+            time_in_force: Some(TimeInForce::Gtc),
+        };
+        assert_eq!(
+            market.into_request("alice"),
+            Err(ClientError::MarketOrderCannotRest)
+        );
+        let order = BinaryRequest::NewOrder {
+            side: Side::Sell,
+            product: Product::APPLE,
+            price: Some(Price::from_str("2").unwrap()),
+            quantity: 15,
+            time_in_force: None,
+        };
+        assert_eq!(
+            order.into_request("alice"),
+            ClientRequest::new_from(
+                "alice",
+                "sell:apple:2:15".to_string(),
+                &Instruments::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_heartbeats() {
+        let result = BinaryCodec::default()
+            .decode(&mut frame(&[HEARTBEAT]))
+            .unwrap();
+        assert_eq!(result, Some(Ok(BinaryRequest::Heartbeat)));
+        let result = BinaryCodec::default()
+            .decode(&mut frame(&[HEARTBEAT, 0]))
+            .unwrap();
+        assert_eq!(result, Some(Err(ClientError::InvalidTransactionMessage)));
+        let mut dst = BytesMut::new();
+        let heartbeat = Envelope::unsequenced(ServerMessage::Heartbeat);
+        BinaryCodec::default().encode(heartbeat, &mut dst).unwrap();
+        let mut body = vec![SERVER_HEARTBEAT];
+        body.extend_from_slice(&0u64.to_be_bytes());
+        assert_eq!(dst, frame(&body));
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let result = BinaryCodec::default()
+            .decode(&mut frame(b"\x06APPLE\0\0\0"))
+            .unwrap();
+        assert_eq!(result, Some(Ok(BinaryRequest::Subscribe(Product::APPLE))));
+        let result = BinaryCodec::default()
+            .decode(&mut frame(b"\x07APPLE"))
+            .unwrap();
+        assert_eq!(result, Some(Err(ClientError::InvalidTransactionMessage)));
+
+        let snapshot = Envelope::unsequenced(ServerMessage::BookSnapshot {
+            product: Product::APPLE,
+            sequence: 4,
+            bids: Vec::new(),
+            asks: vec![Level {
+                price: Price::from_str("1.25").unwrap(),
+                quantity: 10,
+            }],
+        });
+        let mut dst = BytesMut::new();
+        BinaryCodec::default().encode(snapshot, &mut dst).unwrap();
+        let mut body = vec![BOOK];
+        body.extend_from_slice(&0u64.to_be_bytes());
+        body.extend_from_slice(b"APPLE\0\0\0");
+        body.extend_from_slice(&4u64.to_be_bytes());
+        body.extend_from_slice(&0u16.to_be_bytes());
+        body.extend_from_slice(&1u16.to_be_bytes());
+        body.extend_from_slice(&12_500u64.to_be_bytes());
+        body.extend_from_slice(&10u64.to_be_bytes());
+        assert_eq!(dst, frame(&body));
+    }
+
+    #[test]
+    fn test_encode_fill() {
+        let envelope = Envelope {
+            sequence: Some(3),
+            message: ServerMessage::Execution {
+                trade_id: 11,
+                order_id: 7,
+                side: Side::Sell,
+                product: Product::APPLE,
+                price: Price::from_str("1.25").unwrap(),
+                quantity: 5,
+                leaves: 3,
+            },
+        };
+        let mut dst = BytesMut::new();
+        BinaryCodec::default().encode(envelope, &mut dst).unwrap();
+        let mut body = vec![FILL];
+        body.extend_from_slice(&3u64.to_be_bytes());
+        body.extend_from_slice(&11u64.to_be_bytes());
+        body.extend_from_slice(&7u64.to_be_bytes());
+        body.push(b'S');
+        body.extend_from_slice(b"APPLE\0\0\0");
+        body.extend_from_slice(&12_500u64.to_be_bytes());
+        body.extend_from_slice(&5u64.to_be_bytes());
+        body.extend_from_slice(&3u64.to_be_bytes());
+        assert_eq!(dst, frame(&body));
+    }
+}
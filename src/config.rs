@@ -0,0 +1,930 @@
+use crate::accounts::{AccountId, DisconnectPolicy};
+use crate::consts::{
+    BINARY_PORT, BUFFER_SIZE, CANCEL_ON_DISCONNECT, CANCEL_ON_DISCONNECT_GRACE_SECS, CONFIG_PATH,
+    CREDENTIALS_PATH, FIX_PORT, HTTP_PORT, JOURNAL_DIR, LEDGER_PATH, LOCALHOST,
+    MAX_CANCEL_ON_DISCONNECT_GRACE_SECS, PORT, SHOW_LOG_LEVEL, SHOW_LOG_TARGET,
+    SNAPSHOT_INTERVAL_SECS, TLS_CLIENTS_PATH, WEBSOCKET_PORT,
+};
+use crate::errors::{ClientError, Error};
+use crate::instruments::{default_instruments, Instrument, InstrumentSpec};
+use crate::price::Price;
+use crate::products::Product;
+use crate::trader::Quantity;
+use crate::utils::parse_date;
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Command line options, they override the config file.
+#[derive(Debug, Default, Parser)]
+#[command(about = "Simple trading app")]
+pub struct Cli {
+    /// TOML config file [default: trading.toml, when there is one]
+    #[arg(long, short, value_name = "PATH")]
+    config: Option<String>,
+    /// Accounts file, one `<Name>:<Secret>` per line
+    #[arg(long, value_name = "PATH")]
+    credentials: Option<String>,
+    /// file, or memory to keep nothing across restarts
+    #[arg(long, value_name = "BACKEND")]
+    storage: Option<String>,
+    /// Append-only ledger of all trades
+    #[arg(long, value_name = "PATH")]
+    ledger: Option<String>,
+    /// Directory of the journal and snapshots of the books, replayed at startup
+    #[arg(long, value_name = "DIR")]
+    journal: Option<String>,
+    #[arg(long, value_name = "ADDRESS")]
+    text_address: Option<SocketAddr>,
+    #[arg(long, value_name = "ADDRESS")]
+    binary_address: Option<SocketAddr>,
+    #[arg(long, value_name = "ADDRESS")]
+    fix_address: Option<SocketAddr>,
+    #[arg(long, value_name = "ADDRESS")]
+    websocket_address: Option<SocketAddr>,
+    #[arg(long, value_name = "ADDRESS")]
+    http_address: Option<SocketAddr>,
+    /// Messages buffered for each trader
+    #[arg(long, value_name = "SIZE")]
+    channel_size: Option<usize>,
+    /// Cancel the resting orders of disconnected traders, unless their account
+    /// says otherwise: true or false
+    #[arg(long, value_name = "BOOL")]
+    cancel_on_disconnect: Option<bool>,
+    /// How long a disconnected trader has to come back before its orders are cancelled
+    #[arg(long, value_name = "SECONDS")]
+    cancel_on_disconnect_grace_secs: Option<u64>,
+    #[arg(long, value_name = "QUANTITY")]
+    max_order_quantity: Option<Quantity>,
+    /// Largest price times quantity of a limit order
+    #[arg(long, value_name = "VALUE")]
+    max_order_value: Option<String>,
+    /// off, error, warn, info, debug or trace
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<String>,
+    /// text or json
+    #[arg(long, value_name = "FORMAT")]
+    log_format: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    tls_cert: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    tls_key: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    tls_client_ca: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    tls_clients: Option<String>,
+    /// Instead of running the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Writes the trades of a date range from the ledger as CSV
+    ExportTrades {
+        /// First day, as YYYY-MM-DD in UTC
+        #[arg(long, value_parser = parse_date, value_name = "DATE")]
+        from: i64,
+        /// Last day, included [default: the first day]
+        #[arg(long, value_parser = parse_date, value_name = "DATE")]
+        to: Option<i64>,
+        /// CSV file to write [default: standard output]
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<String>,
+    },
+}
+
+/// The config file as written; anything left out takes its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    credentials: Option<String>,
+    storage: Option<String>,
+    ledger: Option<String>,
+    journal: JournalSection,
+    listen: ListenSection,
+    channels: ChannelsSection,
+    session: SessionSection,
+    /// Rules per symbol.
+    instruments: Option<BTreeMap<String, InstrumentSpec>>,
+    admin: AdminSection,
+    risk: RiskSection,
+    log: LogSection,
+    tls: TlsSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct JournalSection {
+    dir: Option<String>,
+    sync: Option<bool>,
+    snapshot_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ListenSection {
+    text: Option<SocketAddr>,
+    binary: Option<SocketAddr>,
+    fix: Option<SocketAddr>,
+    websocket: Option<SocketAddr>,
+    http: Option<SocketAddr>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ChannelsSection {
+    trader: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SessionSection {
+    cancel_on_disconnect: Option<bool>,
+    cancel_on_disconnect_grace_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AdminSection {
+    accounts: Option<Vec<AccountId>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RiskSection {
+    max_order_quantity: Option<Quantity>,
+    max_order_value: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    level: Option<String>,
+    format: Option<String>,
+    show_level: Option<bool>,
+    show_target: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    cert: Option<String>,
+    key: Option<String>,
+    client_ca: Option<String>,
+    clients: Option<String>,
+}
+
+/// Resolved and validated configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub credentials: String,
+    pub storage: StorageBackend,
+    pub ledger: String,
+    pub journal: JournalConfig,
+    pub listen: Listen,
+    pub settings: Settings,
+    /// Listed at startup.
+    pub instruments: Vec<Instrument>,
+    pub log: LogConfig,
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalConfig {
+    pub dir: String,
+    /// Whether every record is synced to disk before it is acknowledged.
+    pub sync: bool,
+    pub snapshot_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listen {
+    pub text: SocketAddr,
+    pub binary: SocketAddr,
+    pub fix: SocketAddr,
+    pub websocket: SocketAddr,
+    pub http: SocketAddr,
+}
+
+/// What the transaction service needs to know.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Messages buffered for each trader.
+    pub channel_size: usize,
+    pub risk: RiskLimits,
+    /// Accounts that can list and delist instruments.
+    pub admins: Vec<AccountId>,
+    /// For accounts without a policy of their own.
+    pub disconnect_policy: DisconnectPolicy,
+}
+
+/// Orders beyond these are rejected; `None` for no limit.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<Quantity>,
+    /// Price times quantity. Market orders have no price, so only their quantity
+    /// is checked.
+    pub max_order_value: Option<Price>,
+}
+
+/// Where the books and the trade history are kept.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// The journal and the ledger.
+    File,
+    Memory,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub show_level: bool,
+    pub show_target: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert: String,
+    pub key: String,
+    pub client_ca: Option<String>,
+    pub clients: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            channel_size: BUFFER_SIZE,
+            risk: RiskLimits::default(),
+            admins: Vec::new(),
+            disconnect_policy: DisconnectPolicy::default(),
+        }
+    }
+}
+
+impl RiskLimits {
+    pub fn check(&self, price: Option<Price>, quantity: Quantity) -> Result<(), ClientError> {
+        if let Some(limit) = self.max_order_quantity {
+            if quantity > limit {
+                return Err(ClientError::QuantityLimitExceeded(limit));
+            }
+        }
+        if let (Some(limit), Some(price)) = (self.max_order_value, price) {
+            let value = price.units() as u128 * quantity as u128;
+            if value > limit.units() as u128 {
+                return Err(ClientError::ValueLimitExceeded(limit));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Reads the config file and applies the command line on top of it.
+    pub fn load(cli: Cli) -> Result<Self, Error> {
+        let file = match &cli.config {
+            Some(path) => ConfigFile::read(path)?,
+            None if Path::new(CONFIG_PATH).exists() => ConfigFile::read(CONFIG_PATH)?,
+            None => ConfigFile::default(),
+        };
+        Self::resolve(file, cli)
+    }
+
+    fn resolve(file: ConfigFile, cli: Cli) -> Result<Self, Error> {
+        let localhost = IpAddr::from_str(LOCALHOST).expect("LOCALHOST is an IP address");
+        let listen = Listen {
+            text: cli
+                .text_address
+                .or(file.listen.text)
+                .unwrap_or(SocketAddr::new(localhost, PORT)),
+            binary: cli
+                .binary_address
+                .or(file.listen.binary)
+                .unwrap_or(SocketAddr::new(localhost, BINARY_PORT)),
+            fix: cli
+                .fix_address
+                .or(file.listen.fix)
+                .unwrap_or(SocketAddr::new(localhost, FIX_PORT)),
+            websocket: cli
+                .websocket_address
+                .or(file.listen.websocket)
+                .unwrap_or(SocketAddr::new(localhost, WEBSOCKET_PORT)),
+            http: cli
+                .http_address
+                .or(file.listen.http)
+                .unwrap_or(SocketAddr::new(localhost, HTTP_PORT)),
+        };
+        listen.validate()?;
+        let channel_size = cli
+            .channel_size
+            .or(file.channels.trader)
+            .unwrap_or(BUFFER_SIZE);
+        if channel_size == 0 {
+            return Err(invalid("channels.trader should be at least 1"));
+        }
+        let snapshot_interval_secs = file
+            .journal
+            .snapshot_interval_secs
+            .unwrap_or(SNAPSHOT_INTERVAL_SECS);
+        if snapshot_interval_secs == 0 {
+            return Err(invalid(
+                "journal.snapshot_interval_secs should be at least 1",
+            ));
+        }
+        let cancel_on_disconnect = cli
+            .cancel_on_disconnect
+            .or(file.session.cancel_on_disconnect)
+            .unwrap_or(CANCEL_ON_DISCONNECT);
+        let grace_secs = cli
+            .cancel_on_disconnect_grace_secs
+            .or(file.session.cancel_on_disconnect_grace_secs);
+        let disconnect_policy = match (cancel_on_disconnect, grace_secs) {
+            (false, Some(_)) => {
+                return Err(invalid(
+                    "session.cancel_on_disconnect_grace_secs needs session.cancel_on_disconnect",
+                ))
+            }
+            (false, None) => DisconnectPolicy::Keep,
+            (true, Some(grace_secs)) if grace_secs > MAX_CANCEL_ON_DISCONNECT_GRACE_SECS => {
+                return Err(invalid(&format!(
+                    "session.cancel_on_disconnect_grace_secs should be at most {}",
+                    MAX_CANCEL_ON_DISCONNECT_GRACE_SECS
+                )))
+            }
+            (true, grace_secs) => DisconnectPolicy::Cancel {
+                grace: Duration::from_secs(grace_secs.unwrap_or(CANCEL_ON_DISCONNECT_GRACE_SECS)),
+            },
+        };
+        let instruments = match file.instruments {
+            Some(instruments) => parse_instruments(instruments)?,
+            None => default_instruments(),
+        };
+        let max_order_quantity = cli.max_order_quantity.or(file.risk.max_order_quantity);
+        if max_order_quantity == Some(0) {
+            return Err(invalid("risk.max_order_quantity should be at least 1"));
+        }
+        let max_order_value = cli
+            .max_order_value
+            .or(file.risk.max_order_value)
+            .map(|value| {
+                Price::from_str(&value).map_err(|_| {
+                    invalid(&format!(
+                        "risk.max_order_value '{}' should be a positive decimal number",
+                        value
+                    ))
+                })
+            })
+            .transpose()?;
+        let log = LogConfig {
+            level: match cli.log_level.or(file.log.level) {
+                Some(level) => LevelFilter::from_str(&level).map_err(|_| {
+                    invalid(&format!(
+                        "log.level '{}' should be off, error, warn, info, debug or trace",
+                        level
+                    ))
+                })?,
+                None => LevelFilter::Info,
+            },
+            format: match cli.log_format.or(file.log.format).as_deref() {
+                None | Some("text") => LogFormat::Text,
+                Some("json") => LogFormat::Json,
+                Some(format) => {
+                    return Err(invalid(&format!(
+                        "log.format '{}' should be text or json",
+                        format
+                    )))
+                }
+            },
+            show_level: file.log.show_level.unwrap_or(SHOW_LOG_LEVEL),
+            show_target: file.log.show_target.unwrap_or(SHOW_LOG_TARGET),
+        };
+        let tls = match (
+            cli.tls_cert.or(file.tls.cert),
+            cli.tls_key.or(file.tls.key),
+            cli.tls_client_ca.or(file.tls.client_ca),
+        ) {
+            (Some(cert), Some(key), client_ca) => Some(TlsConfig {
+                cert,
+                key,
+                client_ca,
+                clients: cli
+                    .tls_clients
+                    .or(file.tls.clients)
+                    .unwrap_or(TLS_CLIENTS_PATH.to_string()),
+            }),
+            (None, None, None) => None,
+            (Some(_), None, _) | (None, Some(_), _) => {
+                return Err(invalid("tls.cert and tls.key go together"))
+            }
+            (None, None, Some(_)) => {
+                return Err(invalid("tls.client_ca needs tls.cert and tls.key"))
+            }
+        };
+        Ok(Self {
+            credentials: cli
+                .credentials
+                .or(file.credentials)
+                .unwrap_or(CREDENTIALS_PATH.to_string()),
+            storage: match cli.storage.or(file.storage).as_deref() {
+                None | Some("file") => StorageBackend::File,
+                Some("memory") => StorageBackend::Memory,
+                Some(storage) => {
+                    return Err(invalid(&format!(
+                        "storage '{}' should be file or memory",
+                        storage
+                    )))
+                }
+            },
+            ledger: cli
+                .ledger
+                .or(file.ledger)
+                .unwrap_or(LEDGER_PATH.to_string()),
+            journal: JournalConfig {
+                dir: cli
+                    .journal
+                    .or(file.journal.dir)
+                    .unwrap_or(JOURNAL_DIR.to_string()),
+                sync: file.journal.sync.unwrap_or(true),
+                snapshot_interval_secs,
+            },
+            listen,
+            settings: Settings {
+                channel_size,
+                risk: RiskLimits {
+                    max_order_quantity,
+                    max_order_value,
+                },
+                admins: file.admin.accounts.unwrap_or_default(),
+                disconnect_policy,
+            },
+            instruments,
+            log,
+            tls,
+        })
+    }
+}
+
+impl ConfigFile {
+    fn read(path: &str) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| invalid(&format!("cannot read '{}': {}", path, e)))?;
+        Self::from_str(&content).map_err(|e| invalid(&format!("in '{}': {}", path, e)))
+    }
+}
+
+impl FromStr for ConfigFile {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl Listen {
+    /// Every protocol needs its own address.
+    fn validate(&self) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        for (name, address) in self.all() {
+            if !seen.insert(address) {
+                return Err(invalid(&format!(
+                    "listen.{} uses {}, which is already taken",
+                    name, address
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn all(&self) -> [(&'static str, SocketAddr); 5] {
+        [
+            ("text", self.text),
+            ("binary", self.binary),
+            ("fix", self.fix),
+            ("websocket", self.websocket),
+            ("http", self.http),
+        ]
+    }
+}
+
+fn parse_instruments(specs: BTreeMap<String, InstrumentSpec>) -> Result<Vec<Instrument>, Error> {
+    if specs.is_empty() {
+        return Err(invalid("instruments should list at least one product"));
+    }
+    let mut instruments: Vec<Instrument> = Vec::new();
+    for (symbol, spec) in specs {
+        let product = Product::from_str(&symbol.to_uppercase()).map_err(|_| {
+            invalid(&format!(
+                "instruments: '{}' should be 1 to 8 letters or digits",
+                symbol
+            ))
+        })?;
+        if instruments
+            .iter()
+            .any(|instrument| instrument.product == product)
+        {
+            return Err(invalid(&format!(
+                "instruments: {} is listed twice",
+                product
+            )));
+        }
+        let instrument = spec
+            .into_instrument(product)
+            .map_err(|reason| invalid(&format!("instruments: {}", reason)))?;
+        instruments.push(instrument);
+    }
+    Ok(instruments)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidConfig(reason.to_string())
+}
+
+impl Display for StorageBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageBackend::File => write!(f, "file"),
+            StorageBackend::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// The resolved values, one `<key> = <value>` per line.
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "credentials = {}", self.credentials)?;
+        writeln!(f, "storage = {}", self.storage)?;
+        writeln!(f, "ledger = {}", self.ledger)?;
+        writeln!(f, "journal.dir = {}", self.journal.dir)?;
+        writeln!(f, "journal.sync = {}", self.journal.sync)?;
+        writeln!(
+            f,
+            "journal.snapshot_interval_secs = {}",
+            self.journal.snapshot_interval_secs
+        )?;
+        for (name, address) in self.listen.all() {
+            writeln!(f, "listen.{} = {}", name, address)?;
+        }
+        writeln!(f, "channels.trader = {}", self.settings.channel_size)?;
+        match self.settings.disconnect_policy {
+            DisconnectPolicy::Keep => writeln!(f, "session.cancel_on_disconnect = false")?,
+            DisconnectPolicy::Cancel { grace } => {
+                writeln!(f, "session.cancel_on_disconnect = true")?;
+                writeln!(
+                    f,
+                    "session.cancel_on_disconnect_grace_secs = {}",
+                    grace.as_secs()
+                )?;
+            }
+        }
+        for instrument in &self.instruments {
+            writeln!(f, "instruments.{} = {}", instrument.product, instrument)?;
+        }
+        writeln!(f, "admin.accounts = {}", self.settings.admins.join(","))?;
+        let risk = &self.settings.risk;
+        writeln!(
+            f,
+            "risk.max_order_quantity = {}",
+            risk.max_order_quantity
+                .map_or("none".to_string(), |limit| limit.to_string())
+        )?;
+        writeln!(
+            f,
+            "risk.max_order_value = {}",
+            risk.max_order_value
+                .map_or("none".to_string(), |limit| limit.to_string())
+        )?;
+        writeln!(f, "log.level = {}", self.log.level)?;
+        writeln!(f, "log.format = {}", self.log.format)?;
+        match &self.tls {
+            Some(tls) => {
+                writeln!(f, "tls.cert = {}", tls.cert)?;
+                writeln!(f, "tls.key = {}", tls.key)?;
+                if let Some(client_ca) = &tls.client_ca {
+                    writeln!(f, "tls.client_ca = {}", client_ca)?;
+                    writeln!(f, "tls.clients = {}", tls.clients)?;
+                }
+                Ok(())
+            }
+            None => writeln!(f, "tls = off"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(file: &str, args: &[&str]) -> Result<Config, Error> {
+        let file = ConfigFile::from_str(file).unwrap();
+        let cli = Cli::try_parse_from([&["trading_app"], args].concat()).unwrap();
+        Config::resolve(file, cli)
+    }
+
+    fn reason(result: Result<Config, Error>) -> String {
+        match result {
+            Err(Error::InvalidConfig(reason)) => reason,
+            result => panic!("not an invalid config: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = resolve("", &[]).unwrap();
+        assert_eq!(config.credentials, CREDENTIALS_PATH);
+        assert_eq!(config.storage, StorageBackend::File);
+        assert_eq!(config.ledger, LEDGER_PATH);
+        assert_eq!(config.journal.dir, JOURNAL_DIR);
+        assert!(config.journal.sync);
+        assert_eq!(
+            config.journal.snapshot_interval_secs,
+            SNAPSHOT_INTERVAL_SECS
+        );
+        assert_eq!(config.listen.text, SocketAddr::from(([127, 0, 0, 1], PORT)));
+        assert_eq!(config.listen.http.port(), HTTP_PORT);
+        assert_eq!(config.settings, Settings::default());
+        assert_eq!(config.instruments, default_instruments());
+        assert_eq!(config.log.level, LevelFilter::Info);
+        assert_eq!(config.log.format, LogFormat::Text);
+        assert_eq!(config.tls, None);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let file = r#"
+            credentials = "accounts.txt"
+
+            [listen]
+            text = "0.0.0.0:40000"
+            http = "[::1]:8080"
+
+            [channels]
+            trader = 10
+
+            [session]
+            cancel_on_disconnect = true
+            cancel_on_disconnect_grace_secs = 30
+
+            [instruments.APPLE]
+            [instruments.pear]
+            tick_size = "0.01"
+            lot_size = 5
+
+            [admin]
+            accounts = ["alice"]
+
+            [risk]
+            max_order_quantity = 500
+            max_order_value = "1000.5"
+
+            [log]
+            level = "debug"
+            format = "json"
+
+            [tls]
+            cert = "server.pem"
+            key = "server.key"
+        "#;
+        let config = resolve(file, &[]).unwrap();
+        assert_eq!(config.credentials, "accounts.txt");
+        assert_eq!(config.listen.text, SocketAddr::from(([0, 0, 0, 0], 40000)));
+        assert_eq!(config.listen.http.to_string(), "[::1]:8080");
+        assert_eq!(config.listen.fix.port(), FIX_PORT);
+        assert_eq!(config.settings.channel_size, 10);
+        assert_eq!(
+            config.settings.disconnect_policy,
+            DisconnectPolicy::Cancel {
+                grace: Duration::from_secs(30)
+            }
+        );
+        assert_eq!(
+            config.instruments,
+            vec![
+                Instrument::new(Product::APPLE),
+                Instrument {
+                    tick_size: Price::from_str("0.01").unwrap(),
+                    lot_size: 5,
+                    min_quantity: 5,
+                    ..Instrument::new(Product::PEAR)
+                }
+            ]
+        );
+        assert_eq!(config.settings.admins, vec!["alice".to_string()]);
+        assert_eq!(config.settings.risk.max_order_quantity, Some(500));
+        assert_eq!(
+            config.settings.risk.max_order_value,
+            Some(Price::from_str("1000.5").unwrap())
+        );
+        assert_eq!(config.log.level, LevelFilter::Debug);
+        assert_eq!(config.log.format, LogFormat::Json);
+        let tls = config.tls.unwrap();
+        assert_eq!(
+            (tls.cert.as_str(), tls.key.as_str()),
+            ("server.pem", "server.key")
+        );
+        assert_eq!(tls.clients, TLS_CLIENTS_PATH);
+    }
+
+    #[test]
+    fn test_export_trades_command() {
+        let cli = Cli::try_parse_from([
+            "trading_app",
+            "--ledger",
+            "ledger.jsonl",
+            "export-trades",
+            "--from",
+            "2024-02-28",
+            "--to",
+            "2024-03-01",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::ExportTrades {
+                from: 19_781,
+                to: Some(19_783),
+                output: None,
+            })
+        );
+        assert_eq!(
+            Config::resolve(ConfigFile::default(), cli).unwrap().ledger,
+            "ledger.jsonl"
+        );
+        for date in ["2024-02-30", "2024-2-28", "yesterday"] {
+            assert!(Cli::try_parse_from(["trading_app", "export-trades", "--from", date]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_command_line_overrides_file() {
+        let file = r#"
+            [listen]
+            text = "0.0.0.0:40000"
+            [risk]
+            max_order_quantity = 100
+            [log]
+            level = "debug"
+        "#;
+        let args = [
+            "--text-address",
+            "127.0.0.1:50000",
+            "--max-order-quantity",
+            "7",
+            "--cancel-on-disconnect",
+            "true",
+        ];
+        let config = resolve(file, &args).unwrap();
+        assert_eq!(
+            config.listen.text,
+            SocketAddr::from(([127, 0, 0, 1], 50000))
+        );
+        assert_eq!(config.settings.risk.max_order_quantity, Some(7));
+        assert_eq!(
+            config.settings.disconnect_policy,
+            DisconnectPolicy::Cancel {
+                grace: Duration::ZERO
+            }
+        );
+        assert_eq!(config.log.level, LevelFilter::Debug);
+        // The command line can turn off what the file turns on
+        let file = "[session]\ncancel_on_disconnect = true";
+        let config = resolve(file, &["--cancel-on-disconnect", "false"]).unwrap();
+        assert_eq!(config.settings.disconnect_policy, DisconnectPolicy::Keep);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(ConfigFile::from_str("[listen]\ntext = \"localhost\"").is_err());
+        assert!(ConfigFile::from_str("[listen]\nsmtp = \"127.0.0.1:25\"").is_err());
+        assert_eq!(
+            reason(resolve("", &["--fix-address", "127.0.0.1:8080"])),
+            "listen.fix uses 127.0.0.1:8080, which is already taken"
+        );
+        assert_eq!(
+            reason(resolve("[channels]\ntrader = 0", &[])),
+            "channels.trader should be at least 1"
+        );
+        assert_eq!(
+            reason(resolve("[journal]\nsnapshot_interval_secs = 0", &[])),
+            "journal.snapshot_interval_secs should be at least 1"
+        );
+        assert_eq!(
+            reason(resolve(
+                "[session]\ncancel_on_disconnect_grace_secs = 5",
+                &[]
+            )),
+            "session.cancel_on_disconnect_grace_secs needs session.cancel_on_disconnect"
+        );
+        assert_eq!(
+            reason(resolve(
+                "",
+                &[
+                    "--cancel-on-disconnect",
+                    "true",
+                    "--cancel-on-disconnect-grace-secs",
+                    "86401"
+                ]
+            )),
+            "session.cancel_on_disconnect_grace_secs should be at most 86400"
+        );
+        assert_eq!(
+            reason(resolve("[instruments]", &[])),
+            "instruments should list at least one product"
+        );
+        assert_eq!(
+            reason(resolve("[instruments.\"apple pie\"]", &[])),
+            "instruments: 'apple pie' should be 1 to 8 letters or digits"
+        );
+        assert_eq!(
+            reason(resolve("[instruments.apple]\n[instruments.APPLE]", &[])),
+            "instruments: APPLE is listed twice"
+        );
+        assert_eq!(
+            reason(resolve("[instruments.APPLE]\nlot_size = 0", &[])),
+            "instruments: lot_size of APPLE should be at least 1"
+        );
+        assert_eq!(
+            reason(resolve("[risk]\nmax_order_value = \"0\"", &[])),
+            "risk.max_order_value '0' should be a positive decimal number"
+        );
+        assert_eq!(
+            reason(resolve("storage = \"sqlite\"", &[])),
+            "storage 'sqlite' should be file or memory"
+        );
+        assert_eq!(
+            reason(resolve("", &["--log-format", "xml"])),
+            "log.format 'xml' should be text or json"
+        );
+        assert_eq!(
+            reason(resolve("", &["--tls-cert", "server.pem"])),
+            "tls.cert and tls.key go together"
+        );
+    }
+
+    #[test]
+    fn test_missing_config_file() {
+        let cli = Cli {
+            config: Some("no/such/trading.toml".to_string()),
+            ..Cli::default()
+        };
+        assert!(reason(Config::load(cli)).starts_with("cannot read 'no/such/trading.toml'"));
+    }
+
+    #[test]
+    fn test_display() {
+        let config = resolve("", &["--max-order-value", "100"]).unwrap();
+        let lines: Vec<String> = config.to_string().lines().map(String::from).collect();
+        assert!(lines.contains(&"listen.binary = 127.0.0.1:8081".to_string()));
+        assert!(lines.contains(
+            &"instruments.APPLE = tick 0.0001, lot 1, quantity 1 to none, price none to none"
+                .to_string()
+        ));
+        assert!(lines.contains(&"session.cancel_on_disconnect = false".to_string()));
+        assert!(lines.contains(&"risk.max_order_quantity = none".to_string()));
+        assert!(lines.contains(&"risk.max_order_value = 100".to_string()));
+        assert_eq!(lines.last().unwrap(), "tls = off");
+    }
+
+    #[test]
+    fn test_risk_limits() {
+        let limits = RiskLimits {
+            max_order_quantity: Some(100),
+            max_order_value: Some(Price::from_str("50").unwrap()),
+        };
+        let price = |price| Some(Price::from_str(price).unwrap());
+        assert_eq!(limits.check(price("0.5"), 100), Ok(()));
+        assert_eq!(
+            limits.check(price("0.5"), 101),
+            Err(ClientError::QuantityLimitExceeded(100))
+        );
+        assert!(limits.check(price("0.5001"), 100).is_err());
+        assert_eq!(limits.check(None, 100), Ok(()));
+        assert_eq!(
+            RiskLimits::default().check(price("1000000"), 1 << 40),
+            Ok(())
+        );
+    }
+}
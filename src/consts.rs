@@ -1,7 +1,53 @@
+// Defaults, the config file and command line can override them
+pub const CONFIG_PATH: &str = "trading.toml";
+
 // TCP config
 pub const LOCALHOST: &str = "127.0.0.1";
-pub const PORT: i16 = 8080;
+pub const PORT: u16 = 8080;
+pub const BINARY_PORT: u16 = 8081;
+pub const FIX_PORT: u16 = 8082;
+pub const WEBSOCKET_PORT: u16 = 8083;
+pub const HTTP_PORT: u16 = 8084;
 pub const BUFFER_SIZE: usize = 1000;
+// Quiet traders get a heartbeat after the interval and are disconnected when
+// they stay quiet for the timeout after it
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+pub const HEARTBEAT_TIMEOUT_SECS: u64 = 10;
+
+// TLS config
+// With a client CA, traders log in with client certificates it signed,
+// mapped to accounts in the clients file, one `<Name>:<Subject>` per line
+pub const TLS_CLIENTS_PATH: &str = "tls_clients.txt";
+pub const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+// Trading config
+// Listed when the config file has no instruments
+pub const DEFAULT_PRODUCTS: [&str; 5] = ["APPLE", "PEAR", "TOMATO", "POTATO", "ONION"];
+pub const PRICE_DECIMALS: u32 = 4;
+// DAY orders expire at this time of day, in seconds after midnight UTC
+pub const END_OF_DAY_UTC: u64 = 22 * 60 * 60;
+// Every change to the books and snapshots of them, replayed at startup
+pub const JOURNAL_DIR: &str = "journal";
+// The books are snapshotted this often, and the journal compacted
+pub const SNAPSHOT_INTERVAL_SECS: u64 = 300;
+// Every trade ever made, for compliance
+pub const LEDGER_PATH: &str = "trades.jsonl";
+// Trades kept for the HTTP trade history
+pub const RECENT_TRADES: usize = 100;
+
+// Accounts config
+// One `<Name>:<Secret>` per line
+pub const CREDENTIALS_PATH: &str = "credentials.txt";
+// Outbound messages kept per account for replay after a reconnect
+pub const JOURNAL_SIZE: usize = 1000;
+// Sequence numbers reserved per account at a time; a restart skips what is
+// left of the reservation, so numbers are never used twice
+pub const SEQUENCE_BLOCK: u64 = 1000;
+// Server-wide cancel-on-disconnect policy, accounts can override it
+pub const CANCEL_ON_DISCONNECT: bool = false;
+pub const CANCEL_ON_DISCONNECT_GRACE_SECS: u64 = 0;
+// Longest grace period, so orders of a trader who never comes back get pulled
+pub const MAX_CANCEL_ON_DISCONNECT_GRACE_SECS: u64 = 24 * 60 * 60;
 
 // Logs config
 pub const SHOW_LOG_LEVEL: bool = false;
@@ -1,23 +1,115 @@
+use crate::messages::Envelope;
+use crate::price::Price;
+use crate::session::Sequence;
+use crate::trader::Quantity;
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error(transparent)]
     IOError(#[from] std::io::Error),
     #[error(transparent)]
-    MessageSendError(#[from] tokio::sync::mpsc::error::SendError<String>),
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error(transparent)]
+    MessageSendError(#[from] tokio::sync::mpsc::error::SendError<Envelope>),
     #[error(transparent)]
     LineReaderError(#[from] tokio_util::codec::LinesCodecError),
     #[error(transparent)]
     ClientError(#[from] ClientError),
+    #[error("Invalid credentials file: {0}")]
+    InvalidCredentialsFile(String),
+    #[error(transparent)]
+    TlsError(#[from] tokio_rustls::rustls::Error),
+    #[error("Invalid TLS config: {0}")]
+    InvalidTlsConfig(String),
+    #[error("No account for client certificate '{0}'")]
+    UnknownClientCertificate(String),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid journal: {0}")]
+    InvalidJournal(String),
+    #[error("Invalid ledger: {0}")]
+    InvalidLedger(String),
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ClientError {
-    #[error("Unknown product. Choose between: APPLE, PEAR, TOMATO, POTATO or ONION")]
+    #[error("Not logged in. Log in first with LOGIN:<Name>:<Secret>[:<LastSeenSequence>]")]
+    NotLoggedIn,
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+    #[error("Already logged in")]
+    AlreadyLoggedIn,
+    #[error("Invalid sequence number. Should be the last sequence number received, or 0")]
+    InvalidSequence,
+    #[error("Messages {from} to {to} are no longer available for replay")]
+    ReplayUnavailable { from: Sequence, to: Sequence },
+    #[error("Unknown product. It is not listed for trading")]
     UnknownProduct,
-    #[error("Unknown action. Choose between: BUY or SELL")]
+    #[error("Unknown action. Choose between: BUY, SELL, CANCEL, AMEND, HEARTBEAT, SUBSCRIBE or UNSUBSCRIBE")]
     UnknownAction,
-    #[error("Invalid transaction message. Should be <Action>:<Item>")]
+    #[error("Invalid price. Should be a positive decimal number, e.g. 1.25")]
+    InvalidPrice,
+    #[error("Unknown time in force. Choose between: GTC, IOC, FOK or DAY")]
+    UnknownTimeInForce,
+    #[error("Market orders cannot rest. Choose between: IOC or FOK")]
+    MarketOrderCannotRest,
+    #[error("Invalid quantity. Should be a positive whole number")]
+    InvalidQuantity,
+    #[error("Invalid order id. Should be the number received in the order ACK")]
+    InvalidOrderId,
+    #[error("Unknown order. It may have been filled or cancelled already")]
+    UnknownOrder,
+    #[error("Invalid transaction message. Should be <Action>:<Item>:<Price|MKT>[:<Quantity>[:<TimeInForce>]], CANCEL:<OrderId>, AMEND:<OrderId>:<Price>:<Quantity>, HEARTBEAT, SUBSCRIBE:<Item> or UNSUBSCRIBE:<Item>")]
     InvalidTransactionMessage,
+    #[error("Unknown side. Choose between: BUY or SELL")]
+    UnknownSide,
+    #[error("Invalid JSON message: {0}")]
+    InvalidJsonMessage(String),
+    #[error("Quantity above the limit of {0} per order")]
+    QuantityLimitExceeded(Quantity),
+    #[error("Order value above the limit of {0} per order")]
+    ValueLimitExceeded(Price),
+    #[error("Invalid price. Should be a multiple of the tick size {0}")]
+    InvalidTick(Price),
+    #[error("Invalid quantity. Should be a multiple of the lot size {0}")]
+    InvalidLot(Quantity),
+    #[error("Quantity below the minimum of {0}")]
+    QuantityTooSmall(Quantity),
+    #[error("Quantity above the maximum of {0}")]
+    QuantityTooLarge(Quantity),
+    #[error("Price outside the band of the product")]
+    PriceOutsideBand,
+}
+
+impl ClientError {
+    /// Stable code clients can branch on; the message text may change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClientError::NotLoggedIn => "NOT_LOGGED_IN",
+            ClientError::InvalidCredentials => "INVALID_CREDENTIALS",
+            ClientError::AlreadyLoggedIn => "ALREADY_LOGGED_IN",
+            ClientError::InvalidSequence => "INVALID_SEQUENCE",
+            ClientError::ReplayUnavailable { .. } => "REPLAY_UNAVAILABLE",
+            ClientError::UnknownProduct => "UNKNOWN_PRODUCT",
+            ClientError::UnknownAction => "UNKNOWN_ACTION",
+            ClientError::InvalidPrice => "INVALID_PRICE",
+            ClientError::UnknownTimeInForce => "UNKNOWN_TIME_IN_FORCE",
+            ClientError::MarketOrderCannotRest => "MARKET_ORDER_CANNOT_REST",
+            ClientError::InvalidQuantity => "INVALID_QUANTITY",
+            ClientError::InvalidOrderId => "INVALID_ORDER_ID",
+            ClientError::UnknownOrder => "UNKNOWN_ORDER",
+            ClientError::InvalidTransactionMessage => "INVALID_MESSAGE",
+            ClientError::UnknownSide => "UNKNOWN_SIDE",
+            ClientError::InvalidJsonMessage(_) => "INVALID_JSON",
+            ClientError::QuantityLimitExceeded(_) => "QUANTITY_LIMIT_EXCEEDED",
+            ClientError::ValueLimitExceeded(_) => "VALUE_LIMIT_EXCEEDED",
+            ClientError::InvalidTick(_) => "INVALID_TICK",
+            ClientError::InvalidLot(_) => "INVALID_LOT",
+            ClientError::QuantityTooSmall(_) => "QUANTITY_TOO_SMALL",
+            ClientError::QuantityTooLarge(_) => "QUANTITY_TOO_LARGE",
+            ClientError::PriceOutsideBand => "PRICE_OUTSIDE_BAND",
+        }
+    }
 }
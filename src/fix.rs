@@ -0,0 +1,313 @@
+use crate::errors::Error;
+use crate::utils::civil_from_days;
+use bytes::{BufMut, BytesMut};
+use log::warn;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::codec::{Decoder, Encoder};
+
+// Every message starts with BeginString and BodyLength and ends with CheckSum,
+// fields are `<Tag>=<Value>` terminated by SOH.
+const BEGIN_STRING: &[u8] = b"8=FIX.4.4\x01";
+const BODY_LENGTH: &[u8] = b"9=";
+const SOH: u8 = 0x01;
+/// `10=<3 digits><SOH>`.
+const CHECKSUM_SIZE: usize = 7;
+const MAX_BODY_LENGTH: usize = 4096;
+
+pub type Tag = u32;
+
+pub mod tag {
+    use super::Tag;
+
+    pub const AVG_PX: Tag = 6;
+    pub const BEGIN_SEQ_NO: Tag = 7;
+    pub const CL_ORD_ID: Tag = 11;
+    pub const CUM_QTY: Tag = 14;
+    pub const END_SEQ_NO: Tag = 16;
+    pub const EXEC_ID: Tag = 17;
+    pub const LAST_PX: Tag = 31;
+    pub const LAST_QTY: Tag = 32;
+    pub const MSG_SEQ_NUM: Tag = 34;
+    pub const MSG_TYPE: Tag = 35;
+    pub const NEW_SEQ_NO: Tag = 36;
+    pub const ORDER_ID: Tag = 37;
+    pub const ORDER_QTY: Tag = 38;
+    pub const ORD_STATUS: Tag = 39;
+    pub const ORD_TYPE: Tag = 40;
+    pub const ORIG_CL_ORD_ID: Tag = 41;
+    pub const POSS_DUP_FLAG: Tag = 43;
+    pub const PRICE: Tag = 44;
+    pub const REF_SEQ_NUM: Tag = 45;
+    pub const SENDER_COMP_ID: Tag = 49;
+    pub const SENDING_TIME: Tag = 52;
+    pub const SIDE: Tag = 54;
+    pub const SYMBOL: Tag = 55;
+    pub const TARGET_COMP_ID: Tag = 56;
+    pub const TEXT: Tag = 58;
+    pub const TIME_IN_FORCE: Tag = 59;
+    pub const ENCRYPT_METHOD: Tag = 98;
+    pub const CXL_REJ_REASON: Tag = 102;
+    pub const ORD_REJ_REASON: Tag = 103;
+    pub const HEART_BT_INT: Tag = 108;
+    pub const TEST_REQ_ID: Tag = 112;
+    pub const ORIG_SENDING_TIME: Tag = 122;
+    pub const GAP_FILL_FLAG: Tag = 123;
+    pub const RESET_SEQ_NUM_FLAG: Tag = 141;
+    pub const EXEC_TYPE: Tag = 150;
+    pub const LEAVES_QTY: Tag = 151;
+    pub const REF_MSG_TYPE: Tag = 372;
+    pub const BUSINESS_REJECT_REASON: Tag = 380;
+    pub const CXL_REJ_RESPONSE_TO: Tag = 434;
+    pub const USERNAME: Tag = 553;
+    pub const PASSWORD: Tag = 554;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+    pub const BUSINESS_MESSAGE_REJECT: &str = "j";
+}
+
+/// Fields of a FIX message in wire order, without BeginString, BodyLength and
+/// CheckSum which the codec takes care of.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(Tag, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self::default().with(tag::MSG_TYPE, msg_type)
+    }
+
+    pub fn with(mut self, tag: Tag, value: impl ToString) -> Self {
+        self.push(tag, value);
+        self
+    }
+
+    pub fn push(&mut self, tag: Tag, value: impl ToString) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    /// Replaces the value of `tag`, or adds it.
+    pub fn set(&mut self, tag: Tag, value: impl ToString) {
+        match self.fields.iter_mut().find(|(field, _)| *field == tag) {
+            Some((_, current)) => *current = value.to_string(),
+            None => self.push(tag, value),
+        }
+    }
+
+    /// First value of `tag`.
+    pub fn get(&self, tag: Tag) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn fields(&self) -> &[(Tag, String)] {
+        &self.fields
+    }
+
+    /// Parses fields separated by SOH or, as in logs and rejects, by `|`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut message = FixMessage::default();
+        for field in text.split(['\x01', '|']).filter(|field| !field.is_empty()) {
+            let (tag, value) = field.split_once('=')?;
+            message.push(tag.parse().ok()?, value);
+        }
+        Some(message)
+    }
+}
+
+/// `|` separated, the way FIX messages are usually logged.
+impl Display for FixMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (tag, value) in &self.fields {
+            write!(f, "{}={}|", tag, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// FIX 4.4 framing. Messages with a wrong body length or checksum are garbled
+/// and dropped, as the spec asks; anything that is not FIX ends the connection.
+#[derive(Debug, Default)]
+pub struct FixCodec;
+
+impl Decoder for FixCodec {
+    type Item = FixMessage;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<FixMessage>, Error> {
+        loop {
+            let prefix = src.len().min(BEGIN_STRING.len());
+            if src[..prefix] != BEGIN_STRING[..prefix] {
+                return Err(invalid_data("expected FIX.4.4 BeginString"));
+            }
+            let header = &src[prefix..];
+            let end = match header.iter().position(|&byte| byte == SOH) {
+                Some(end) => end,
+                None if header.len() > BODY_LENGTH.len() + 4 => {
+                    return Err(invalid_data("BodyLength too long"))
+                }
+                None => return Ok(None),
+            };
+            let body_length = header[..end]
+                .strip_prefix(BODY_LENGTH)
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .and_then(|digits| digits.parse::<usize>().ok())
+                .filter(|length| *length <= MAX_BODY_LENGTH)
+                .ok_or_else(|| invalid_data("invalid BodyLength"))?;
+            let body_start = prefix + end + 1;
+            let body_end = body_start + body_length;
+            if src.len() < body_end + CHECKSUM_SIZE {
+                src.reserve(body_end + CHECKSUM_SIZE - src.len());
+                return Ok(None);
+            }
+            let frame = src.split_to(body_end + CHECKSUM_SIZE);
+            let trailer = format!("10={:03}\x01", checksum(&frame[..body_end]));
+            let message = std::str::from_utf8(&frame[body_start..body_end])
+                .ok()
+                .filter(|_| &frame[body_end..] == trailer.as_bytes())
+                .and_then(FixMessage::parse);
+            match message {
+                Some(message) => return Ok(Some(message)),
+                None => warn!("dropped garbled FIX message"),
+            }
+        }
+    }
+}
+
+impl Encoder<FixMessage> for FixCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: FixMessage, dst: &mut BytesMut) -> Result<(), Error> {
+        let mut body = String::new();
+        for (tag, value) in message.fields {
+            body.push_str(&format!("{}={}\x01", tag, value));
+        }
+        let start = dst.len();
+        dst.put_slice(BEGIN_STRING);
+        dst.put_slice(format!("9={}\x01", body.len()).as_bytes());
+        dst.put_slice(body.as_bytes());
+        let checksum = checksum(&dst[start..]);
+        dst.put_slice(format!("10={:03}\x01", checksum).as_bytes());
+        Ok(())
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn invalid_data(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+/// UTCTimestamp with milliseconds, e.g. `20240131-17:05:09.123`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn heartbeat() -> FixMessage {
+        FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "OMS")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, 2)
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buffer = BytesMut::new();
+        FixCodec.encode(heartbeat(), &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..],
+            b"8=FIX.4.4\x019=29\x0135=0\x0149=OMS\x0156=EXCHANGE\x0134=2\x0110=054\x01"
+        );
+    }
+
+    #[test]
+    fn test_decode_partial_frames() {
+        let mut encoded = BytesMut::new();
+        FixCodec.encode(heartbeat(), &mut encoded).unwrap();
+        let mut buffer = BytesMut::new();
+        for byte in &encoded[..encoded.len() - 1] {
+            buffer.put_u8(*byte);
+            assert_eq!(FixCodec.decode(&mut buffer).unwrap(), None);
+        }
+        buffer.put_u8(SOH);
+        assert_eq!(FixCodec.decode(&mut buffer).unwrap(), Some(heartbeat()));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_decode_drops_garbled_messages() {
+        let mut buffer = BytesMut::new();
+        FixCodec.encode(heartbeat(), &mut buffer).unwrap();
+        let checksum_digit = buffer.len() - 2;
+        buffer[checksum_digit] = b'9';
+        FixCodec
+            .encode(heartbeat().with(tag::TEST_REQ_ID, "T1"), &mut buffer)
+            .unwrap();
+        let message = FixCodec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(message.get(tag::TEST_REQ_ID), Some("T1"));
+    }
+
+    #[test]
+    fn test_decode_rejects_other_protocols() {
+        let mut buffer = BytesMut::from(&b"LOGIN:alice:wonderland\n"[..]);
+        assert!(FixCodec.decode(&mut buffer).is_err());
+        let mut buffer = BytesMut::from(&b"8=FIX.4.2\x01"[..]);
+        assert!(FixCodec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let message = FixMessage::parse("35=D|11=order-1|54=1|").unwrap();
+        assert_eq!(message.msg_type(), msg_type::NEW_ORDER_SINGLE);
+        assert_eq!(message.get(tag::CL_ORD_ID), Some("order-1"));
+        assert_eq!(message.to_string(), "35=D|11=order-1|54=1|");
+        assert_eq!(FixMessage::parse("35=D|side"), None);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-00:00:00.000");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_222_399_123);
+        assert_eq!(timestamp(time), "20240229-15:59:59.123");
+    }
+}
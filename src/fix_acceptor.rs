@@ -0,0 +1,1461 @@
+use crate::accounts::{AccountId, Credentials};
+use crate::actions::Side;
+use crate::consts::JOURNAL_SIZE;
+use crate::errors::{ClientError, Error};
+use crate::fix::{msg_type, tag, timestamp, FixCodec, FixMessage, Tag};
+use crate::instruments::Instruments;
+use crate::messages::{Envelope, ServerMessage};
+use crate::order_book::{OrderId, RestingOrder};
+use crate::price::Price;
+use crate::products::Product;
+use crate::protocol::{Control, OrderEntryCodec};
+use crate::session::Sequence;
+use crate::time_in_force::TimeInForce;
+use crate::trader::{check_quantity, ClientRequest, Quantity, Transaction};
+use bytes::BytesMut;
+use log::{debug, warn};
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_util::codec::{Decoder, Encoder};
+
+const DEFAULT_HEART_BT_INT: &str = "30";
+/// OrderID of rejected orders, which never got one.
+const NO_ORDER_ID: &str = "NONE";
+
+// ExecType and OrdStatus values
+const NEW: char = '0';
+const PARTIALLY_FILLED: char = '1';
+const FILLED: char = '2';
+const CANCELED: char = '4';
+const REPLACED: char = '5';
+const REJECTED: char = '8';
+const EXPIRED: char = 'C';
+const TRADE: char = 'F';
+
+// Reject reasons
+const UNKNOWN_SYMBOL: &str = "1";
+const ORDER_EXCEEDS_LIMIT: &str = "3";
+const UNKNOWN_ORDER: &str = "1";
+const UNSUPPORTED_MESSAGE_TYPE: &str = "3";
+const OTHER: &str = "99";
+
+/// An order as the FIX client knows it, so reports can carry its ClOrdID.
+#[derive(Debug, Clone)]
+struct FixOrder {
+    /// `None` for orders entered on another connection.
+    cl_ord_id: Option<String>,
+    side: Side,
+    product: Product,
+    quantity: Quantity,
+    cum_quantity: Quantity,
+    /// Price units times quantity of all fills, for the average price.
+    notional: u128,
+}
+
+impl FixOrder {
+    fn new(cl_ord_id: &str, transaction: &Transaction) -> Self {
+        Self {
+            cl_ord_id: Some(cl_ord_id.to_string()),
+            side: transaction.side,
+            product: transaction.product,
+            quantity: transaction.quantity,
+            cum_quantity: 0,
+            notional: 0,
+        }
+    }
+
+    fn status(&self) -> char {
+        if self.cum_quantity > 0 {
+            PARTIALLY_FILLED
+        } else {
+            NEW
+        }
+    }
+
+    fn avg_px(&self) -> String {
+        match self.cum_quantity {
+            0 => "0".to_string(),
+            cum_quantity => Price::from_units((self.notional / cum_quantity as u128) as u64)
+                .map_or("0".to_string(), |price| price.to_string()),
+        }
+    }
+}
+
+/// An application message kept for ResendRequest.
+#[derive(Debug)]
+struct Sent {
+    sequence: Sequence,
+    sending_time: String,
+    msg_type: &'static str,
+    body: FixMessage,
+}
+
+/// FIX session state of an account, kept across its connections so our
+/// sequence numbers carry on and a ResendRequest after a reconnect can be answered.
+#[derive(Debug, Default)]
+pub struct FixSession {
+    next_inbound: Sequence,
+    last_outbound: Sequence,
+    sent: VecDeque<Sent>,
+    exec_ids: u64,
+    orders: HashMap<OrderId, FixOrder>,
+}
+
+/// FIX sessions of the accounts between their connections, shared by the acceptors.
+pub type FixSessions = Arc<Mutex<HashMap<AccountId, FixSession>>>;
+
+/// FIX 4.4 order entry. An account's FIX session carries on where its last
+/// connection left it, unless the Logon sets ResetSeqNumFlag; the first one
+/// takes the MsgSeqNum of the Logon as given and numbers our messages from 1.
+/// Orders are known by their ClOrdID on the wire and by their OrderID here.
+#[derive(Debug, Default)]
+pub struct FixAcceptor {
+    sessions: FixSessions,
+    /// Products orders can be entered for.
+    instruments: Arc<Instruments>,
+    sender_comp_id: String,
+    target_comp_id: String,
+    heart_bt_int: String,
+    reset_seq_num: bool,
+    logged_in: bool,
+    /// Account the session belongs to, once logged in.
+    account_id: Option<AccountId>,
+    session: FixSession,
+    /// Highest MsgSeqNum seen past a gap while our ResendRequest is pending.
+    resend_until: Option<Sequence>,
+    /// New order waiting for the OrderID the server gives it.
+    pending_order: Option<FixOrder>,
+    /// ClOrdIDs of cancel and replace requests, by the order they are for.
+    pending_cancels: HashMap<OrderId, String>,
+    pending_replaces: HashMap<OrderId, String>,
+}
+
+impl FixAcceptor {
+    pub fn new(sessions: FixSessions, instruments: Arc<Instruments>) -> Self {
+        Self {
+            sessions,
+            instruments,
+            ..Default::default()
+        }
+    }
+
+    /// Picks up the account's FIX session where its last connection left it.
+    /// Returns whether messages are missing before the Logon, so they have to
+    /// be requested.
+    fn resume(&mut self, account_id: &str) -> bool {
+        self.account_id = Some(account_id.to_string());
+        let stored = self.sessions.lock().unwrap().remove(account_id);
+        let Some(stored) = stored.filter(|_| !self.reset_seq_num) else {
+            return false;
+        };
+        let logon_sequence = self.session.next_inbound - 1;
+        let expected = stored.next_inbound;
+        self.session = stored;
+        match logon_sequence.cmp(&expected) {
+            Ordering::Equal => self.session.next_inbound = logon_sequence + 1,
+            Ordering::Greater => self.resend_until = Some(logon_sequence),
+            // Only when another connection of the account moved the session
+            // on since the Logon; the initiator's next message ends it
+            Ordering::Less => {}
+        }
+        logon_sequence > expected
+    }
+
+    /// Header for our side of the session, followed by `body`. Resent messages
+    /// keep their number and carry their original sending time.
+    fn frame(
+        &self,
+        msg_type: &str,
+        sequence: Sequence,
+        orig_sending_time: Option<&str>,
+        body: &FixMessage,
+    ) -> FixMessage {
+        let mut message = FixMessage::new(msg_type)
+            .with(tag::SENDER_COMP_ID, &self.sender_comp_id)
+            .with(tag::TARGET_COMP_ID, &self.target_comp_id)
+            .with(tag::MSG_SEQ_NUM, sequence);
+        if orig_sending_time.is_some() {
+            message.push(tag::POSS_DUP_FLAG, "Y");
+        }
+        message.push(tag::SENDING_TIME, timestamp(SystemTime::now()));
+        if let Some(orig_sending_time) = orig_sending_time {
+            message.push(tag::ORIG_SENDING_TIME, orig_sending_time);
+        }
+        for (tag, value) in body.fields() {
+            message.push(*tag, value);
+        }
+        message
+    }
+
+    fn send(
+        &mut self,
+        msg_type: &'static str,
+        body: FixMessage,
+        dst: &mut BytesMut,
+    ) -> Result<(), Error> {
+        self.session.last_outbound += 1;
+        let message = self.frame(msg_type, self.session.last_outbound, None, &body);
+        if !is_admin(msg_type) {
+            if self.session.sent.len() == JOURNAL_SIZE {
+                self.session.sent.pop_front();
+            }
+            self.session.sent.push_back(Sent {
+                sequence: self.session.last_outbound,
+                sending_time: message
+                    .get(tag::SENDING_TIME)
+                    .unwrap_or_default()
+                    .to_string(),
+                msg_type,
+                body,
+            });
+        }
+        FixCodec.encode(message, dst)
+    }
+
+    fn logout(&mut self, text: &str, dst: &mut BytesMut) -> Result<Control, Error> {
+        let body = FixMessage::default().with(tag::TEXT, text);
+        self.send(msg_type::LOGOUT, body, dst)?;
+        Ok(Control::Logout)
+    }
+
+    /// Resends application messages in the requested range and gap fills the
+    /// session messages and anything no longer kept.
+    fn resend(&mut self, request: &FixMessage, dst: &mut BytesMut) -> Result<(), Error> {
+        let begin = parse_sequence(request, tag::BEGIN_SEQ_NO)
+            .unwrap_or(1)
+            .max(1);
+        let end = match parse_sequence(request, tag::END_SEQ_NO) {
+            Some(end) if end > 0 => end.min(self.session.last_outbound),
+            _ => self.session.last_outbound,
+        };
+        let mut gap_from = None;
+        for sequence in begin..=end {
+            let resent = self
+                .session
+                .sent
+                .binary_search_by_key(&sequence, |sent| sent.sequence)
+                .map(|index| &self.session.sent[index])
+                .map(|sent| {
+                    self.frame(
+                        sent.msg_type,
+                        sequence,
+                        Some(&sent.sending_time),
+                        &sent.body,
+                    )
+                });
+            match resent {
+                Ok(message) => {
+                    if let Some(from) = gap_from.take() {
+                        self.gap_fill(from, sequence, dst)?;
+                    }
+                    FixCodec.encode(message, dst)?;
+                }
+                Err(_) => {
+                    gap_from.get_or_insert(sequence);
+                }
+            }
+        }
+        match gap_from {
+            Some(from) => self.gap_fill(from, end + 1, dst),
+            None => Ok(()),
+        }
+    }
+
+    fn gap_fill(
+        &self,
+        from: Sequence,
+        new_seq_no: Sequence,
+        dst: &mut BytesMut,
+    ) -> Result<(), Error> {
+        let body = FixMessage::default()
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, new_seq_no);
+        let now = timestamp(SystemTime::now());
+        let message = self.frame(msg_type::SEQUENCE_RESET, from, Some(&now), &body);
+        FixCodec.encode(message, dst)
+    }
+
+    /// OrderID of a cancel or replace request, given directly or by the
+    /// ClOrdID of the order.
+    fn order_id(&self, request: &FixMessage) -> Result<OrderId, ClientError> {
+        if let Some(order_id) = request.get(tag::ORDER_ID) {
+            return order_id.parse().map_err(|_| ClientError::InvalidOrderId);
+        }
+        let orig_cl_ord_id = required(request, tag::ORIG_CL_ORD_ID)?;
+        self.session
+            .orders
+            .iter()
+            .find(|(_, order)| order.cl_ord_id.as_deref() == Some(orig_cl_ord_id))
+            .map(|(order_id, _)| *order_id)
+            .ok_or(ClientError::UnknownOrder)
+    }
+
+    fn decode_new_order(
+        &mut self,
+        trader_id: &str,
+        request: &FixMessage,
+    ) -> Result<ClientRequest, ClientError> {
+        let cl_ord_id = required(request, tag::CL_ORD_ID)?;
+        let side = decode_side(required(request, tag::SIDE)?)?;
+        let product = self.instruments.listed(required(request, tag::SYMBOL)?)?;
+        let price = match required(request, tag::ORD_TYPE)? {
+            "1" => None,
+            "2" => Some(Price::from_str(required(request, tag::PRICE)?)?),
+            _ => return Err(ClientError::InvalidTransactionMessage),
+        };
+        let quantity = decode_quantity(required(request, tag::ORDER_QTY)?)?;
+        let time_in_force = request
+            .get(tag::TIME_IN_FORCE)
+            .map(decode_time_in_force)
+            .transpose()?;
+        let transaction = Transaction::new(
+            trader_id,
+            side,
+            product,
+            price,
+            Some(quantity),
+            time_in_force,
+        )?;
+        self.pending_order = Some(FixOrder::new(cl_ord_id, &transaction));
+        Ok(ClientRequest::Order(transaction))
+    }
+
+    fn decode_cancel(&mut self, request: &FixMessage) -> Result<ClientRequest, ClientError> {
+        let cl_ord_id = required(request, tag::CL_ORD_ID)?;
+        let order_id = self.order_id(request)?;
+        self.pending_cancels.insert(order_id, cl_ord_id.to_string());
+        Ok(ClientRequest::Cancel(order_id))
+    }
+
+    /// OrderQty of a replace is the new total, so the open quantity is what is
+    /// left of it after the fills so far.
+    fn decode_replace(&mut self, request: &FixMessage) -> Result<ClientRequest, ClientError> {
+        let cl_ord_id = required(request, tag::CL_ORD_ID)?;
+        let order_id = self.order_id(request)?;
+        let price = Price::from_str(required(request, tag::PRICE)?)?;
+        let quantity = decode_quantity(required(request, tag::ORDER_QTY)?)?;
+        let cum_quantity = self
+            .session
+            .orders
+            .get(&order_id)
+            .map_or(0, |order| order.cum_quantity);
+        let quantity = check_quantity(quantity.saturating_sub(cum_quantity))?;
+        self.pending_replaces
+            .insert(order_id, cl_ord_id.to_string());
+        Ok(ClientRequest::Amend {
+            order_id,
+            price,
+            quantity,
+        })
+    }
+
+    fn execution_report(
+        &mut self,
+        order_id: OrderId,
+        order: Option<&FixOrder>,
+        exec_type: char,
+        ord_status: char,
+        leaves: Quantity,
+    ) -> FixMessage {
+        self.session.exec_ids += 1;
+        let mut report = FixMessage::default().with(tag::ORDER_ID, order_id);
+        if let Some(cl_ord_id) = order.and_then(|order| order.cl_ord_id.as_ref()) {
+            report.push(tag::CL_ORD_ID, cl_ord_id);
+        }
+        report.push(tag::EXEC_ID, self.session.exec_ids);
+        report.push(tag::EXEC_TYPE, exec_type);
+        report.push(tag::ORD_STATUS, ord_status);
+        if let Some(order) = order {
+            report.push(tag::SYMBOL, order.product);
+            report.push(tag::SIDE, encode_side(order.side));
+            report.push(tag::ORDER_QTY, order.quantity);
+        }
+        report.push(tag::LEAVES_QTY, leaves);
+        report.push(tag::CUM_QTY, order.map_or(0, |order| order.cum_quantity));
+        report.push(tag::AVG_PX, order.map_or("0".to_string(), FixOrder::avg_px));
+        report
+    }
+
+    /// Orders entered on another connection of the account are not acked here.
+    fn ack(&mut self, order_id: OrderId) -> Option<FixMessage> {
+        let Some(order) = self.session.orders.get(&order_id).cloned() else {
+            debug!("FIX ack of an order from elsewhere ({})", order_id);
+            return None;
+        };
+        Some(self.execution_report(order_id, Some(&order), NEW, NEW, order.quantity))
+    }
+
+    /// Reports for a cancel request carry its ClOrdID, and the order's as OrigClOrdID.
+    fn canceled(&mut self, order_id: OrderId, exec_type: char) -> FixMessage {
+        let order = self.session.orders.remove(&order_id);
+        let mut report = self.execution_report(order_id, order.as_ref(), exec_type, exec_type, 0);
+        if let Some(cl_ord_id) = self.pending_cancels.remove(&order_id) {
+            report.set(tag::CL_ORD_ID, cl_ord_id);
+            if let Some(orig_cl_ord_id) = order.and_then(|order| order.cl_ord_id) {
+                report.push(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+            }
+        }
+        report
+    }
+
+    fn replaced(&mut self, resting: &RestingOrder) -> FixMessage {
+        let mut order = self
+            .session
+            .orders
+            .remove(&resting.order_id)
+            .unwrap_or(FixOrder {
+                cl_ord_id: None,
+                side: resting.side,
+                product: resting.product,
+                quantity: resting.quantity,
+                cum_quantity: 0,
+                notional: 0,
+            });
+        let orig_cl_ord_id = match self.pending_replaces.remove(&resting.order_id) {
+            Some(cl_ord_id) => order.cl_ord_id.replace(cl_ord_id),
+            None => None,
+        };
+        order.quantity = order.cum_quantity + resting.quantity;
+        let mut report = self.execution_report(
+            resting.order_id,
+            Some(&order),
+            REPLACED,
+            order.status(),
+            resting.quantity,
+        );
+        if let Some(orig_cl_ord_id) = orig_cl_ord_id {
+            report.push(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        }
+        report.push(tag::PRICE, resting.price);
+        self.session.orders.insert(resting.order_id, order);
+        report
+    }
+
+    fn fill(
+        &mut self,
+        order_id: OrderId,
+        side: Side,
+        product: Product,
+        price: Price,
+        quantity: Quantity,
+        leaves: Quantity,
+    ) -> FixMessage {
+        let mut order = self.session.orders.remove(&order_id).unwrap_or(FixOrder {
+            cl_ord_id: None,
+            side,
+            product,
+            quantity: quantity + leaves,
+            cum_quantity: 0,
+            notional: 0,
+        });
+        order.cum_quantity += quantity;
+        order.notional += price.units() as u128 * quantity as u128;
+        let ord_status = if leaves == 0 {
+            FILLED
+        } else {
+            PARTIALLY_FILLED
+        };
+        let mut report = self.execution_report(order_id, Some(&order), TRADE, ord_status, leaves);
+        report.push(tag::LAST_PX, price);
+        report.push(tag::LAST_QTY, quantity);
+        if leaves > 0 {
+            self.session.orders.insert(order_id, order);
+        }
+        report
+    }
+
+    /// Rejected orders get a rejected ExecutionReport, cancels and replaces an
+    /// OrderCancelReject; anything else is rejected at the session level.
+    fn reject(&mut self, error: ClientError, input: Option<String>) -> (&'static str, FixMessage) {
+        let text = error.to_string();
+        if !self.logged_in {
+            return (
+                msg_type::LOGOUT,
+                FixMessage::default().with(tag::TEXT, text),
+            );
+        }
+        let request = input
+            .as_deref()
+            .and_then(FixMessage::parse)
+            .unwrap_or_default();
+        match request.msg_type() {
+            msg_type::NEW_ORDER_SINGLE => {
+                self.pending_order = None;
+                self.session.exec_ids += 1;
+                let mut report = FixMessage::default().with(tag::ORDER_ID, NO_ORDER_ID);
+                copy(&request, &mut report, &[tag::CL_ORD_ID]);
+                report.push(tag::EXEC_ID, self.session.exec_ids);
+                report.push(tag::EXEC_TYPE, REJECTED);
+                report.push(tag::ORD_STATUS, REJECTED);
+                copy(
+                    &request,
+                    &mut report,
+                    &[tag::SYMBOL, tag::SIDE, tag::ORDER_QTY],
+                );
+                report.push(tag::LEAVES_QTY, 0);
+                report.push(tag::CUM_QTY, 0);
+                report.push(tag::AVG_PX, 0);
+                let reason = match error {
+                    ClientError::UnknownProduct => UNKNOWN_SYMBOL,
+                    ClientError::QuantityLimitExceeded(_) | ClientError::ValueLimitExceeded(_) => {
+                        ORDER_EXCEEDS_LIMIT
+                    }
+                    _ => OTHER,
+                };
+                report.push(tag::ORD_REJ_REASON, reason);
+                report.push(tag::TEXT, text);
+                (msg_type::EXECUTION_REPORT, report)
+            }
+            msg_type::ORDER_CANCEL_REQUEST | msg_type::ORDER_CANCEL_REPLACE_REQUEST => {
+                let cancel = request.msg_type() == msg_type::ORDER_CANCEL_REQUEST;
+                let order_id = self.order_id(&request).ok();
+                let order = order_id.and_then(|order_id| self.session.orders.get(&order_id));
+                let ord_status = order.map_or(REJECTED, FixOrder::status);
+                let mut body = FixMessage::default().with(
+                    tag::ORDER_ID,
+                    order_id.map_or(NO_ORDER_ID.to_string(), |order_id| order_id.to_string()),
+                );
+                copy(&request, &mut body, &[tag::CL_ORD_ID, tag::ORIG_CL_ORD_ID]);
+                body.push(tag::ORD_STATUS, ord_status);
+                body.push(tag::CXL_REJ_RESPONSE_TO, if cancel { "1" } else { "2" });
+                let reason = match error {
+                    ClientError::UnknownOrder => UNKNOWN_ORDER,
+                    _ => OTHER,
+                };
+                body.push(tag::CXL_REJ_REASON, reason);
+                body.push(tag::TEXT, text);
+                if let Some(order_id) = order_id {
+                    match cancel {
+                        true => self.pending_cancels.remove(&order_id),
+                        false => self.pending_replaces.remove(&order_id),
+                    };
+                }
+                (msg_type::ORDER_CANCEL_REJECT, body)
+            }
+            msg_type => {
+                let ref_seq_num = request.get(tag::MSG_SEQ_NUM).unwrap_or("0");
+                let mut body = FixMessage::default().with(tag::REF_SEQ_NUM, ref_seq_num);
+                body.push(tag::REF_MSG_TYPE, msg_type);
+                if error == ClientError::UnknownAction {
+                    body.push(tag::BUSINESS_REJECT_REASON, UNSUPPORTED_MESSAGE_TYPE);
+                    body.push(tag::TEXT, text);
+                    return (msg_type::BUSINESS_MESSAGE_REJECT, body);
+                }
+                body.push(tag::TEXT, text);
+                (msg_type::REJECT, body)
+            }
+        }
+    }
+}
+
+impl Decoder for FixAcceptor {
+    type Item = FixMessage;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<FixMessage>, Error> {
+        FixCodec.decode(src)
+    }
+}
+
+impl Encoder<Envelope> for FixAcceptor {
+    type Error = Error;
+
+    fn encode(&mut self, envelope: Envelope, dst: &mut BytesMut) -> Result<(), Error> {
+        let (msg_type, body) = match envelope.message {
+            ServerMessage::LoggedIn { account_id, .. } => {
+                self.logged_in = true;
+                let gap = self.resume(&account_id);
+                let mut body = FixMessage::default()
+                    .with(tag::ENCRYPT_METHOD, 0)
+                    .with(tag::HEART_BT_INT, &self.heart_bt_int);
+                if self.reset_seq_num {
+                    body.push(tag::RESET_SEQ_NUM_FLAG, "Y");
+                }
+                self.send(msg_type::LOGON, body, dst)?;
+                if gap {
+                    let body = FixMessage::default()
+                        .with(tag::BEGIN_SEQ_NO, self.session.next_inbound)
+                        .with(tag::END_SEQ_NO, 0);
+                    self.send(msg_type::RESEND_REQUEST, body, dst)?;
+                }
+                return Ok(());
+            }
+            ServerMessage::Ack { order_id, .. } => match self.ack(order_id) {
+                Some(report) => (msg_type::EXECUTION_REPORT, report),
+                None => return Ok(()),
+            },
+            ServerMessage::Canceled { order_id } => (
+                msg_type::EXECUTION_REPORT,
+                self.canceled(order_id, CANCELED),
+            ),
+            ServerMessage::Expired {
+                order_id,
+                time_in_force,
+                ..
+            } => {
+                let exec_type = match time_in_force {
+                    TimeInForce::Day => EXPIRED,
+                    _ => CANCELED,
+                };
+                (
+                    msg_type::EXECUTION_REPORT,
+                    self.canceled(order_id, exec_type),
+                )
+            }
+            ServerMessage::Replaced(order) => (msg_type::EXECUTION_REPORT, self.replaced(&order)),
+            ServerMessage::Execution {
+                order_id,
+                side,
+                product,
+                price,
+                quantity,
+                leaves,
+                ..
+            } => (
+                msg_type::EXECUTION_REPORT,
+                self.fill(order_id, side, product, price, quantity, leaves),
+            ),
+            // The trade tape and book feed are market data, not part of order entry
+            ServerMessage::Trade { .. }
+            | ServerMessage::BookSnapshot { .. }
+            | ServerMessage::BookUpdate { .. }
+            | ServerMessage::Unsubscribed { .. } => return Ok(()),
+            ServerMessage::Reject { error, input } => self.reject(error, input),
+            // A TestRequest, so the initiator has to answer
+            ServerMessage::Heartbeat => {
+                let test_req_id = timestamp(SystemTime::now());
+                let body = FixMessage::default().with(tag::TEST_REQ_ID, test_req_id);
+                (msg_type::TEST_REQUEST, body)
+            }
+        };
+        self.send(msg_type, body, dst)?;
+        if !self.logged_in {
+            // A failed Logon ends the FIX session, a retry starts a new one
+            *self = Self::new(self.sessions.clone(), self.instruments.clone());
+        }
+        Ok(())
+    }
+}
+
+impl OrderEntryCodec for FixAcceptor {
+    fn decode_login(&mut self, logon: &FixMessage) -> Result<Credentials, ClientError> {
+        if logon.msg_type() != msg_type::LOGON {
+            return Err(ClientError::NotLoggedIn);
+        }
+        self.target_comp_id = logon
+            .get(tag::SENDER_COMP_ID)
+            .unwrap_or_default()
+            .to_string();
+        self.sender_comp_id = logon
+            .get(tag::TARGET_COMP_ID)
+            .unwrap_or_default()
+            .to_string();
+        self.heart_bt_int = logon
+            .get(tag::HEART_BT_INT)
+            .unwrap_or(DEFAULT_HEART_BT_INT)
+            .to_string();
+        self.reset_seq_num = logon.get(tag::RESET_SEQ_NUM_FLAG) == Some("Y");
+        self.session.next_inbound = parse_sequence(logon, tag::MSG_SEQ_NUM)
+            .unwrap_or(1)
+            .checked_add(1)
+            .ok_or(ClientError::InvalidSequence)?;
+        let name = required(logon, tag::USERNAME).map_err(|_| ClientError::InvalidCredentials)?;
+        let secret = required(logon, tag::PASSWORD).map_err(|_| ClientError::InvalidCredentials)?;
+        // Messages the account's session has already had cannot come again
+        let expected = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|session| session.next_inbound);
+        if !self.reset_seq_num && expected > Some(self.session.next_inbound - 1) {
+            return Err(ClientError::InvalidSequence);
+        }
+        Ok(Credentials {
+            name: name.to_string(),
+            secret: secret.to_string(),
+            last_seen: None,
+        })
+    }
+
+    fn decode_request(
+        &mut self,
+        trader_id: &str,
+        request: &FixMessage,
+    ) -> Result<ClientRequest, ClientError> {
+        match request.msg_type() {
+            msg_type::LOGON => Err(ClientError::AlreadyLoggedIn),
+            msg_type::NEW_ORDER_SINGLE => self.decode_new_order(trader_id, request),
+            msg_type::ORDER_CANCEL_REQUEST => self.decode_cancel(request),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.decode_replace(request),
+            _ => Err(ClientError::UnknownAction),
+        }
+    }
+
+    fn echo(request: &FixMessage) -> Option<String> {
+        Some(request.to_string())
+    }
+
+    fn placed(&mut self, order_id: OrderId) {
+        if let Some(order) = self.pending_order.take() {
+            self.session.orders.insert(order_id, order);
+        }
+    }
+
+    /// Keeps the FIX session for the account's next connection.
+    fn closed(&mut self) {
+        if let Some(account_id) = self.account_id.take() {
+            let session = std::mem::take(&mut self.session);
+            self.sessions.lock().unwrap().insert(account_id, session);
+        }
+    }
+
+    /// The HeartBtInt agreed at Logon, 0 turns heartbeats off.
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        let seconds = self.heart_bt_int.parse().ok()?;
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+
+    /// Checks MsgSeqNum and answers the session messages. Messages past a gap
+    /// are dropped until the initiator has resent the missing ones.
+    fn control(&mut self, message: &FixMessage, dst: &mut BytesMut) -> Result<Control, Error> {
+        let Some(sequence) = parse_sequence(message, tag::MSG_SEQ_NUM) else {
+            return self.logout("MsgSeqNum missing", dst);
+        };
+        let gap_fill = message.get(tag::GAP_FILL_FLAG) == Some("Y");
+        if message.msg_type() == msg_type::SEQUENCE_RESET && !gap_fill {
+            if let Some(new_seq_no) = parse_sequence(message, tag::NEW_SEQ_NO) {
+                self.session.next_inbound = self.session.next_inbound.max(new_seq_no);
+            }
+            return Ok(Control::Handled);
+        }
+        if sequence < self.session.next_inbound {
+            if message.get(tag::POSS_DUP_FLAG) == Some("Y") {
+                return Ok(Control::Handled);
+            }
+            let text = format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                self.session.next_inbound, sequence
+            );
+            return self.logout(&text, dst);
+        }
+        if sequence > self.session.next_inbound {
+            if message.msg_type() == msg_type::RESEND_REQUEST {
+                self.resend(message, dst)?;
+            }
+            if self.resend_until.is_none() {
+                let body = FixMessage::default()
+                    .with(tag::BEGIN_SEQ_NO, self.session.next_inbound)
+                    .with(tag::END_SEQ_NO, 0);
+                self.send(msg_type::RESEND_REQUEST, body, dst)?;
+            }
+            self.resend_until = self.resend_until.max(Some(sequence));
+            return Ok(Control::Handled);
+        }
+        self.session.next_inbound += 1;
+        if self.resend_until < Some(self.session.next_inbound) {
+            self.resend_until = None;
+        }
+        match message.msg_type() {
+            msg_type::HEARTBEAT => Ok(Control::Handled),
+            msg_type::TEST_REQUEST => {
+                let mut body = FixMessage::default();
+                copy(message, &mut body, &[tag::TEST_REQ_ID]);
+                self.send(msg_type::HEARTBEAT, body, dst)?;
+                Ok(Control::Handled)
+            }
+            msg_type::RESEND_REQUEST => {
+                self.resend(message, dst)?;
+                Ok(Control::Handled)
+            }
+            msg_type::REJECT => {
+                warn!("FIX session reject received ({})", message);
+                Ok(Control::Handled)
+            }
+            msg_type::SEQUENCE_RESET => {
+                if let Some(new_seq_no) = parse_sequence(message, tag::NEW_SEQ_NO) {
+                    self.session.next_inbound = self.session.next_inbound.max(new_seq_no);
+                }
+                Ok(Control::Handled)
+            }
+            msg_type::LOGOUT => {
+                self.send(msg_type::LOGOUT, FixMessage::default(), dst)?;
+                Ok(Control::Logout)
+            }
+            _ => Ok(Control::Request),
+        }
+    }
+}
+
+/// Session level messages, which are gap filled instead of resent.
+fn is_admin(msg_type: &str) -> bool {
+    matches!(
+        msg_type,
+        msg_type::HEARTBEAT
+            | msg_type::TEST_REQUEST
+            | msg_type::RESEND_REQUEST
+            | msg_type::REJECT
+            | msg_type::SEQUENCE_RESET
+            | msg_type::LOGOUT
+            | msg_type::LOGON
+    )
+}
+
+fn required(message: &FixMessage, tag: Tag) -> Result<&str, ClientError> {
+    message
+        .get(tag)
+        .ok_or(ClientError::InvalidTransactionMessage)
+}
+
+fn copy(from: &FixMessage, to: &mut FixMessage, tags: &[Tag]) {
+    for tag in tags {
+        if let Some(value) = from.get(*tag) {
+            to.push(*tag, value);
+        }
+    }
+}
+
+fn parse_sequence(message: &FixMessage, tag: Tag) -> Option<Sequence> {
+    message.get(tag)?.parse().ok()
+}
+
+fn decode_quantity(quantity: &str) -> Result<Quantity, ClientError> {
+    quantity
+        .parse::<Quantity>()
+        .map_err(|_| ClientError::InvalidQuantity)
+}
+
+fn decode_side(side: &str) -> Result<Side, ClientError> {
+    match side {
+        "1" => Ok(Side::Buy),
+        "2" => Ok(Side::Sell),
+        _ => Err(ClientError::UnknownSide),
+    }
+}
+
+fn encode_side(side: Side) -> char {
+    match side {
+        Side::Buy => '1',
+        Side::Sell => '2',
+    }
+}
+
+fn decode_time_in_force(time_in_force: &str) -> Result<TimeInForce, ClientError> {
+    match time_in_force {
+        "0" => Ok(TimeInForce::Day),
+        "1" => Ok(TimeInForce::Gtc),
+        "3" => Ok(TimeInForce::Ioc),
+        "4" => Ok(TimeInForce::Fok),
+        _ => Err(ClientError::UnknownTimeInForce),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::transaction_service::TransactionService;
+    use futures::SinkExt;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+
+    fn logon(sequence: Sequence) -> FixMessage {
+        FixMessage::new(msg_type::LOGON)
+            .with(tag::SENDER_COMP_ID, "OMS")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, sequence)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, 30)
+            .with(tag::USERNAME, "alice")
+            .with(tag::PASSWORD, "wonderland")
+    }
+
+    fn message(msg_type: &str, sequence: Sequence) -> FixMessage {
+        FixMessage::new(msg_type).with(tag::MSG_SEQ_NUM, sequence)
+    }
+
+    fn new_order(sequence: Sequence, cl_ord_id: &str) -> FixMessage {
+        message(msg_type::NEW_ORDER_SINGLE, sequence)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SIDE, 2)
+            .with(tag::SYMBOL, "APPLE")
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "1.25")
+            .with(tag::ORDER_QTY, 10)
+            .with(tag::TIME_IN_FORCE, 1)
+    }
+
+    fn decode_all(buffer: &mut BytesMut) -> Vec<FixMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = FixCodec.decode(buffer).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Logs `acceptor` in as alice with `logon`, returning the replies.
+    fn log_in(acceptor: &mut FixAcceptor, logon: &FixMessage) -> Vec<FixMessage> {
+        let credentials = acceptor.decode_login(logon).unwrap();
+        assert_eq!(credentials.name, "alice");
+        let logged_in = ServerMessage::LoggedIn {
+            account_id: "alice".to_string(),
+            last_sequence: 0,
+        };
+        let mut buffer = BytesMut::new();
+        acceptor
+            .encode(Envelope::unsequenced(logged_in), &mut buffer)
+            .unwrap();
+        decode_all(&mut buffer)
+    }
+
+    fn logged_in() -> FixAcceptor {
+        let mut acceptor = FixAcceptor::default();
+        let reply = log_in(&mut acceptor, &logon(1)).remove(0);
+        assert_eq!(reply.msg_type(), msg_type::LOGON);
+        assert_eq!(reply.get(tag::SENDER_COMP_ID), Some("EXCHANGE"));
+        assert_eq!(reply.get(tag::TARGET_COMP_ID), Some("OMS"));
+        assert_eq!(reply.get(tag::MSG_SEQ_NUM), Some("1"));
+        acceptor
+    }
+
+    fn encode_all(acceptor: &mut FixAcceptor, messages: Vec<ServerMessage>) -> Vec<FixMessage> {
+        let mut buffer = BytesMut::new();
+        for message in messages {
+            acceptor
+                .encode(Envelope::unsequenced(message), &mut buffer)
+                .unwrap();
+        }
+        decode_all(&mut buffer)
+    }
+
+    fn handle(acceptor: &mut FixAcceptor, message: &FixMessage) -> (Control, Vec<FixMessage>) {
+        let mut buffer = BytesMut::new();
+        let control = acceptor.control(message, &mut buffer).unwrap();
+        (control, decode_all(&mut buffer))
+    }
+
+    #[test]
+    fn test_decode_new_order_single() {
+        let mut acceptor = logged_in();
+        assert_eq!(
+            acceptor.decode_request("alice", &new_order(2, "a1")),
+            Ok(ClientRequest::Order(Transaction {
+                trader_id: "alice".to_string(),
+                side: Side::Sell,
+                product: Product::APPLE,
+                price: Price::from_str("1.25").ok(),
+                quantity: 10,
+                time_in_force: TimeInForce::Gtc,
+            }))
+        );
+        let market_gtc = message(msg_type::NEW_ORDER_SINGLE, 3)
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::SIDE, 1)
+            .with(tag::SYMBOL, "APPLE")
+            .with(tag::ORD_TYPE, 1)
+            .with(tag::ORDER_QTY, 5)
+            .with(tag::TIME_IN_FORCE, 1);
+        assert_eq!(
+            acceptor.decode_request("alice", &market_gtc),
+            Err(ClientError::MarketOrderCannotRest)
+        );
+    }
+
+    #[test]
+    fn test_reports_carry_cl_ord_ids() {
+        let mut acceptor = logged_in();
+        acceptor
+            .decode_request("alice", &new_order(2, "a1"))
+            .unwrap();
+        acceptor.placed(7);
+        let mut buffer = BytesMut::new();
+        for message in [
+            ServerMessage::Ack {
+                product: Product::APPLE,
+                order_id: 7,
+            },
+            ServerMessage::Execution {
+                trade_id: 1,
+                order_id: 7,
+                side: Side::Sell,
+                product: Product::APPLE,
+                price: Price::from_str("1.25").unwrap(),
+                quantity: 4,
+                leaves: 6,
+            },
+        ] {
+            acceptor
+                .encode(Envelope::unsequenced(message), &mut buffer)
+                .unwrap();
+        }
+        let cancel = message(msg_type::ORDER_CANCEL_REQUEST, 3)
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::ORIG_CL_ORD_ID, "a1");
+        assert_eq!(
+            acceptor.decode_request("alice", &cancel),
+            Ok(ClientRequest::Cancel(7))
+        );
+        acceptor
+            .encode(
+                Envelope::unsequenced(ServerMessage::Canceled { order_id: 7 }),
+                &mut buffer,
+            )
+            .unwrap();
+        let reports = decode_all(&mut buffer);
+        let field = |index: usize, tag: Tag| reports[index].get(tag).unwrap().to_string();
+        assert_eq!(field(0, tag::EXEC_TYPE), "0");
+        assert_eq!(field(0, tag::CL_ORD_ID), "a1");
+        assert_eq!(field(0, tag::ORDER_ID), "7");
+        assert_eq!(field(1, tag::EXEC_TYPE), "F");
+        assert_eq!(field(1, tag::ORD_STATUS), "1");
+        assert_eq!(field(1, tag::CUM_QTY), "4");
+        assert_eq!(field(1, tag::AVG_PX), "1.25");
+        assert_eq!(field(2, tag::EXEC_TYPE), "4");
+        assert_eq!(field(2, tag::CL_ORD_ID), "a2");
+        assert_eq!(field(2, tag::ORIG_CL_ORD_ID), "a1");
+    }
+
+    #[test]
+    fn test_replace_quantity_includes_fills() {
+        let mut acceptor = logged_in();
+        acceptor.session.orders.insert(
+            7,
+            FixOrder {
+                cum_quantity: 4,
+                ..FixOrder::new(
+                    "a1",
+                    &Transaction::new(
+                        "alice",
+                        Side::Sell,
+                        Product::APPLE,
+                        Price::from_str("1").ok(),
+                        Some(10),
+                        None,
+                    )
+                    .unwrap(),
+                )
+            },
+        );
+        let replace = message(msg_type::ORDER_CANCEL_REPLACE_REQUEST, 2)
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::ORIG_CL_ORD_ID, "a1")
+            .with(tag::PRICE, "1.5")
+            .with(tag::ORDER_QTY, 12);
+        assert_eq!(
+            acceptor.decode_request("alice", &replace),
+            Ok(ClientRequest::Amend {
+                order_id: 7,
+                price: Price::from_str("1.5").unwrap(),
+                quantity: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn test_rejects() {
+        let mut acceptor = logged_in();
+        let mut buffer = BytesMut::new();
+        for (error, request) in [
+            (ClientError::UnknownProduct, new_order(2, "a1")),
+            (
+                ClientError::UnknownOrder,
+                message(msg_type::ORDER_CANCEL_REQUEST, 3)
+                    .with(tag::CL_ORD_ID, "a2")
+                    .with(tag::ORIG_CL_ORD_ID, "a1"),
+            ),
+            (ClientError::UnknownAction, message("R", 4)),
+        ] {
+            let reject = ServerMessage::Reject {
+                error,
+                input: FixAcceptor::echo(&request),
+            };
+            acceptor
+                .encode(Envelope::unsequenced(reject), &mut buffer)
+                .unwrap();
+        }
+        let rejects = decode_all(&mut buffer);
+        assert_eq!(rejects[0].msg_type(), msg_type::EXECUTION_REPORT);
+        assert_eq!(rejects[0].get(tag::ORD_STATUS), Some("8"));
+        assert_eq!(rejects[0].get(tag::CL_ORD_ID), Some("a1"));
+        assert_eq!(rejects[0].get(tag::ORD_REJ_REASON), Some(UNKNOWN_SYMBOL));
+        assert_eq!(rejects[1].msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(rejects[1].get(tag::CXL_REJ_RESPONSE_TO), Some("1"));
+        assert_eq!(rejects[1].get(tag::CXL_REJ_REASON), Some(UNKNOWN_ORDER));
+        assert_eq!(rejects[2].msg_type(), msg_type::BUSINESS_MESSAGE_REJECT);
+        assert_eq!(rejects[2].get(tag::REF_SEQ_NUM), Some("4"));
+        assert_eq!(rejects[2].get(tag::REF_MSG_TYPE), Some("R"));
+    }
+
+    #[test]
+    fn test_rejected_order_is_not_acked() {
+        let mut acceptor = logged_in();
+        let rejected = new_order(2, "a1");
+        acceptor.decode_request("alice", &rejected).unwrap();
+        let reject = ServerMessage::Reject {
+            error: ClientError::InvalidPrice,
+            input: FixAcceptor::echo(&rejected),
+        };
+        acceptor
+            .decode_request("alice", &new_order(3, "a2"))
+            .unwrap();
+        acceptor.placed(8);
+        let ack = |order_id| ServerMessage::Ack {
+            product: Product::APPLE,
+            order_id,
+        };
+        // Order 9 was entered over another connection of the account
+        let reports = encode_all(&mut acceptor, vec![reject, ack(8), ack(9)]);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].get(tag::ORD_STATUS), Some("8"));
+        assert_eq!(reports[0].get(tag::CL_ORD_ID), Some("a1"));
+        assert_eq!(reports[1].get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(reports[1].get(tag::CL_ORD_ID), Some("a2"));
+        assert_eq!(reports[1].get(tag::ORDER_ID), Some("8"));
+    }
+
+    #[test]
+    fn test_logon_sequence_overflow_logs_out() {
+        let mut acceptor = FixAcceptor::default();
+        assert_eq!(
+            acceptor.decode_login(&logon(Sequence::MAX)),
+            Err(ClientError::InvalidSequence)
+        );
+        let reject = ServerMessage::Reject {
+            error: ClientError::InvalidSequence,
+            input: None,
+        };
+        let logout = encode_all(&mut acceptor, vec![reject]).remove(0);
+        assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    }
+
+    #[test]
+    fn test_session_carries_on_after_reconnect() {
+        let sessions = FixSessions::default();
+        let mut acceptor = FixAcceptor::new(sessions.clone(), Arc::default());
+        log_in(&mut acceptor, &logon(1));
+        let order = new_order(2, "a1");
+        assert_eq!(handle(&mut acceptor, &order).0, Control::Request);
+        acceptor.decode_request("alice", &order).unwrap();
+        acceptor.placed(7);
+        let ack = ServerMessage::Ack {
+            product: Product::APPLE,
+            order_id: 7,
+        };
+        encode_all(&mut acceptor, vec![ack]);
+        acceptor.closed();
+
+        // Messages the session already had are too low
+        let mut acceptor = FixAcceptor::new(sessions.clone(), Arc::default());
+        assert_eq!(
+            acceptor.decode_login(&logon(2)),
+            Err(ClientError::InvalidSequence)
+        );
+
+        let mut acceptor = FixAcceptor::new(sessions.clone(), Arc::default());
+        let reply = log_in(&mut acceptor, &logon(3)).remove(0);
+        assert_eq!(reply.get(tag::MSG_SEQ_NUM), Some("3"));
+        let resend_request = message(msg_type::RESEND_REQUEST, 4)
+            .with(tag::BEGIN_SEQ_NO, 2)
+            .with(tag::END_SEQ_NO, 2);
+        let (_, replies) = handle(&mut acceptor, &resend_request);
+        assert_eq!(replies[0].msg_type(), msg_type::EXECUTION_REPORT);
+        assert_eq!(replies[0].get(tag::MSG_SEQ_NUM), Some("2"));
+        assert_eq!(replies[0].get(tag::CL_ORD_ID), Some("a1"));
+        acceptor.closed();
+
+        // Messages missed before the Logon are requested
+        let mut acceptor = FixAcceptor::new(sessions.clone(), Arc::default());
+        let replies = log_in(&mut acceptor, &logon(8));
+        assert_eq!(replies[0].msg_type(), msg_type::LOGON);
+        assert_eq!(replies[1].msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(replies[1].get(tag::BEGIN_SEQ_NO), Some("5"));
+        assert_eq!(acceptor.resend_until, Some(8));
+        acceptor.closed();
+
+        let mut acceptor = FixAcceptor::new(sessions, Arc::default());
+        let reset = logon(1).with(tag::RESET_SEQ_NUM_FLAG, "Y");
+        let reply = log_in(&mut acceptor, &reset).remove(0);
+        assert_eq!(reply.get(tag::MSG_SEQ_NUM), Some("1"));
+        assert_eq!(reply.get(tag::RESET_SEQ_NUM_FLAG), Some("Y"));
+        assert_eq!(acceptor.session.next_inbound, 2);
+    }
+
+    #[test]
+    fn test_failed_logon_logs_out() {
+        let mut acceptor = FixAcceptor::default();
+        acceptor.decode_login(&logon(1)).unwrap();
+        let reject = ServerMessage::Reject {
+            error: ClientError::InvalidCredentials,
+            input: None,
+        };
+        let mut buffer = BytesMut::new();
+        acceptor
+            .encode(Envelope::unsequenced(reject), &mut buffer)
+            .unwrap();
+        let logout = decode_all(&mut buffer).remove(0);
+        assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+        assert_eq!(logout.get(tag::MSG_SEQ_NUM), Some("1"));
+        assert_eq!(acceptor.session.last_outbound, 0);
+    }
+
+    #[test]
+    fn test_test_request() {
+        let mut acceptor = logged_in();
+        let test_request = message(msg_type::TEST_REQUEST, 2).with(tag::TEST_REQ_ID, "ping");
+        let (control, replies) = handle(&mut acceptor, &test_request);
+        assert_eq!(control, Control::Handled);
+        assert_eq!(replies[0].msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(replies[0].get(tag::TEST_REQ_ID), Some("ping"));
+        assert_eq!(replies[0].get(tag::MSG_SEQ_NUM), Some("2"));
+    }
+
+    #[test]
+    fn test_heartbeat_is_a_test_request() {
+        let mut acceptor = logged_in();
+        assert_eq!(acceptor.heartbeat_interval(), Some(Duration::from_secs(30)));
+        let mut buffer = BytesMut::new();
+        let heartbeat = Envelope::unsequenced(ServerMessage::Heartbeat);
+        acceptor.encode(heartbeat, &mut buffer).unwrap();
+        let test_request = decode_all(&mut buffer).remove(0);
+        assert_eq!(test_request.msg_type(), msg_type::TEST_REQUEST);
+        assert!(test_request.get(tag::TEST_REQ_ID).is_some());
+        acceptor.heart_bt_int = "0".to_string();
+        assert_eq!(acceptor.heartbeat_interval(), None);
+    }
+
+    #[test]
+    fn test_inbound_gap_is_resent() {
+        let mut acceptor = logged_in();
+        let (control, replies) = handle(&mut acceptor, &new_order(4, "a3"));
+        assert_eq!(control, Control::Handled);
+        assert_eq!(replies[0].msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(replies[0].get(tag::BEGIN_SEQ_NO), Some("2"));
+        assert_eq!(replies[0].get(tag::END_SEQ_NO), Some("0"));
+        // Only one ResendRequest while the gap is open
+        let (_, replies) = handle(&mut acceptor, &message(msg_type::HEARTBEAT, 5));
+        assert!(replies.is_empty());
+        let gap_fill = message(msg_type::SEQUENCE_RESET, 2)
+            .with(tag::POSS_DUP_FLAG, "Y")
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, 4);
+        assert_eq!(handle(&mut acceptor, &gap_fill).0, Control::Handled);
+        let resent = new_order(4, "a3").with(tag::POSS_DUP_FLAG, "Y");
+        assert_eq!(handle(&mut acceptor, &resent).0, Control::Request);
+        let resent = message(msg_type::HEARTBEAT, 5).with(tag::POSS_DUP_FLAG, "Y");
+        assert_eq!(handle(&mut acceptor, &resent).0, Control::Handled);
+        assert_eq!(acceptor.session.next_inbound, 6);
+        assert_eq!(acceptor.resend_until, None);
+    }
+
+    #[test]
+    fn test_sequence_too_low_logs_out() {
+        let mut acceptor = logged_in();
+        let (control, replies) = handle(&mut acceptor, &message(msg_type::HEARTBEAT, 1));
+        assert_eq!(control, Control::Logout);
+        assert_eq!(replies[0].msg_type(), msg_type::LOGOUT);
+        let duplicate = message(msg_type::HEARTBEAT, 1).with(tag::POSS_DUP_FLAG, "Y");
+        let (control, replies) = self::handle(&mut acceptor, &duplicate);
+        assert_eq!(control, Control::Handled);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn test_resend_request_gap_fills_session_messages() {
+        let mut acceptor = logged_in();
+        acceptor
+            .decode_request("alice", &new_order(2, "a1"))
+            .unwrap();
+        acceptor.placed(7);
+        let mut buffer = BytesMut::new();
+        let ack = ServerMessage::Ack {
+            product: Product::APPLE,
+            order_id: 7,
+        };
+        acceptor
+            .encode(Envelope::unsequenced(ack), &mut buffer)
+            .unwrap();
+        let test_request = message(msg_type::TEST_REQUEST, 3).with(tag::TEST_REQ_ID, "ping");
+        handle(&mut acceptor, &test_request);
+        let resend_request = message(msg_type::RESEND_REQUEST, 4)
+            .with(tag::BEGIN_SEQ_NO, 1)
+            .with(tag::END_SEQ_NO, 0);
+        let (_, replies) = handle(&mut acceptor, &resend_request);
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0].msg_type(), msg_type::SEQUENCE_RESET);
+        assert_eq!(replies[0].get(tag::MSG_SEQ_NUM), Some("1"));
+        assert_eq!(replies[0].get(tag::NEW_SEQ_NO), Some("2"));
+        assert_eq!(replies[1].msg_type(), msg_type::EXECUTION_REPORT);
+        assert_eq!(replies[1].get(tag::MSG_SEQ_NUM), Some("2"));
+        assert_eq!(replies[1].get(tag::POSS_DUP_FLAG), Some("Y"));
+        assert_eq!(replies[1].get(tag::CL_ORD_ID), Some("a1"));
+        assert!(replies[1].get(tag::ORIG_SENDING_TIME).is_some());
+        assert_eq!(replies[2].msg_type(), msg_type::SEQUENCE_RESET);
+        assert_eq!(replies[2].get(tag::MSG_SEQ_NUM), Some("3"));
+        assert_eq!(replies[2].get(tag::NEW_SEQ_NO), Some("4"));
+    }
+
+    /// Minimal FIX initiator, the way an OMS would connect.
+    struct Initiator {
+        frames: Framed<TcpStream, FixCodec>,
+        sequence: Sequence,
+    }
+
+    impl Initiator {
+        async fn logon(address: SocketAddr, name: &str, secret: &str) -> (Self, FixMessage) {
+            let stream = TcpStream::connect(address).await.unwrap();
+            let mut initiator = Initiator {
+                frames: Framed::new(stream, FixCodec),
+                sequence: 0,
+            };
+            let logon = FixMessage::default()
+                .with(tag::ENCRYPT_METHOD, 0)
+                .with(tag::HEART_BT_INT, 30)
+                .with(tag::USERNAME, name)
+                .with(tag::PASSWORD, secret);
+            initiator.send(msg_type::LOGON, logon).await;
+            let reply = initiator.receive().await.unwrap();
+            (initiator, reply)
+        }
+
+        async fn send(&mut self, msg_type: &str, body: FixMessage) {
+            self.sequence += 1;
+            let mut message = FixMessage::new(msg_type)
+                .with(tag::SENDER_COMP_ID, "OMS")
+                .with(tag::TARGET_COMP_ID, "EXCHANGE")
+                .with(tag::MSG_SEQ_NUM, self.sequence)
+                .with(tag::SENDING_TIME, timestamp(SystemTime::now()));
+            for (tag, value) in body.fields() {
+                message.push(*tag, value);
+            }
+            self.frames.send(message).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Option<FixMessage> {
+            tokio::time::timeout(Duration::from_secs(1), self.frames.next())
+                .await
+                .expect("no FIX message within a second")
+                .map(Result::unwrap)
+        }
+
+        async fn new_order(
+            &mut self,
+            cl_ord_id: &str,
+            side: Side,
+            quantity: Quantity,
+            time_in_force: &str,
+        ) {
+            let order = FixMessage::default()
+                .with(tag::CL_ORD_ID, cl_ord_id)
+                .with(tag::SIDE, encode_side(side))
+                .with(tag::SYMBOL, "APPLE")
+                .with(tag::ORD_TYPE, 2)
+                .with(tag::PRICE, "1.25")
+                .with(tag::ORDER_QTY, quantity)
+                .with(tag::TIME_IN_FORCE, time_in_force);
+            self.send(msg_type::NEW_ORDER_SINGLE, order).await;
+        }
+    }
+
+    async fn start_acceptor() -> SocketAddr {
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder").unwrap();
+        let service = Arc::new(TransactionService::new(accounts));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let sessions = FixSessions::default();
+        let instruments = Arc::clone(service.registry());
+        tokio::task::spawn(crate::listen(
+            listener,
+            service,
+            None,
+            crate::framed(move || FixAcceptor::new(sessions.clone(), instruments.clone())),
+        ));
+        address
+    }
+
+    #[tokio::test]
+    async fn test_order_entry_end_to_end() {
+        let address = start_acceptor().await;
+        let (mut alice, reply) = Initiator::logon(address, "alice", "wonderland").await;
+        assert_eq!(reply.msg_type(), msg_type::LOGON);
+        let (mut bob, _) = Initiator::logon(address, "bob", "builder").await;
+
+        alice.new_order("a1", Side::Sell, 10, "1").await;
+        let ack = alice.receive().await.unwrap();
+        assert_eq!(ack.get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(ack.get(tag::CL_ORD_ID), Some("a1"));
+        let order_id = ack.get(tag::ORDER_ID).unwrap().to_string();
+
+        bob.new_order("b1", Side::Buy, 4, "3").await;
+        assert_eq!(bob.receive().await.unwrap().get(tag::EXEC_TYPE), Some("0"));
+        let fill = bob.receive().await.unwrap();
+        assert_eq!(fill.get(tag::EXEC_TYPE), Some("F"));
+        assert_eq!(fill.get(tag::ORD_STATUS), Some("2"));
+        assert_eq!(fill.get(tag::CL_ORD_ID), Some("b1"));
+        assert_eq!(fill.get(tag::LAST_PX), Some("1.25"));
+        let fill = alice.receive().await.unwrap();
+        assert_eq!(fill.get(tag::ORD_STATUS), Some("1"));
+        assert_eq!(fill.get(tag::LEAVES_QTY), Some("6"));
+
+        let replace = FixMessage::default()
+            .with(tag::ORIG_CL_ORD_ID, "a1")
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::PRICE, "1.3")
+            .with(tag::ORDER_QTY, 8);
+        alice
+            .send(msg_type::ORDER_CANCEL_REPLACE_REQUEST, replace)
+            .await;
+        let replaced = alice.receive().await.unwrap();
+        assert_eq!(replaced.get(tag::EXEC_TYPE), Some("5"));
+        assert_eq!(replaced.get(tag::CL_ORD_ID), Some("a2"));
+        assert_eq!(replaced.get(tag::LEAVES_QTY), Some("4"));
+
+        let cancel = FixMessage::default()
+            .with(tag::ORDER_ID, &order_id)
+            .with(tag::CL_ORD_ID, "a3");
+        alice
+            .send(msg_type::ORDER_CANCEL_REQUEST, cancel.clone())
+            .await;
+        let canceled = alice.receive().await.unwrap();
+        assert_eq!(canceled.get(tag::EXEC_TYPE), Some("4"));
+        assert_eq!(canceled.get(tag::ORIG_CL_ORD_ID), Some("a2"));
+        alice.send(msg_type::ORDER_CANCEL_REQUEST, cancel).await;
+        let reject = alice.receive().await.unwrap();
+        assert_eq!(reject.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(reject.get(tag::CXL_REJ_REASON), Some(UNKNOWN_ORDER));
+
+        alice.send(msg_type::LOGOUT, FixMessage::default()).await;
+        assert_eq!(alice.receive().await.unwrap().msg_type(), msg_type::LOGOUT);
+        assert_eq!(alice.receive().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_resend_request_end_to_end() {
+        let address = start_acceptor().await;
+        let (mut alice, _) = Initiator::logon(address, "alice", "wonderland").await;
+        alice.new_order("a1", Side::Sell, 10, "1").await;
+        alice.receive().await.unwrap();
+        let resend_request = FixMessage::default()
+            .with(tag::BEGIN_SEQ_NO, 2)
+            .with(tag::END_SEQ_NO, 2);
+        alice.send(msg_type::RESEND_REQUEST, resend_request).await;
+        let resent = alice.receive().await.unwrap();
+        assert_eq!(resent.get(tag::MSG_SEQ_NUM), Some("2"));
+        assert_eq!(resent.get(tag::POSS_DUP_FLAG), Some("Y"));
+        assert_eq!(resent.get(tag::CL_ORD_ID), Some("a1"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_logon_end_to_end() {
+        let address = start_acceptor().await;
+        let (_, reply) = Initiator::logon(address, "alice", "looking-glass").await;
+        assert_eq!(reply.msg_type(), msg_type::LOGOUT);
+        assert!(reply.get(tag::TEXT).is_some());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::consts::HEARTBEAT_TIMEOUT_SECS;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What a quiet session needs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Idle {
+    /// The trader has been quiet for the interval.
+    SendHeartbeat,
+    /// The trader did not answer the heartbeat in time.
+    TimedOut,
+}
+
+/// Watches a session for traders that went quiet, e.g. behind a half-open
+/// connection. Any traffic from the trader counts as an answer.
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Option<Duration>,
+    last_heard: Instant,
+    sent: bool,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            last_heard: Instant::now(),
+            sent: false,
+        }
+    }
+
+    pub fn timeout() -> Duration {
+        Duration::from_secs(HEARTBEAT_TIMEOUT_SECS)
+    }
+
+    pub fn heard(&mut self) {
+        self.last_heard = Instant::now();
+        self.sent = false;
+    }
+
+    /// Waits until the trader has been quiet for too long; forever without an
+    /// interval. Cancel safe.
+    pub async fn idle(&mut self) -> Idle {
+        let Some(interval) = self.interval else {
+            return std::future::pending().await;
+        };
+        if self.sent {
+            tokio::time::sleep_until(self.last_heard + interval + Self::timeout()).await;
+            return Idle::TimedOut;
+        }
+        tokio::time::sleep_until(self.last_heard + interval).await;
+        self.sent = true;
+        Idle::SendHeartbeat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::consts::HEARTBEAT_INTERVAL_SECS;
+    use crate::protocol::TextCodec;
+    use crate::transaction_service::TransactionService;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    async fn login(address: std::net::SocketAddr) -> (BufReader<TcpStream>, String) {
+        let mut alice = BufReader::new(TcpStream::connect(address).await.unwrap());
+        alice.write_all(b"LOGIN:alice:wonderland\n").await.unwrap();
+        let answer = read_line(&mut alice).await;
+        (alice, answer)
+    }
+
+    async fn read_line(trader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        trader.read_line(&mut line).await.unwrap();
+        line
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quiet_trader_is_disconnected() {
+        let accounts = Accounts::from_str("alice:wonderland").unwrap();
+        let service = Arc::new(TransactionService::new(accounts));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(crate::listen(
+            listener,
+            service,
+            None,
+            crate::framed(TextCodec::default),
+        ));
+        let (mut alice, answer) = login(address).await;
+        assert_eq!(answer, "LOGGED_IN:alice:0\n");
+
+        let start = Instant::now();
+        assert_eq!(read_line(&mut alice).await, "HEARTBEAT\n");
+        assert_eq!(start.elapsed().as_secs(), HEARTBEAT_INTERVAL_SECS);
+        alice.write_all(b"heartbeat\n").await.unwrap();
+        assert_eq!(read_line(&mut alice).await, "HEARTBEAT\n");
+        // No answer this time, the session ends and alice can log in again
+        assert_eq!(read_line(&mut alice).await, "");
+        let (_, answer) = login(address).await;
+        assert_eq!(answer, "LOGGED_IN:alice:0\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_then_timeout() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(Some(INTERVAL));
+        assert_eq!(heartbeat.idle().await, Idle::SendHeartbeat);
+        assert_eq!(start.elapsed(), INTERVAL);
+        assert_eq!(heartbeat.idle().await, Idle::TimedOut);
+        assert_eq!(start.elapsed(), INTERVAL + Heartbeat::timeout());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_traffic_answers_heartbeat() {
+        let mut heartbeat = Heartbeat::new(Some(INTERVAL));
+        assert_eq!(heartbeat.idle().await, Idle::SendHeartbeat);
+        tokio::time::advance(Heartbeat::timeout() / 2).await;
+        heartbeat.heard();
+        let answered = Instant::now();
+        assert_eq!(heartbeat.idle().await, Idle::SendHeartbeat);
+        assert_eq!(answered.elapsed(), INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_interval() {
+        let mut heartbeat = Heartbeat::new(None);
+        let idle = tokio::time::timeout(INTERVAL * 10, heartbeat.idle()).await;
+        assert!(idle.is_err());
+    }
+}
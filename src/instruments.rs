@@ -0,0 +1,328 @@
+use crate::consts::DEFAULT_PRODUCTS;
+use crate::errors::ClientError;
+use crate::price::Price;
+use crate::products::Product;
+use crate::trader::Quantity;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Trading rules of a listed product.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub product: Product,
+    /// Prices are multiples of the tick size.
+    pub tick_size: Price,
+    /// Quantities are multiples of the lot size.
+    pub lot_size: Quantity,
+    pub min_quantity: Quantity,
+    pub max_quantity: Option<Quantity>,
+    /// Price band for limit orders; market orders have no price to check.
+    pub min_price: Option<Price>,
+    pub max_price: Option<Price>,
+}
+
+/// An instrument as written in the config file or posted over HTTP; anything left
+/// out takes its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstrumentSpec {
+    tick_size: Option<String>,
+    lot_size: Option<Quantity>,
+    min_quantity: Option<Quantity>,
+    max_quantity: Option<Quantity>,
+    min_price: Option<String>,
+    max_price: Option<String>,
+}
+
+/// The instruments listed for trading; they can be listed and delisted at runtime.
+#[derive(Debug)]
+pub struct Instruments(RwLock<BTreeMap<Product, Instrument>>);
+
+impl Instrument {
+    /// Any positive price and quantity.
+    pub fn new(product: Product) -> Self {
+        Self {
+            product,
+            tick_size: Price::from_units(1).expect("one unit is a valid price"),
+            lot_size: 1,
+            min_quantity: 1,
+            max_quantity: None,
+            min_price: None,
+            max_price: None,
+        }
+    }
+
+    pub fn check(&self, price: Option<Price>, quantity: Quantity) -> Result<(), ClientError> {
+        if let Some(price) = price {
+            if !price.units().is_multiple_of(self.tick_size.units()) {
+                return Err(ClientError::InvalidTick(self.tick_size));
+            }
+            let below = self.min_price.is_some_and(|min_price| price < min_price);
+            let above = self.max_price.is_some_and(|max_price| price > max_price);
+            if below || above {
+                return Err(ClientError::PriceOutsideBand);
+            }
+        }
+        if !quantity.is_multiple_of(self.lot_size) {
+            return Err(ClientError::InvalidLot(self.lot_size));
+        }
+        if quantity < self.min_quantity {
+            return Err(ClientError::QuantityTooSmall(self.min_quantity));
+        }
+        match self.max_quantity {
+            Some(max_quantity) if quantity > max_quantity => {
+                Err(ClientError::QuantityTooLarge(max_quantity))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl InstrumentSpec {
+    /// Explains what is wrong with the rules, if anything.
+    pub fn into_instrument(self, product: Product) -> Result<Instrument, String> {
+        let price = |name: &str, value: Option<String>| {
+            value
+                .map(|value| {
+                    Price::from_str(&value).map_err(|_| {
+                        format!(
+                            "{} '{}' of {} should be a positive decimal number",
+                            name, value, product
+                        )
+                    })
+                })
+                .transpose()
+        };
+        let defaults = Instrument::new(product);
+        let instrument = Instrument {
+            product,
+            tick_size: price("tick_size", self.tick_size)?.unwrap_or(defaults.tick_size),
+            lot_size: self.lot_size.unwrap_or(defaults.lot_size),
+            min_quantity: self
+                .min_quantity
+                .or(self.lot_size)
+                .unwrap_or(defaults.min_quantity),
+            max_quantity: self.max_quantity,
+            min_price: price("min_price", self.min_price)?,
+            max_price: price("max_price", self.max_price)?,
+        };
+        if instrument.lot_size == 0 {
+            return Err(format!("lot_size of {} should be at least 1", product));
+        }
+        if instrument.min_quantity == 0
+            || !instrument.min_quantity.is_multiple_of(instrument.lot_size)
+            || instrument
+                .max_quantity
+                .is_some_and(|max_quantity| max_quantity < instrument.min_quantity)
+        {
+            return Err(format!(
+                "min_quantity and max_quantity of {} should be lots, smallest first",
+                product
+            ));
+        }
+        if let (Some(min_price), Some(max_price)) = (instrument.min_price, instrument.max_price) {
+            if max_price < min_price {
+                return Err(format!(
+                    "min_price of {} should not be above max_price",
+                    product
+                ));
+            }
+        }
+        Ok(instrument)
+    }
+}
+
+impl Instruments {
+    pub fn new(instruments: Vec<Instrument>) -> Self {
+        let instruments = instruments
+            .into_iter()
+            .map(|instrument| (instrument.product, instrument))
+            .collect();
+        Self(RwLock::new(instruments))
+    }
+
+    pub fn get(&self, product: Product) -> Result<Instrument, ClientError> {
+        self.0
+            .read()
+            .unwrap()
+            .get(&product)
+            .copied()
+            .ok_or(ClientError::UnknownProduct)
+    }
+
+    /// All listed instruments, by symbol.
+    pub fn all(&self) -> Vec<Instrument> {
+        self.0.read().unwrap().values().copied().collect()
+    }
+
+    /// Lists a new instrument or changes the rules of a listed one, returning
+    /// the rules it replaced.
+    pub fn list(&self, instrument: Instrument) -> Option<Instrument> {
+        self.0
+            .write()
+            .unwrap()
+            .insert(instrument.product, instrument)
+    }
+
+    pub fn delist(&self, product: Product) -> Result<Instrument, ClientError> {
+        self.0
+            .write()
+            .unwrap()
+            .remove(&product)
+            .ok_or(ClientError::UnknownProduct)
+    }
+
+    /// The product a client named, in any case, if it is listed.
+    pub fn listed(&self, symbol: &str) -> Result<Product, ClientError> {
+        let product = Product::from_str(&symbol.to_uppercase())?;
+        self.get(product).map(|instrument| instrument.product)
+    }
+}
+
+/// The products we have always traded, without restrictions.
+pub fn default_instruments() -> Vec<Instrument> {
+    DEFAULT_PRODUCTS
+        .iter()
+        .map(|symbol| Instrument::new(Product::from_str(symbol).expect("valid symbol")))
+        .collect()
+}
+
+impl Default for Instruments {
+    fn default() -> Self {
+        Self::new(default_instruments())
+    }
+}
+
+/// The rules on one line, e.g. for the startup logs.
+impl Display for Instrument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let or_none = |value: Option<String>| value.unwrap_or("none".to_string());
+        write!(
+            f,
+            "tick {}, lot {}, quantity {} to {}, price {} to {}",
+            self.tick_size,
+            self.lot_size,
+            self.min_quantity,
+            or_none(self.max_quantity.map(|quantity| quantity.to_string())),
+            or_none(self.min_price.map(|price| price.to_string())),
+            or_none(self.max_price.map(|price| price.to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(price: &str) -> Option<Price> {
+        Some(Price::from_str(price).unwrap())
+    }
+
+    fn spec(toml: &str) -> InstrumentSpec {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let instrument = spec(
+            r#"
+                tick_size = "0.05"
+                lot_size = 10
+                max_quantity = 1000
+                min_price = "1"
+                max_price = "2"
+            "#,
+        )
+        .into_instrument(Product::APPLE)
+        .unwrap();
+        assert_eq!(instrument.min_quantity, 10);
+        assert_eq!(instrument.check(price("1.05"), 20), Ok(()));
+        assert_eq!(instrument.check(None, 1000), Ok(()));
+        assert_eq!(
+            instrument.check(price("1.01"), 20),
+            Err(ClientError::InvalidTick(price("0.05").unwrap()))
+        );
+        assert_eq!(
+            instrument.check(price("2.05"), 20),
+            Err(ClientError::PriceOutsideBand)
+        );
+        assert_eq!(
+            instrument.check(price("0.95"), 20),
+            Err(ClientError::PriceOutsideBand)
+        );
+        assert_eq!(
+            instrument.check(price("1"), 25),
+            Err(ClientError::InvalidLot(10))
+        );
+        assert_eq!(
+            instrument.check(None, 0),
+            Err(ClientError::QuantityTooSmall(10))
+        );
+        assert_eq!(
+            instrument.check(None, 1010),
+            Err(ClientError::QuantityTooLarge(1000))
+        );
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        for (toml, reason) in [
+            (
+                "tick_size = \"0\"",
+                "tick_size '0' of APPLE should be a positive decimal number",
+            ),
+            ("lot_size = 0", "lot_size of APPLE should be at least 1"),
+            (
+                "lot_size = 10\nmin_quantity = 5",
+                "min_quantity and max_quantity of APPLE should be lots, smallest first",
+            ),
+            (
+                "min_quantity = 5\nmax_quantity = 4",
+                "min_quantity and max_quantity of APPLE should be lots, smallest first",
+            ),
+            (
+                "min_price = \"2\"\nmax_price = \"1\"",
+                "min_price of APPLE should not be above max_price",
+            ),
+        ] {
+            assert_eq!(
+                spec(toml).into_instrument(Product::APPLE),
+                Err(reason.to_string())
+            );
+        }
+        assert!(toml::from_str::<InstrumentSpec>("tick = \"1\"").is_err());
+    }
+
+    #[test]
+    fn test_list_and_delist() {
+        let instruments = Instruments::default();
+        assert_eq!(instruments.all().len(), DEFAULT_PRODUCTS.len());
+        let kiwi = Product::from_str("KIWI").unwrap();
+        assert_eq!(instruments.get(kiwi), Err(ClientError::UnknownProduct));
+        assert_eq!(instruments.list(Instrument::new(kiwi)), None);
+        assert_eq!(instruments.get(kiwi), Ok(Instrument::new(kiwi)));
+        let lots = Instrument {
+            lot_size: 5,
+            ..Instrument::new(kiwi)
+        };
+        assert_eq!(instruments.list(lots), Some(Instrument::new(kiwi)));
+        assert_eq!(instruments.delist(kiwi), Ok(lots));
+        assert_eq!(instruments.delist(kiwi), Err(ClientError::UnknownProduct));
+        assert_eq!(instruments.get(kiwi), Err(ClientError::UnknownProduct));
+    }
+
+    #[test]
+    fn test_listed() {
+        let instruments = Instruments::default();
+        assert_eq!(instruments.listed("apple"), Ok(Product::APPLE));
+        assert_eq!(instruments.listed("GME"), Err(ClientError::UnknownProduct));
+        assert_eq!(instruments.listed("GME!"), Err(ClientError::UnknownProduct));
+        assert_eq!(
+            Instruments::default().listed("GME"),
+            Err(ClientError::UnknownProduct)
+        );
+    }
+}
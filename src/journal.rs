@@ -0,0 +1,497 @@
+use crate::accounts::AccountId;
+use crate::errors::Error;
+use crate::instruments::Instrument;
+use crate::order_book::{OrderId, RestingOrder, Trade, TradeId};
+use crate::price::Price;
+use crate::products::Product;
+use crate::session::Sequence;
+use crate::trader::{Quantity, Transaction};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A change to the books, one JSON object per line of the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    /// An accepted order with the trades it made; what is left of it rests or
+    /// expires according to its time in force.
+    Order {
+        order_id: OrderId,
+        transaction: Transaction,
+        trades: Vec<Trade>,
+        /// Sequence numbers reserved for the accounts told about it.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        sequences: BTreeMap<AccountId, Sequence>,
+    },
+    /// A resting order left the book without trading: cancelled, expired or delisted.
+    Cancel { order_id: OrderId },
+    Amend {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+        trades: Vec<Trade>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        sequences: BTreeMap<AccountId, Sequence>,
+    },
+    /// A product listed, or its rules changed, at runtime.
+    List { instrument: Instrument },
+    /// A product delisted at runtime, after its resting orders were cancelled.
+    Delist { product: Product },
+    /// Sequence numbers reserved for accounts between the other records.
+    Sequences {
+        sequences: BTreeMap<AccountId, Sequence>,
+    },
+}
+
+/// The state the journal rebuilds, as of its first `offset` records; recovery
+/// starts from the latest snapshot and replays only the records after it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub offset: u64,
+    pub last_order_id: OrderId,
+    pub last_trade_id: TradeId,
+    /// Resting orders of every book, each side in the order they would be executed.
+    pub orders: Vec<RestingOrder>,
+    /// Newest first.
+    pub recent_trades: Vec<Trade>,
+    /// Sequence numbers reserved for each account; numbering carries on after them.
+    pub sequences: BTreeMap<AccountId, Sequence>,
+    /// Products listed, or delisted when `None`, at runtime; they override the
+    /// configured instruments.
+    #[serde(default)]
+    pub listings: BTreeMap<Product, Option<Instrument>>,
+}
+
+/// Write-ahead journal of the books: changes are appended, and synced to disk,
+/// before they are acknowledged. Without a directory nothing is kept.
+///
+/// The directory holds segments of records, `journal-<offset>.jsonl`, named after
+/// the offset of their first record, and snapshots, `snapshot-<offset>.json`.
+/// Every snapshot starts a new segment, and makes the older ones redundant.
+#[derive(Debug, Default)]
+pub struct Journal {
+    dir: Option<PathBuf>,
+    file: Option<File>,
+    /// Records written since the very first one, across segments.
+    offset: u64,
+    /// Offset of the latest snapshot.
+    snapshot_offset: u64,
+    sync: bool,
+}
+
+/// A file of the journal directory, by its offset.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Entry {
+    Segment(u64),
+    Snapshot(u64),
+}
+
+impl Entry {
+    fn parse(name: &str) -> Option<Self> {
+        let offset = |prefix: &str, suffix: &str| {
+            name.strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .parse::<u64>()
+                .ok()
+        };
+        offset("journal-", ".jsonl")
+            .map(Entry::Segment)
+            .or_else(|| offset("snapshot-", ".json").map(Entry::Snapshot))
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Entry::Segment(offset) => format!("journal-{:020}.jsonl", offset),
+            Entry::Snapshot(offset) => format!("snapshot-{:020}.json", offset),
+        }
+    }
+
+    /// Segments and snapshots in `dir`, each sorted by offset.
+    fn list(dir: &Path) -> io::Result<(Vec<u64>, Vec<u64>)> {
+        let mut segments = Vec::new();
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            match entry?.file_name().to_str().and_then(Entry::parse) {
+                Some(Entry::Segment(offset)) => segments.push(offset),
+                Some(Entry::Snapshot(offset)) => snapshots.push(offset),
+                None => {}
+            }
+        }
+        segments.sort_unstable();
+        snapshots.sort_unstable();
+        Ok((segments, snapshots))
+    }
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, creating it when missing, and reads the latest
+    /// snapshot and the records after it. A torn last record, from a crash
+    /// halfway through writing it, is cut off.
+    pub fn open(dir: &Path, sync: bool) -> Result<(Self, Option<Snapshot>, Vec<Record>), Error> {
+        fs::create_dir_all(dir)?;
+        let (segments, snapshots) = Entry::list(dir)?;
+        let snapshot = match snapshots.last() {
+            Some(&offset) => {
+                let name = Entry::Snapshot(offset).name();
+                let snapshot: Snapshot = serde_json::from_slice(&fs::read(dir.join(&name))?)
+                    .map_err(|e| Error::InvalidJournal(format!("{}: {}", name, e)))?;
+                Some(snapshot)
+            }
+            None => None,
+        };
+        let snapshot_offset = snapshot.as_ref().map_or(0, |snapshot| snapshot.offset);
+        let mut records = Vec::new();
+        let mut offset = segments.first().copied().unwrap_or(snapshot_offset);
+        if offset > snapshot_offset {
+            return Err(Error::InvalidJournal(format!(
+                "records {} to {} are missing",
+                snapshot_offset + 1,
+                offset
+            )));
+        }
+        let mut file = None;
+        for (index, &start) in segments.iter().enumerate() {
+            let name = Entry::Segment(start).name();
+            if start != offset {
+                return Err(Error::InvalidJournal(format!(
+                    "{} should start at record {}",
+                    name,
+                    offset + 1
+                )));
+            }
+            let last = index == segments.len() - 1;
+            let mut segment = OpenOptions::new()
+                .read(true)
+                .append(last)
+                .open(dir.join(&name))?;
+            let mut content = Vec::new();
+            segment.read_to_end(&mut content)?;
+            let (segment_records, length) = Self::parse(&content)
+                .map_err(|reason| Error::InvalidJournal(format!("{}: {}", name, reason)))?;
+            if length < content.len() {
+                if !last {
+                    return Err(Error::InvalidJournal(format!(
+                        "{} ends with a torn record",
+                        name
+                    )));
+                }
+                warn!(
+                    "journal ends with a torn record, cutting off its {} bytes",
+                    content.len() - length
+                );
+                segment.set_len(length as u64)?;
+                segment.sync_all()?;
+            }
+            for record in segment_records {
+                if offset >= snapshot_offset {
+                    records.push(record);
+                }
+                offset += 1;
+            }
+            if last {
+                file = Some(segment);
+            }
+        }
+        if offset < snapshot_offset {
+            return Err(Error::InvalidJournal(format!(
+                "records {} to {} are missing",
+                offset + 1,
+                snapshot_offset
+            )));
+        }
+        let mut journal = Self {
+            dir: Some(dir.to_path_buf()),
+            file,
+            offset,
+            snapshot_offset,
+            sync,
+        };
+        if journal.file.is_none() {
+            journal.roll()?;
+        }
+        Ok((journal, snapshot, records))
+    }
+
+    /// Records of complete lines, and the length of those lines. Only the last
+    /// line can be incomplete; anything else unreadable is corruption.
+    fn parse(content: &[u8]) -> Result<(Vec<Record>, usize), String> {
+        let mut records = Vec::new();
+        let mut length = 0;
+        for (index, line) in content.split_inclusive(|&byte| byte == b'\n').enumerate() {
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            let record =
+                serde_json::from_slice(line).map_err(|e| format!("record {}: {}", index + 1, e))?;
+            records.push(record);
+            length += line.len();
+        }
+        Ok((records, length))
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        if self.sync {
+            file.sync_data()?;
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    /// Number of records written so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether there are records the latest snapshot does not cover.
+    pub fn has_changes(&self) -> bool {
+        self.offset > self.snapshot_offset
+    }
+
+    /// Writes `snapshot`, which has to cover all records so far, then removes
+    /// the older snapshots and the segments it covers.
+    pub fn snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.roll()?;
+        if let Some(dir) = &self.dir {
+            compact(dir, snapshot)?;
+        }
+        self.snapshot_offset = snapshot.offset;
+        Ok(())
+    }
+
+    /// Starts a new segment at the current offset.
+    fn roll(&mut self) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dir.join(Entry::Segment(self.offset).name()))?;
+        sync_dir(dir)?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+/// Writes `snapshot` to `dir`, then removes the older snapshots and the
+/// segments it covers. The journal has to be rolled at the snapshot offset first.
+fn compact(dir: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let name = Entry::Snapshot(snapshot.offset).name();
+    let temp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&temp)?;
+    file.write_all(&serde_json::to_vec(snapshot)?)?;
+    file.sync_all()?;
+    fs::rename(&temp, dir.join(&name))?;
+    sync_dir(dir)?;
+
+    let (segments, snapshots) = Entry::list(dir)?;
+    // A segment is covered when the next one starts within the snapshot
+    for pair in segments.windows(2) {
+        if pair[1] <= snapshot.offset {
+            fs::remove_file(dir.join(Entry::Segment(pair[0]).name()))?;
+        }
+    }
+    for offset in snapshots {
+        if offset < snapshot.offset {
+            fs::remove_file(dir.join(Entry::Snapshot(offset).name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes new, renamed and removed files of `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::actions::Side;
+    use crate::time_in_force::TimeInForce;
+    use std::str::FromStr;
+
+    /// A journal directory of its own, removed when the test is done.
+    pub struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("trading_app_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+
+        /// Names of the files in the directory, sorted.
+        pub fn files(&self) -> Vec<String> {
+            let mut files: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::Order {
+                order_id: 1,
+                transaction: Transaction {
+                    trader_id: "alice".to_string(),
+                    side: Side::Sell,
+                    product: Product::APPLE,
+                    price: Some(Price::from_str("1.25").unwrap()),
+                    quantity: 10,
+                    time_in_force: TimeInForce::Gtc,
+                },
+                trades: Vec::new(),
+                sequences: BTreeMap::from([("alice".to_string(), 1000)]),
+            },
+            Record::Amend {
+                order_id: 1,
+                price: Price::from_str("1.5").unwrap(),
+                quantity: 5,
+                trades: Vec::new(),
+                sequences: BTreeMap::new(),
+            },
+            Record::Cancel { order_id: 1 },
+        ]
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let dir = TempDir::new("append");
+        let (mut journal, snapshot, recovered) = Journal::open(&dir.0, true).unwrap();
+        assert_eq!(snapshot, None);
+        assert!(recovered.is_empty());
+        for record in records() {
+            journal.append(&record).unwrap();
+        }
+        drop(journal);
+        let (journal, _, recovered) = Journal::open(&dir.0, true).unwrap();
+        assert_eq!(recovered, records());
+        assert_eq!(journal.offset(), 3);
+        assert_eq!(dir.files(), vec![Entry::Segment(0).name()]);
+    }
+
+    #[test]
+    fn test_record_format() {
+        let line = serde_json::to_string(&records()[1]).unwrap();
+        assert_eq!(
+            line,
+            r#"{"type":"amend","order_id":1,"price":"1.5","quantity":5,"trades":[]}"#
+        );
+        assert_eq!(
+            Entry::Segment(42).name(),
+            "journal-00000000000000000042.jsonl"
+        );
+        assert_eq!(
+            Entry::parse("snapshot-00000000000000000042.json"),
+            Some(Entry::Snapshot(42))
+        );
+        assert_eq!(Entry::parse("snapshot-00000000000000000042.json.tmp"), None);
+    }
+
+    #[test]
+    fn test_torn_last_record_is_cut_off() {
+        let dir = TempDir::new("torn");
+        let (mut journal, _, _) = Journal::open(&dir.0, false).unwrap();
+        for record in &records()[..2] {
+            journal.append(record).unwrap();
+        }
+        drop(journal);
+        let path = dir.0.join(Entry::Segment(0).name());
+        let complete = fs::read(&path).unwrap();
+        let torn = br#"{"type":"cancel","ord"#;
+        fs::write(&path, [complete.as_slice(), torn].concat()).unwrap();
+
+        let (mut journal, _, recovered) = Journal::open(&dir.0, false).unwrap();
+        assert_eq!(recovered, records()[..2]);
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        // Appending carries on where the complete records end
+        journal.append(&records()[2]).unwrap();
+        drop(journal);
+        let (_, _, recovered) = Journal::open(&dir.0, false).unwrap();
+        assert_eq!(recovered, records());
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        let dir = TempDir::new("corrupt");
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(
+            dir.0.join(Entry::Segment(0).name()),
+            "{\"type\":\"cancel\",\"order_id\":1}\nnot json\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Journal::open(&dir.0, false),
+            Err(Error::InvalidJournal(reason)) if reason.contains(": record 2:")
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_and_compaction() {
+        let dir = TempDir::new("compact");
+        let (mut journal, _, _) = Journal::open(&dir.0, false).unwrap();
+        let [order, amend, cancel] = records().try_into().unwrap();
+        journal.append(&order).unwrap();
+        assert!(journal.has_changes());
+        let first = Snapshot {
+            offset: 1,
+            last_order_id: 1,
+            ..Default::default()
+        };
+        journal.snapshot(&first).unwrap();
+        assert!(!journal.has_changes());
+        journal.append(&amend).unwrap();
+        let second = Snapshot {
+            offset: 2,
+            ..first.clone()
+        };
+        journal.snapshot(&second).unwrap();
+        journal.append(&cancel).unwrap();
+        drop(journal);
+        assert_eq!(
+            dir.files(),
+            vec![Entry::Segment(2).name(), Entry::Snapshot(2).name()]
+        );
+
+        let (journal, snapshot, recovered) = Journal::open(&dir.0, false).unwrap();
+        assert_eq!(snapshot, Some(second));
+        assert_eq!(recovered, vec![cancel]);
+        assert_eq!(journal.offset(), 3);
+    }
+
+    #[test]
+    fn test_missing_records() {
+        let dir = TempDir::new("missing");
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(
+            dir.0.join(Entry::Segment(3).name()),
+            "{\"type\":\"cancel\",\"order_id\":1}\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Journal::open(&dir.0, false),
+            Err(Error::InvalidJournal(reason)) if reason == "records 1 to 3 are missing"
+        ));
+    }
+}
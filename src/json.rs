@@ -0,0 +1,493 @@
+use crate::accounts::Credentials;
+use crate::actions::Side;
+use crate::errors::ClientError;
+use crate::instruments::Instruments;
+use crate::market_data::LevelChange;
+use crate::messages::{Envelope, ServerMessage};
+use crate::order_book::{Level, OrderId};
+use crate::price::Price;
+use crate::products::Product;
+use crate::session::Sequence;
+use crate::time_in_force::TimeInForce;
+use crate::trader::{check_quantity, parse_limit, ClientRequest, Quantity, Transaction};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+/// Requests of the JSON lines protocol, one object per line, told apart by `type`.
+/// Prices are decimal strings, so they keep their exact value.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum JsonRequest {
+    Login {
+        name: String,
+        secret: String,
+        last_seen: Option<Sequence>,
+    },
+    Order(JsonOrder),
+    Cancel {
+        order_id: OrderId,
+    },
+    Amend {
+        order_id: OrderId,
+        price: String,
+        quantity: Quantity,
+    },
+    Heartbeat,
+    Subscribe {
+        product: String,
+    },
+    Unsubscribe {
+        product: String,
+    },
+}
+
+/// A new order, also the body of an order posted over HTTP.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonOrder {
+    side: String,
+    product: String,
+    /// Missing or `"MKT"` for market orders.
+    price: Option<String>,
+    quantity: Option<Quantity>,
+    time_in_force: Option<String>,
+}
+
+impl JsonOrder {
+    fn into_transaction(
+        self,
+        trader_id: &str,
+        instruments: &Instruments,
+    ) -> Result<Transaction, ClientError> {
+        let side = Side::from_str(&self.side.to_uppercase())?;
+        let product = instruments.listed(&self.product)?;
+        let price = match self.price {
+            Some(price) => parse_limit(&price)?,
+            None => None,
+        };
+        let time_in_force = self
+            .time_in_force
+            .map(|time_in_force| TimeInForce::from_str(&time_in_force.to_uppercase()))
+            .transpose()?;
+        Transaction::new(
+            trader_id,
+            side,
+            product,
+            price,
+            self.quantity,
+            time_in_force,
+        )
+    }
+}
+
+fn parse(line: &str) -> Result<JsonRequest, ClientError> {
+    serde_json::from_str(line).map_err(|e| ClientError::InvalidJsonMessage(e.to_string()))
+}
+
+pub fn decode_login(line: &str) -> Result<Credentials, ClientError> {
+    match parse(line) {
+        Ok(JsonRequest::Login {
+            name,
+            secret,
+            last_seen,
+        }) => Ok(Credentials {
+            name,
+            secret,
+            last_seen,
+        }),
+        Ok(_) => Err(ClientError::NotLoggedIn),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn decode_request(
+    trader_id: &str,
+    line: &str,
+    instruments: &Instruments,
+) -> Result<ClientRequest, ClientError> {
+    match parse(line)? {
+        JsonRequest::Login { .. } => Err(ClientError::AlreadyLoggedIn),
+        JsonRequest::Order(order) => order
+            .into_transaction(trader_id, instruments)
+            .map(ClientRequest::Order),
+        JsonRequest::Cancel { order_id } => Ok(ClientRequest::Cancel(order_id)),
+        JsonRequest::Amend {
+            order_id,
+            price,
+            quantity,
+        } => Ok(ClientRequest::Amend {
+            order_id,
+            price: Price::from_str(&price)?,
+            quantity: check_quantity(quantity)?,
+        }),
+        JsonRequest::Heartbeat => Ok(ClientRequest::Heartbeat),
+        JsonRequest::Subscribe { product } => {
+            Product::from_str(&product.to_uppercase()).map(ClientRequest::Subscribe)
+        }
+        JsonRequest::Unsubscribe { product } => {
+            Product::from_str(&product.to_uppercase()).map(ClientRequest::Unsubscribe)
+        }
+    }
+}
+
+/// Decodes the body of an order posted over HTTP, which has no `type`, for a
+/// product listed in `instruments`.
+pub fn decode_order(
+    trader_id: &str,
+    body: &str,
+    instruments: &Instruments,
+) -> Result<Transaction, ClientError> {
+    serde_json::from_str::<JsonOrder>(body)
+        .map_err(|e| ClientError::InvalidJsonMessage(e.to_string()))?
+        .into_transaction(trader_id, instruments)
+}
+
+/// One JSON object per message; sequenced messages start with their `seq`.
+pub fn encode(envelope: &Envelope) -> String {
+    let mut object = Map::new();
+    if let Some(sequence) = envelope.sequence {
+        object.insert("seq".to_string(), json!(sequence));
+    }
+    if let Value::Object(message) = to_value(&envelope.message) {
+        object.extend(message);
+    }
+    Value::Object(object).to_string()
+}
+
+fn to_value(message: &ServerMessage) -> Value {
+    match message {
+        ServerMessage::LoggedIn {
+            account_id,
+            last_sequence,
+        } => json!({
+            "type": "logged_in",
+            "account": account_id,
+            "last_sequence": last_sequence,
+        }),
+        ServerMessage::Ack { product, order_id } => json!({
+            "type": "ack",
+            "product": product.to_string(),
+            "order_id": order_id,
+        }),
+        ServerMessage::Canceled { order_id } => json!({
+            "type": "canceled",
+            "order_id": order_id,
+        }),
+        ServerMessage::Expired {
+            order_id,
+            quantity,
+            time_in_force,
+        } => json!({
+            "type": "canceled",
+            "order_id": order_id,
+            "quantity": quantity,
+            "time_in_force": time_in_force.to_string(),
+        }),
+        ServerMessage::Replaced(order) => json!({
+            "type": "replaced",
+            "order_id": order.order_id,
+            "product": order.product.to_string(),
+            "side": order.side.to_string(),
+            "price": order.price.to_string(),
+            "quantity": order.quantity,
+        }),
+        ServerMessage::Execution {
+            trade_id,
+            order_id,
+            side,
+            product,
+            price,
+            quantity,
+            leaves,
+        } => json!({
+            "type": "fill",
+            "trade_id": trade_id,
+            "order_id": order_id,
+            "side": side.to_string(),
+            "product": product.to_string(),
+            "price": price.to_string(),
+            "quantity": quantity,
+            "leaves": leaves,
+        }),
+        ServerMessage::Trade {
+            product,
+            price,
+            quantity,
+        } => json!({
+            "type": "trade",
+            "product": product.to_string(),
+            "price": price.to_string(),
+            "quantity": quantity,
+        }),
+        ServerMessage::Reject { error, input } => json!({
+            "type": "reject",
+            "code": error.code(),
+            "reason": error.to_string(),
+            "input": input,
+        }),
+        ServerMessage::Heartbeat => json!({ "type": "heartbeat" }),
+        ServerMessage::BookSnapshot {
+            product,
+            sequence,
+            bids,
+            asks,
+        } => json!({
+            "type": "book",
+            "product": product.to_string(),
+            "sequence": sequence,
+            "bids": levels(bids),
+            "asks": levels(asks),
+        }),
+        ServerMessage::BookUpdate {
+            product,
+            sequence,
+            update,
+        } => json!({
+            "type": "book_update",
+            "product": product.to_string(),
+            "sequence": sequence,
+            "action": match update.change {
+                LevelChange::Add => "add",
+                LevelChange::Modify => "modify",
+                LevelChange::Delete => "delete",
+            },
+            "side": update.side.to_string(),
+            "price": update.price.to_string(),
+            "quantity": update.quantity,
+        }),
+        ServerMessage::Unsubscribed { product } => json!({
+            "type": "unsubscribed",
+            "product": product.to_string(),
+        }),
+    }
+}
+
+fn levels(levels: &[Level]) -> Value {
+    levels
+        .iter()
+        .map(|level| json!({ "price": level.price.to_string(), "quantity": level.quantity }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::Instrument;
+
+    fn price(price: &str) -> Price {
+        Price::from_str(price).unwrap()
+    }
+
+    #[test]
+    fn test_decode_login() {
+        let result = decode_login(r#"{"type":"login","name":"alice","secret":"wonderland"}"#);
+        assert_eq!(
+            result.ok(),
+            Some(Credentials {
+                name: "alice".to_string(),
+                secret: "wonderland".to_string(),
+                last_seen: None,
+            })
+        );
+        let result = decode_login(r#"{"type":"cancel","order_id":7}"#);
+        assert!(matches!(result, Err(ClientError::NotLoggedIn)));
+    }
+
+    #[test]
+    fn test_decode_order() {
+        let line = r#"{"type":"order","side":"sell","product":"APPLE","price":"1.25","quantity":15,"time_in_force":"IOC"}"#;
+        assert_eq!(
+            decode_request("alice", line, &Instruments::default()).ok(),
+            Some(ClientRequest::Order(Transaction {
+                trader_id: "alice".to_string(),
+                side: Side::Sell,
+                product: Product::APPLE,
+                price: Some(price("1.25")),
+                quantity: 15,
+                time_in_force: TimeInForce::Ioc,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_decode_order_defaults() {
+        let line = r#"{"type":"order","side":"BUY","product":"apple"}"#;
+        match decode_request("alice", line, &Instruments::default()) {
+            Ok(ClientRequest::Order(transaction)) => {
+                assert_eq!(transaction.price, None);
+                assert_eq!(transaction.quantity, 1);
+                assert_eq!(transaction.time_in_force, TimeInForce::Ioc);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_decode_order_same_validation_as_text() {
+        for (line, expected) in [
+            (
+                r#"{"type":"order","side":"hold","product":"APPLE","price":"1"}"#,
+                ClientError::UnknownSide,
+            ),
+            (
+                r#"{"type":"order","side":"BUY","product":"GAME STOP","price":"1"}"#,
+                ClientError::UnknownProduct,
+            ),
+            (
+                r#"{"type":"order","side":"BUY","product":"GME","price":"1"}"#,
+                ClientError::UnknownProduct,
+            ),
+            (
+                r#"{"type":"order","side":"BUY","product":"APPLE","price":"cheap"}"#,
+                ClientError::InvalidPrice,
+            ),
+            (
+                r#"{"type":"order","side":"BUY","product":"APPLE","price":"1","quantity":0}"#,
+                ClientError::InvalidQuantity,
+            ),
+            (
+                r#"{"type":"order","side":"BUY","product":"APPLE","time_in_force":"GTC"}"#,
+                ClientError::MarketOrderCannotRest,
+            ),
+        ] {
+            assert_eq!(
+                decode_request("alice", line, &Instruments::default()).err(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_posted_order() {
+        let instruments = Instruments::default();
+        let body = r#"{"side":"buy","product":"pear","price":"2","quantity":3}"#;
+        let transaction = decode_order("bob", body, &instruments).unwrap();
+        assert_eq!(transaction.trader_id, "bob");
+        assert_eq!(transaction.product, Product::PEAR);
+        assert_eq!(transaction.price, Some(price("2")));
+        assert_eq!(transaction.quantity, 3);
+        let result = decode_order(
+            "bob",
+            r#"{"type":"order","side":"BUY","product":"PEAR"}"#,
+            &instruments,
+        );
+        assert!(matches!(result, Err(ClientError::InvalidJsonMessage(_))));
+        // Checked against the registry it is given
+        let body = r#"{"side":"buy","product":"kiwi","price":"2"}"#;
+        let result = decode_order("bob", body, &instruments);
+        assert_eq!(result.err(), Some(ClientError::UnknownProduct));
+        let kiwi = Product::from_str("KIWI").unwrap();
+        instruments.list(Instrument::new(kiwi));
+        let transaction = decode_order("bob", body, &instruments).unwrap();
+        assert_eq!(transaction.product, kiwi);
+    }
+
+    #[test]
+    fn test_decode_cancel_and_amend() {
+        let result = decode_request(
+            "alice",
+            r#"{"type":"cancel","order_id":17}"#,
+            &Instruments::default(),
+        );
+        assert_eq!(result.ok(), Some(ClientRequest::Cancel(17)));
+        let result = decode_request(
+            "alice",
+            r#"{"type":"amend","order_id":17,"price":"1.5","quantity":20}"#,
+            &Instruments::default(),
+        );
+        assert_eq!(
+            result.ok(),
+            Some(ClientRequest::Amend {
+                order_id: 17,
+                price: price("1.5"),
+                quantity: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let result = decode_request("alice", r#"{"type":"heartbeat"}"#, &Instruments::default());
+        assert_eq!(result.ok(), Some(ClientRequest::Heartbeat));
+        let envelope = Envelope::unsequenced(ServerMessage::Heartbeat);
+        assert_eq!(encode(&envelope), r#"{"type":"heartbeat"}"#);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let result = decode_request(
+            "alice",
+            r#"{"type":"subscribe","product":"apple"}"#,
+            &Instruments::default(),
+        );
+        assert_eq!(result.ok(), Some(ClientRequest::Subscribe(Product::APPLE)));
+        let result = decode_request(
+            "alice",
+            r#"{"type":"unsubscribe","product":"APPLE"}"#,
+            &Instruments::default(),
+        );
+        assert_eq!(
+            result.ok(),
+            Some(ClientRequest::Unsubscribe(Product::APPLE))
+        );
+        let snapshot = Envelope::unsequenced(ServerMessage::BookSnapshot {
+            product: Product::APPLE,
+            sequence: 2,
+            bids: vec![Level {
+                price: price("1.25"),
+                quantity: 10,
+            }],
+            asks: Vec::new(),
+        });
+        assert_eq!(
+            encode(&snapshot),
+            r#"{"type":"book","product":"APPLE","sequence":2,"bids":[{"price":"1.25","quantity":10}],"asks":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_json() {
+        for line in [
+            "buy:apple:1",
+            r#"{"type":"cancel"}"#,
+            r#"{"type":"short","order_id":1}"#,
+            r#"{"type":"cancel","order_id":-1}"#,
+        ] {
+            let result = decode_request("alice", line, &Instruments::default());
+            assert!(matches!(result, Err(ClientError::InvalidJsonMessage(_))));
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let envelope = Envelope {
+            sequence: Some(3),
+            message: ServerMessage::Execution {
+                trade_id: 11,
+                order_id: 7,
+                side: Side::Sell,
+                product: Product::APPLE,
+                price: price("1.25"),
+                quantity: 5,
+                leaves: 3,
+            },
+        };
+        assert_eq!(
+            encode(&envelope),
+            r#"{"seq":3,"type":"fill","trade_id":11,"order_id":7,"side":"SELL","product":"APPLE","price":"1.25","quantity":5,"leaves":3}"#
+        );
+    }
+
+    #[test]
+    fn test_encode_unsequenced_reject() {
+        let envelope = Envelope::unsequenced(ServerMessage::Reject {
+            error: ClientError::UnknownOrder,
+            input: Some(r#"{"type":"cancel","order_id":7}"#.to_string()),
+        });
+        assert_eq!(
+            encode(&envelope),
+            r#"{"type":"reject","code":"UNKNOWN_ORDER","reason":"Unknown order. It may have been filled or cancelled already","input":"{\"type\":\"cancel\",\"order_id\":7}"}"#
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use crate::accounts::AccountId;
+use crate::actions::Side;
+use crate::errors::Error;
+use crate::order_book::{Trade, TradeId};
+use crate::price::Price;
+use crate::products::Product;
+use crate::trader::Quantity;
+use crate::utils::civil_from_days;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A trade as kept for compliance, one JSON object per line of the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub trade_id: TradeId,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub product: Product,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub buyer: AccountId,
+    pub seller: AccountId,
+    /// Side of the incoming order that made the trade.
+    pub aggressor: Side,
+}
+
+/// Append-only ledger of all trades. Unlike the journal it is never compacted.
+/// Without a file nothing is kept.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    file: Option<File>,
+    last_trade_id: TradeId,
+    sync: bool,
+}
+
+impl LedgerEntry {
+    pub fn new(trade: &Trade) -> Self {
+        let (buyer, seller) = match trade.aggressor.side {
+            Side::Buy => (&trade.aggressor, &trade.resting),
+            Side::Sell => (&trade.resting, &trade.aggressor),
+        };
+        Self {
+            trade_id: trade.trade_id,
+            timestamp: trade.timestamp,
+            product: trade.product,
+            price: trade.price,
+            quantity: trade.quantity,
+            buyer: buyer.trader_id.clone(),
+            seller: seller.trader_id.clone(),
+            aggressor: trade.aggressor.side,
+        }
+    }
+
+    /// Columns of the CSV export.
+    pub const CSV_HEADER: &'static str =
+        "trade_id,timestamp,product,price,quantity,buyer,seller,aggressor";
+
+    pub fn to_csv(&self) -> String {
+        [
+            self.trade_id.to_string(),
+            iso_timestamp(self.timestamp),
+            self.product.to_string(),
+            self.price.to_string(),
+            self.quantity.to_string(),
+            csv_field(&self.buyer),
+            csv_field(&self.seller),
+            self.aggressor.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+impl Ledger {
+    /// Opens the ledger at `path`, creating it when missing. A torn last entry,
+    /// from a crash halfway through writing it, is cut off.
+    pub fn open(path: &Path, sync: bool) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        let (entries, length) = parse(&content)?;
+        if length < content.len() {
+            warn!(
+                "ledger ends with a torn entry, cutting off its {} bytes",
+                content.len() - length
+            );
+            file.set_len(length as u64)?;
+            file.sync_all()?;
+        }
+        Ok(Self {
+            file: Some(file),
+            last_trade_id: entries.last().map_or(0, |entry| entry.trade_id),
+            sync,
+        })
+    }
+
+    /// Appends the trades not in the ledger yet; replaying the journal makes
+    /// trades again that are already there.
+    pub fn append(&mut self, trades: &[Trade]) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let mut lines = Vec::new();
+        for trade in trades {
+            if trade.trade_id <= self.last_trade_id {
+                continue;
+            }
+            serde_json::to_writer(&mut lines, &LedgerEntry::new(trade))?;
+            lines.push(b'\n');
+            self.last_trade_id = trade.trade_id;
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        file.write_all(&lines)?;
+        if self.sync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Entries of complete lines, and the length of those lines. Only the last
+/// line can be incomplete; anything else unreadable is corruption.
+fn parse(content: &[u8]) -> Result<(Vec<LedgerEntry>, usize), Error> {
+    let mut entries = Vec::new();
+    let mut length = 0;
+    for (index, line) in content.split_inclusive(|&byte| byte == b'\n').enumerate() {
+        if !line.ends_with(b"\n") {
+            break;
+        }
+        let entry = serde_json::from_slice(line)
+            .map_err(|e| Error::InvalidLedger(format!("entry {}: {}", index + 1, e)))?;
+        entries.push(entry);
+        length += line.len();
+    }
+    Ok((entries, length))
+}
+
+/// Writes the trades of the ledger at `path` made from day `from` to day `to`,
+/// both included and counted since 1970-01-01 UTC, as CSV. Returns how many
+/// there were.
+pub fn export_csv(
+    path: &Path,
+    from: i64,
+    to: i64,
+    output: &mut impl Write,
+) -> Result<usize, Error> {
+    let content = fs::read(path)?;
+    let (entries, _) = parse(&content)?;
+    let start = (from.max(0) as u64).saturating_mul(MILLIS_PER_DAY);
+    let end = ((to + 1).max(0) as u64).saturating_mul(MILLIS_PER_DAY);
+    writeln!(output, "{}", LedgerEntry::CSV_HEADER)?;
+    let mut exported = 0;
+    for entry in entries
+        .iter()
+        .filter(|entry| (start..end).contains(&entry.timestamp))
+    {
+        writeln!(output, "{}", entry.to_csv())?;
+        exported += 1;
+    }
+    output.flush()?;
+    Ok(exported)
+}
+
+/// ISO 8601 in UTC with milliseconds, e.g. `2024-01-31T17:05:09.123Z`.
+fn iso_timestamp(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis % 1000
+    )
+}
+
+/// Quotes a field when it has a comma, a quote or a line break in it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::tests::TempDir;
+    use crate::order_book::Execution;
+    use crate::utils::{millis_since_epoch, parse_date};
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn trade(trade_id: TradeId, buyer: &str, seller: &str, time: SystemTime) -> Trade {
+        let execution = |trader_id: &str, side| Execution {
+            order_id: trade_id,
+            trader_id: trader_id.to_string(),
+            side,
+            leaves: 0,
+        };
+        Trade {
+            trade_id,
+            product: Product::APPLE,
+            price: Price::from_str("1.25").unwrap(),
+            quantity: 10,
+            aggressor: execution(seller, Side::Sell),
+            resting: execution(buyer, Side::Buy),
+            timestamp: millis_since_epoch(time),
+        }
+    }
+
+    /// Milliseconds into 2024-02-29.
+    fn leap_day(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_709_164_800_000 + millis)
+    }
+
+    #[test]
+    fn test_entry() {
+        let entry = LedgerEntry::new(&trade(7, "bob", "alice", leap_day(57_599_123)));
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"trade_id":7,"timestamp":1709222399123,"product":"APPLE","price":"1.25","quantity":10,"buyer":"bob","seller":"alice","aggressor":"SELL"}"#
+        );
+        assert_eq!(
+            entry.to_csv(),
+            "7,2024-02-29T15:59:59.123Z,APPLE,1.25,10,bob,alice,SELL"
+        );
+        let quoted = LedgerEntry::new(&trade(8, "bob, jr", "\"al\"", leap_day(0)));
+        assert_eq!(
+            quoted.to_csv(),
+            "8,2024-02-29T00:00:00.000Z,APPLE,1.25,10,\"bob, jr\",\"\"\"al\"\"\",SELL"
+        );
+    }
+
+    #[test]
+    fn test_append_and_export() {
+        let dir = TempDir::new("ledger");
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("trades.jsonl");
+        let mut ledger = Ledger::open(&path, false).unwrap();
+        ledger
+            .append(&[trade(
+                1,
+                "bob",
+                "alice",
+                leap_day(0) - Duration::from_millis(1),
+            )])
+            .unwrap();
+        ledger
+            .append(&[
+                trade(2, "bob", "alice", leap_day(1)),
+                trade(3, "carol", "alice", leap_day(1)),
+            ])
+            .unwrap();
+        // Replayed trades are in the ledger already
+        ledger
+            .append(&[trade(3, "carol", "alice", leap_day(2))])
+            .unwrap();
+        drop(ledger);
+        let mut torn = fs::read(&path).unwrap();
+        torn.extend_from_slice(br#"{"trade_id":4,"#);
+        fs::write(&path, torn).unwrap();
+        Ledger::open(&path, false).unwrap();
+
+        let mut csv = Vec::new();
+        let day = parse_date("2024-02-29").unwrap();
+        assert_eq!(export_csv(&path, day, day, &mut csv).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "trade_id,timestamp,product,price,quantity,buyer,seller,aggressor\n\
+             2,2024-02-29T00:00:00.001Z,APPLE,1.25,10,bob,alice,SELL\n\
+             3,2024-02-29T00:00:00.001Z,APPLE,1.25,10,carol,alice,SELL\n"
+        );
+        let mut csv = Vec::new();
+        assert_eq!(export_csv(&path, day - 1, day, &mut csv).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_corrupt_entry() {
+        let dir = TempDir::new("corrupt_ledger");
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("trades.jsonl");
+        fs::write(&path, "not json\n").unwrap();
+        assert!(matches!(
+            Ledger::open(&path, false),
+            Err(Error::InvalidLedger(reason)) if reason.starts_with("entry 1:")
+        ));
+    }
+}
@@ -1,111 +1,506 @@
+mod accounts;
 mod actions;
+mod binary;
+mod config;
 mod consts;
 mod errors;
+mod fix;
+mod fix_acceptor;
+mod heartbeat;
+mod instruments;
+mod journal;
+mod json;
+mod ledger;
+mod market_data;
+mod messages;
+mod order_book;
+mod price;
 mod products;
+mod protocol;
+mod rest;
+mod session;
 mod storage;
+mod time_in_force;
+mod tls;
 mod trader;
 mod transaction_service;
 mod utils;
+mod websocket;
 
-use consts::{BUFFER_SIZE, LOCALHOST, PORT};
-use errors::Error;
-use futures::sink::SinkExt;
+use accounts::{AccountId, Accounts, DisconnectPolicy};
+use binary::BinaryCodec;
+use bytes::BytesMut;
+use clap::Parser;
+use config::{Cli, Command, Config, Listen, StorageBackend};
+use errors::{ClientError, Error};
+use fix_acceptor::{FixAcceptor, FixSessions};
+use heartbeat::{Heartbeat, Idle};
+use instruments::Instruments;
 use log::{error, info};
+use messages::{Envelope, ServerMessage};
+use order_book::OrderId;
+use protocol::{Connection, Control, Frame, OrderEntryCodec, TextCodec};
+use std::future::{ready, Future, Ready};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use std::time::{Duration, SystemTime};
+use storage::{FileStorage, MemoryStorage, Storage};
+use tls::{Stream, Tls};
+use tokio::net::TcpListener;
 use tokio::runtime;
-use tokio::sync::mpsc::channel;
-use tokio_stream::StreamExt;
-use tokio_util::codec::{Framed, LinesCodec};
-use trader::{Trader, Transaction};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_util::codec::Framed;
+use trader::{ClientRequest, Trader};
 use transaction_service::TransactionService;
-use utils::{get_greeting_message, init_logs};
+use utils::{get_greeting_message, init_logs, time_until_end_of_day};
+use websocket::WebSocket;
 
 fn main() -> Result<(), Error> {
+    let mut cli = Cli::parse();
+    let command = cli.command.take();
+    let config = match Config::load(cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(Command::ExportTrades { from, to, output }) = command {
+        return export_trades(&config.ledger, from, to.unwrap_or(from), output.as_deref());
+    }
     let rt = runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
-    init_logs();
-    let transaction_service = Arc::new(TransactionService::default());
-    rt.block_on(run_trading(transaction_service))
+    init_logs(&config.log);
+    for line in config.to_string().lines() {
+        info!("{}", line);
+    }
+    let accounts = Accounts::load(&config.credentials)?;
+    let transaction_service = Arc::new(TransactionService::with_settings(
+        accounts,
+        config.settings,
+        Instruments::new(config.instruments),
+    ));
+    let storage: Box<dyn Storage> = match config.storage {
+        StorageBackend::File => Box::new(FileStorage::open(
+            Path::new(&config.journal.dir),
+            Path::new(&config.ledger),
+            config.journal.sync,
+        )?),
+        StorageBackend::Memory => Box::new(MemoryStorage::default()),
+    };
+    let records = transaction_service.recover(storage)?;
+    info!("recovered {} journal records", records);
+    let tls = match &config.tls {
+        Some(tls) => Some(Arc::new(Tls::load(
+            &tls.cert,
+            &tls.key,
+            tls.client_ca.as_deref(),
+            &tls.clients,
+        )?)),
+        None => None,
+    };
+    let snapshot_interval = Duration::from_secs(config.journal.snapshot_interval_secs);
+    rt.block_on(run_trading(
+        transaction_service,
+        config.listen,
+        tls,
+        snapshot_interval,
+    ))
+}
+
+/// Writes the ledger's trades from day `from` to day `to` as CSV, to `output`
+/// or the standard output.
+fn export_trades(ledger: &str, from: i64, to: i64, output: Option<&str>) -> Result<(), Error> {
+    let trades = match output {
+        Some(path) => {
+            let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+            ledger::export_csv(Path::new(ledger), from, to, &mut file)?
+        }
+        None => ledger::export_csv(Path::new(ledger), from, to, &mut io::stdout().lock())?,
+    };
+    eprintln!("exported {} trades", trades);
+    Ok(())
+}
+
+async fn run_trading(
+    transaction_service: Arc<TransactionService>,
+    addresses: Listen,
+    tls: Option<Arc<Tls>>,
+    snapshot_interval: Duration,
+) -> Result<(), Error> {
+    let day_service = Arc::clone(&transaction_service);
+    tokio::task::spawn(async move {
+        if let Err(e) = expire_day_orders(day_service).await {
+            error!("Error occurred while expiring day orders! {}", e);
+        }
+    });
+    tokio::task::spawn(take_snapshots(
+        Arc::clone(&transaction_service),
+        snapshot_interval,
+    ));
+    // Every codec checks orders against the products the service lists
+    let instruments = Arc::clone(transaction_service.registry());
+    let binary_instruments = Arc::clone(&instruments);
+    spawn_listener(
+        "binary",
+        addresses.binary,
+        &transaction_service,
+        &tls,
+        framed(move || BinaryCodec::new(binary_instruments.clone())),
+    )
+    .await?;
+    let fix_sessions = FixSessions::default();
+    let fix_instruments = Arc::clone(&instruments);
+    spawn_listener(
+        "FIX",
+        addresses.fix,
+        &transaction_service,
+        &tls,
+        framed(move || FixAcceptor::new(fix_sessions.clone(), fix_instruments.clone())),
+    )
+    .await?;
+    let websocket_instruments = Arc::clone(&instruments);
+    spawn_listener(
+        "WebSocket",
+        addresses.websocket,
+        &transaction_service,
+        &tls,
+        move |stream| WebSocket::accept(stream, TextCodec::new(websocket_instruments.clone())),
+    )
+    .await?;
+    let http_listener = TcpListener::bind(addresses.http).await?;
+    let http_listener = rest::HttpListener::new(http_listener, tls.clone());
+    let router = rest::router(Arc::clone(&transaction_service));
+    tokio::task::spawn(async move {
+        if let Err(e) = axum::serve(http_listener, router).await {
+            error!("Error occurred while serving HTTP! {}", e);
+        }
+    });
+    let listener = TcpListener::bind(addresses.text).await?;
+    let text = framed(move || TextCodec::new(instruments.clone()));
+    listen(listener, transaction_service, tls, text).await
 }
 
-async fn run_trading(transaction_service: Arc<TransactionService>) -> Result<(), Error> {
-    let address = format!("{}:{}", LOCALHOST, PORT);
+/// Binds `address` and accepts traders on it in the background.
+async fn spawn_listener<T, F, Fut>(
+    name: &'static str,
+    address: SocketAddr,
+    transaction_service: &Arc<TransactionService>,
+    tls: &Option<Arc<Tls>>,
+    connect: F,
+) -> Result<(), Error>
+where
+    T: Connection + 'static,
+    Frame<T>: Send,
+    F: Fn(Stream) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send + 'static,
+{
     let listener = TcpListener::bind(address).await?;
+    let transaction_service = Arc::clone(transaction_service);
+    let tls = tls.clone();
+    tokio::task::spawn(async move {
+        if let Err(e) = listen(listener, transaction_service, tls, connect).await {
+            error!("Error occurred while accepting {} connections! {}", name, e);
+        }
+    });
+    Ok(())
+}
+
+/// Accepts traders on `listener`, `connect` sets up the connection of each
+/// once it is past the TLS handshake.
+async fn listen<T, F, Fut>(
+    listener: TcpListener,
+    transaction_service: Arc<TransactionService>,
+    tls: Option<Arc<Tls>>,
+    connect: F,
+) -> Result<(), Error>
+where
+    T: Connection + 'static,
+    Frame<T>: Send,
+    F: Fn(Stream) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send + 'static,
+{
     loop {
         let (stream, socket_addr) = listener.accept().await?;
-        let trader_id = socket_addr.port();
         let transaction_service = Arc::clone(&transaction_service);
-        info!("{}", get_greeting_message(socket_addr.ip(), trader_id)?);
+        info!(
+            "{}",
+            get_greeting_message(socket_addr.ip(), socket_addr.port())?
+        );
+        let tls = tls.clone();
+        let connect = connect.clone();
         tokio::task::spawn(async move {
-            if let Err(e) = process(trader_id, stream, transaction_service).await {
-                error!("Error occurred! {}", e.to_string());
+            let result = async move {
+                let (stream, certified) = match tls {
+                    Some(tls) => {
+                        let stream = tls.handshake(stream).await?;
+                        let certified = tls.client_account(&stream)?;
+                        (stream, certified)
+                    }
+                    None => (Stream::Plain(stream), None),
+                };
+                let connection = connect(stream).await?;
+                process(connection, transaction_service, certified).await
+            };
+            if let Err(e) = result.await {
+                error!("Error occurred! {}", e);
             }
         });
     }
 }
 
-fn handle_new_trader(
-    trader_id: u16,
-    stream: TcpStream,
+/// Frames every trader's connection with a codec of its own, made by `codec`.
+fn framed<C, F>(codec: F) -> impl Fn(Stream) -> Ready<Result<Framed<Stream, C>, Error>> + Clone
+where
+    C: OrderEntryCodec,
+    F: Fn() -> C + Clone,
+{
+    move |stream| ready(Ok(Framed::new(stream, codec())))
+}
+
+async fn expire_day_orders(transaction_service: Arc<TransactionService>) -> Result<(), Error> {
+    loop {
+        tokio::time::sleep(time_until_end_of_day(SystemTime::now())).await;
+        for order in transaction_service.end_of_day() {
+            info!(
+                "cancel order ('{}', {}, {} left, {})",
+                order.trader_id, order.order_id, order.quantity, order.time_in_force
+            );
+            transaction_service
+                .confirm_expiry(
+                    &order.trader_id,
+                    order.order_id,
+                    order.quantity,
+                    order.time_in_force,
+                )
+                .await?;
+        }
+    }
+}
+
+/// A failed snapshot leaves the journal as it was, so trading goes on and the
+/// next one tries again.
+async fn take_snapshots(transaction_service: Arc<TransactionService>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match transaction_service.snapshot() {
+            Ok(Some(records)) => info!("snapshot ({} journal records)", records),
+            Ok(None) => {}
+            Err(e) => error!("Error occurred while taking a snapshot! {}", e),
+        }
+    }
+}
+
+/// Waits for a successful `LOGIN`, answering any other message with an error.
+/// Traders with a client certificate can only log in to its account, and their
+/// secret is not checked. Returns `None` when the trader disconnects before logging in.
+async fn login<T: Connection>(
+    connection: &mut T,
+    sender: Sender<Envelope>,
+    transaction_service: &TransactionService,
+    certified: Option<&AccountId>,
+) -> Result<Option<AccountId>, Error> {
+    while let Some(result) = connection.next_frame().await {
+        let frame = match result {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!("Error occurred while processing login. {}", e);
+                continue;
+            }
+        };
+        let login = connection
+            .codec_mut()
+            .decode_login(&frame)
+            .and_then(|credentials| match certified {
+                Some(account_id) if *account_id == credentials.name => transaction_service
+                    .open_session(account_id.clone(), credentials.last_seen, sender.clone()),
+                Some(_) => Err(ClientError::InvalidCredentials),
+                None => transaction_service.login(&credentials, sender.clone()),
+            });
+        match login {
+            Ok((account_id, messages)) => {
+                info!("logged in ('{}')", account_id);
+                for message in messages {
+                    connection.send(message).await?;
+                }
+                return Ok(Some(account_id));
+            }
+            Err(error) => {
+                // Login messages are not echoed back, they hold the secret
+                let reject = ServerMessage::Reject { error, input: None };
+                connection.send(Envelope::unsequenced(reject)).await?
+            }
+        }
+    }
+    Ok(None)
+}
+
+async fn process<T>(
+    mut connection: T,
     transaction_service: Arc<TransactionService>,
-) -> Trader {
-    let lines = Framed::new(stream, LinesCodec::new());
-    let (sender, receiver) = channel(BUFFER_SIZE);
-    transaction_service.register_trader(trader_id, sender);
-    Trader {
+    certified: Option<AccountId>,
+) -> Result<(), Error>
+where
+    T: Connection,
+    Frame<T>: Send,
+{
+    let (sender, receiver) = channel(transaction_service.settings().channel_size);
+    let login = login(
+        &mut connection,
+        sender,
+        &transaction_service,
+        certified.as_ref(),
+    );
+    let trader_id = match login.await? {
+        Some(account_id) => account_id,
+        None => return Ok(()),
+    };
+    let mut trader = Trader {
         trader_id,
-        lines,
+        connection,
         receiver_ch: receiver,
+    };
+    // Whatever ended the session, the trader has to be cleaned up
+    let result = trade(&mut trader, &transaction_service).await;
+    trader.connection.codec_mut().closed();
+    info!("logged out ('{}')", trader.trader_id);
+    let login = transaction_service.remove_trader(&trader.trader_id);
+    if let DisconnectPolicy::Cancel { grace } =
+        transaction_service.disconnect_policy(&trader.trader_id)
+    {
+        tokio::time::sleep(grace).await;
+        let orders = transaction_service.cancel_on_disconnect(&trader.trader_id, login);
+        if !orders.is_empty() {
+            info!(
+                "cancel all orders ('{}', {} orders, disconnected)",
+                trader.trader_id,
+                orders.len()
+            );
+        }
+        for order in orders {
+            transaction_service
+                .confirm_cancel(&order.trader_id, order.order_id)
+                .await?;
+        }
     }
+    result
 }
 
-async fn process(
-    trader_id: u16,
-    stream: TcpStream,
-    transaction_service: Arc<TransactionService>,
-) -> Result<(), Error> {
-    let mut trader = handle_new_trader(trader_id, stream, transaction_service.clone());
+/// Runs a logged in session until the trader logs out, disconnects or goes quiet.
+async fn trade<T>(
+    trader: &mut Trader<T>,
+    transaction_service: &Arc<TransactionService>,
+) -> Result<(), Error>
+where
+    T: Connection,
+    Frame<T>: Send,
+{
+    let mut heartbeat = Heartbeat::new(trader.connection.codec_mut().heartbeat_interval());
     loop {
         tokio::select! {
-            Some(msg) = trader.receiver_ch.recv() => {
-                trader.lines.send(&msg).await?;
+            Some(envelope) = trader.receiver_ch.recv() => {
+                send(&mut trader.connection, envelope).await?;
             }
-            result = trader.lines.next() => match result {
-                Some(Ok(line)) => read_transaction_message(trader_id, line, transaction_service.clone()).await?,
-                Some(Err(e)) => error!("Error occurred while processing transaction. {}",e.to_string()),
-                None => break,
+            result = trader.connection.next_frame() => match result {
+                Some(Ok(frame)) => {
+                    heartbeat.heard();
+                    let mut replies = BytesMut::new();
+                    let control = trader.connection.codec_mut().control(&frame, &mut replies)?;
+                    trader.connection.send_encoded(replies).await?;
+                    match control {
+                        Control::Request => {
+                            let request = trader.connection.codec_mut().decode_request(&trader.trader_id, &frame);
+                            let input = T::Codec::echo(&frame);
+                            let placed = read_transaction_message(&trader.trader_id, input, request, transaction_service.clone()).await?;
+                            if let Some(order_id) = placed {
+                                trader.connection.codec_mut().placed(order_id);
+                            }
+                        }
+                        Control::Handled => {}
+                        Control::Logout => return Ok(()),
+                    }
+                }
+                Some(Err(e)) => error!("Error occurred while processing transaction. {}", e),
+                None => return Ok(()),
+            },
+            idle = heartbeat.idle() => match idle {
+                Idle::SendHeartbeat => {
+                    let heartbeat = Envelope::unsequenced(ServerMessage::Heartbeat);
+                    send(&mut trader.connection, heartbeat).await?;
+                }
+                Idle::TimedOut => {
+                    info!("heartbeat timed out ('{}')", trader.trader_id);
+                    return Ok(());
+                }
             },
         }
     }
-    transaction_service.remove_trader(trader_id);
-    Ok(())
+}
+
+/// Gives up on traders that stop reading, e.g. behind a half-open connection,
+/// instead of blocking the session forever.
+async fn send<T: Connection>(connection: &mut T, envelope: Envelope) -> Result<(), Error> {
+    match tokio::time::timeout(Heartbeat::timeout(), connection.send(envelope)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "trader stopped reading").into()),
+    }
 }
 
 async fn read_transaction_message(
-    trader_id: u16,
-    line: String,
+    trader_id: &str,
+    input: Option<String>,
+    request: Result<ClientRequest, ClientError>,
     transaction_service: Arc<TransactionService>,
-) -> Result<(), Error> {
-    match Transaction::new_from(trader_id, line) {
-        Ok(transaction) => {
-            info!("{}", transaction);
-            transaction_service
-                .confirm(transaction.trader_id, transaction.product)
-                .await?;
-            match transaction_service.try_trade_with(transaction) {
-                Some(matched_item) => {
-                    info!("{}", TransactionService::log_trade(matched_item));
-                    transaction_service.inform_all(matched_item).await?;
+) -> Result<Option<OrderId>, Error> {
+    match request {
+        Ok(ClientRequest::Order(transaction)) => {
+            match transaction_service.place_order(transaction).await {
+                Ok(placement) => return Ok(Some(placement.order_id)),
+                Err(Error::ClientError(e)) => {
+                    transaction_service.reject(trader_id, e, input).await?
                 }
-                None => transaction_service.register_order(transaction),
+                Err(e) => return Err(e),
             }
         }
-        Err(e) => {
-            let error_msg = format!("{}", e.to_string());
-            transaction_service.send_error(trader_id, error_msg).await?;
+        Ok(ClientRequest::Cancel(order_id)) => {
+            match transaction_service.cancel_order(trader_id, order_id) {
+                Ok(_) => {
+                    info!("cancel order ('{}', {})", trader_id, order_id);
+                    transaction_service
+                        .confirm_cancel(trader_id, order_id)
+                        .await?;
+                }
+                Err(e) => transaction_service.reject(trader_id, e, input).await?,
+            }
         }
+        Ok(ClientRequest::Amend {
+            order_id,
+            price,
+            quantity,
+        }) => match transaction_service.amend_order(trader_id, order_id, price, quantity) {
+            Ok((order, trades)) => {
+                info!(
+                    "amend order ('{}', {}, {} @ {})",
+                    trader_id, order_id, quantity, price
+                );
+                transaction_service.confirm_replace(&order).await?;
+                transaction_service.publish_trades(&trades).await?;
+            }
+            Err(e) => transaction_service.reject(trader_id, e, input).await?,
+        },
+        Ok(ClientRequest::Heartbeat) => {}
+        Ok(ClientRequest::Subscribe(product)) => {
+            if let Err(e) = transaction_service.subscribe(trader_id, product) {
+                transaction_service.reject(trader_id, e, input).await?
+            }
+        }
+        Ok(ClientRequest::Unsubscribe(product)) => {
+            transaction_service.unsubscribe(trader_id, product).await?
+        }
+        Err(e) => transaction_service.reject(trader_id, e, input).await?,
     };
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,223 @@
+use crate::accounts::AccountId;
+use crate::actions::Side;
+use crate::messages::{Envelope, ServerMessage};
+use crate::order_book::Level;
+use crate::price::Price;
+use crate::products::Product;
+use crate::trader::Quantity;
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+
+/// What happened to a price level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LevelChange {
+    Add,
+    Modify,
+    Delete,
+}
+
+/// A change to one price level of a book; a deleted level has no quantity left.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LevelUpdate {
+    pub change: LevelChange,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+/// Level-2 feed of one book: the depth last published and who subscribed to it.
+/// Every update gets the next sequence number, so subscribers can tell when
+/// they missed some and subscribe again for a new snapshot.
+#[derive(Debug, Default)]
+pub struct MarketData {
+    /// Of the latest update, also carried by snapshots.
+    sequence: u64,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    subscribers: HashMap<AccountId, Sender<Envelope>>,
+}
+
+impl MarketData {
+    pub fn new(bids: Vec<Level>, asks: Vec<Level>) -> Self {
+        Self {
+            bids,
+            asks,
+            ..Default::default()
+        }
+    }
+
+    /// Sends the subscriber a snapshot; updates follow it from then on.
+    pub fn subscribe(&mut self, product: Product, account_id: &str, sender: Sender<Envelope>) {
+        let snapshot = ServerMessage::BookSnapshot {
+            product,
+            sequence: self.sequence,
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+        };
+        if send(&sender, snapshot) {
+            self.subscribers.insert(account_id.to_string(), sender);
+        }
+    }
+
+    /// Whether the account was subscribed.
+    pub fn unsubscribe(&mut self, account_id: &str) -> bool {
+        self.subscribers.remove(account_id).is_some()
+    }
+
+    /// Moves on to the new depth of the book, sending subscribers an update per
+    /// changed level.
+    pub fn update(&mut self, product: Product, bids: Vec<Level>, asks: Vec<Level>) {
+        let mut updates = diff(Side::Buy, &self.bids, &bids);
+        updates.extend(diff(Side::Sell, &self.asks, &asks));
+        (self.bids, self.asks) = (bids, asks);
+        for update in updates {
+            self.sequence += 1;
+            let message = ServerMessage::BookUpdate {
+                product,
+                sequence: self.sequence,
+                update,
+            };
+            self.subscribers
+                .retain(|_, sender| send(sender, message.clone()));
+        }
+    }
+}
+
+/// Sends without waiting, so updates leave in the order the books changed. A
+/// subscriber that cannot keep up misses the message and sees a gap; one that
+/// disconnected is dropped.
+fn send(sender: &Sender<Envelope>, message: ServerMessage) -> bool {
+    match sender.try_send(Envelope::unsequenced(message)) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            debug!("market data subscriber is behind, dropping an update");
+            true
+        }
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+/// Updates turning the `old` levels of a side into the `new` ones, by price.
+fn diff(side: Side, old: &[Level], new: &[Level]) -> Vec<LevelUpdate> {
+    let mut levels: BTreeMap<Price, (Quantity, Quantity)> = BTreeMap::new();
+    for level in old {
+        levels.entry(level.price).or_default().0 = level.quantity;
+    }
+    for level in new {
+        levels.entry(level.price).or_default().1 = level.quantity;
+    }
+    levels
+        .into_iter()
+        .filter_map(|(price, (before, after))| {
+            let change = match (before, after) {
+                (0, _) => LevelChange::Add,
+                (_, 0) => LevelChange::Delete,
+                _ if before != after => LevelChange::Modify,
+                _ => return None,
+            };
+            Some(LevelUpdate {
+                change,
+                side,
+                price,
+                quantity: after,
+            })
+        })
+        .collect()
+}
+
+impl Display for LevelChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelChange::Add => write!(f, "ADD"),
+            LevelChange::Modify => write!(f, "MODIFY"),
+            LevelChange::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tokio::sync::mpsc::channel;
+
+    fn level(price: &str, quantity: Quantity) -> Level {
+        Level {
+            price: Price::from_str(price).unwrap(),
+            quantity,
+        }
+    }
+
+    fn update(change: LevelChange, side: Side, price: &str, quantity: Quantity) -> LevelUpdate {
+        LevelUpdate {
+            change,
+            side,
+            price: Price::from_str(price).unwrap(),
+            quantity,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = [level("1.3", 5), level("1.2", 10), level("1.1", 4)];
+        let new = [level("1.25", 7), level("1.2", 3), level("1.1", 4)];
+        assert_eq!(
+            diff(Side::Buy, &old, &new),
+            vec![
+                update(LevelChange::Modify, Side::Buy, "1.2", 3),
+                update(LevelChange::Add, Side::Buy, "1.25", 7),
+                update(LevelChange::Delete, Side::Buy, "1.3", 0),
+            ]
+        );
+        assert!(diff(Side::Sell, &new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_then_updates() {
+        let mut market_data = MarketData::new(vec![level("1", 5)], Vec::new());
+        let (sender, mut receiver) = channel(10);
+        market_data.subscribe(Product::APPLE, "alice", sender);
+        market_data.update(Product::APPLE, vec![level("1", 3)], vec![level("2", 1)]);
+        assert!(market_data.unsubscribe("alice"));
+        market_data.update(Product::APPLE, Vec::new(), Vec::new());
+
+        let messages: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|envelope| envelope.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "BOOK:APPLE:0:5@1:",
+                "BOOK_UPDATE:APPLE:1:MODIFY:BUY:1:3",
+                "BOOK_UPDATE:APPLE:2:ADD:SELL:2:1",
+            ]
+        );
+        // Nobody listens anymore, but the sequence keeps counting
+        let (sender, mut receiver) = channel(10);
+        market_data.subscribe(Product::APPLE, "bob", sender);
+        assert_eq!(receiver.try_recv().unwrap().to_string(), "BOOK:APPLE:4::");
+    }
+
+    #[test]
+    fn test_slow_and_gone_subscribers() {
+        let mut market_data = MarketData::default();
+        let (slow, mut slow_receiver) = channel(1);
+        let (gone, gone_receiver) = channel(10);
+        market_data.subscribe(Product::APPLE, "slow", slow);
+        market_data.subscribe(Product::APPLE, "gone", gone);
+        drop(gone_receiver);
+        market_data.update(Product::APPLE, vec![level("1", 1)], Vec::new());
+        assert!(!market_data.unsubscribe("gone"));
+        // The snapshot filled the channel, so the update was missed
+        assert!(slow_receiver.try_recv().is_ok());
+        market_data.update(Product::APPLE, vec![level("1", 2)], Vec::new());
+        assert_eq!(
+            slow_receiver.try_recv().unwrap().to_string(),
+            "BOOK_UPDATE:APPLE:2:MODIFY:BUY:1:2"
+        );
+        assert!(market_data.unsubscribe("slow"));
+    }
+}
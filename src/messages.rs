@@ -0,0 +1,275 @@
+use crate::accounts::AccountId;
+use crate::actions::{ServerActions, Side};
+use crate::errors::ClientError;
+use crate::market_data::LevelUpdate;
+use crate::order_book::{Level, OrderId, RestingOrder, TradeId};
+use crate::price::Price;
+use crate::products::Product;
+use crate::session::Sequence;
+use crate::time_in_force::TimeInForce;
+use crate::trader::Quantity;
+use std::fmt::{Display, Formatter};
+
+/// Everything the server tells a trader, independent of the wire format.
+/// `Display` gives the colon separated text format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    LoggedIn {
+        account_id: AccountId,
+        last_sequence: Sequence,
+    },
+    Ack {
+        product: Product,
+        order_id: OrderId,
+    },
+    Canceled {
+        order_id: OrderId,
+    },
+    /// Remainder of an order cancelled by its time in force.
+    Expired {
+        order_id: OrderId,
+        quantity: Quantity,
+        time_in_force: TimeInForce,
+    },
+    Replaced(RestingOrder),
+    /// One side of a trade, for the trader who owns the order.
+    Execution {
+        trade_id: TradeId,
+        order_id: OrderId,
+        side: Side,
+        product: Product,
+        price: Price,
+        quantity: Quantity,
+        leaves: Quantity,
+    },
+    /// Anonymous trade tape, sent to everyone.
+    Trade {
+        product: Product,
+        price: Price,
+        quantity: Quantity,
+    },
+    /// A request that could not be carried out, with the input that caused it
+    /// when there is one.
+    Reject {
+        error: ClientError,
+        input: Option<String>,
+    },
+    /// Sent to a quiet trader, who has to answer before the session times out.
+    Heartbeat,
+    /// Depth of a book for a new subscriber, as of update `sequence`.
+    BookSnapshot {
+        product: Product,
+        sequence: u64,
+        bids: Vec<Level>,
+        asks: Vec<Level>,
+    },
+    /// Change to a price level of a subscribed book.
+    BookUpdate {
+        product: Product,
+        sequence: u64,
+        update: LevelUpdate,
+    },
+    Unsubscribed {
+        product: Product,
+    },
+}
+
+/// A message on its way to a trader, numbered when it is part of the
+/// account's sequenced stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub sequence: Option<Sequence>,
+    pub message: ServerMessage,
+}
+
+impl Envelope {
+    pub fn unsequenced(message: ServerMessage) -> Self {
+        Self {
+            sequence: None,
+            message,
+        }
+    }
+}
+
+impl Display for ServerMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerMessage::LoggedIn {
+                account_id,
+                last_sequence,
+            } => write!(
+                f,
+                "{}:{}:{}",
+                ServerActions::LoggedIn,
+                account_id,
+                last_sequence
+            ),
+            ServerMessage::Ack { product, order_id } => {
+                write!(f, "{}:{}:{}", ServerActions::Ack, product, order_id)
+            }
+            ServerMessage::Canceled { order_id } => {
+                write!(f, "{}:{}", ServerActions::Canceled, order_id)
+            }
+            ServerMessage::Expired {
+                order_id,
+                quantity,
+                time_in_force,
+            } => write!(
+                f,
+                "{}:{}:{}:{}",
+                ServerActions::Canceled,
+                order_id,
+                quantity,
+                time_in_force
+            ),
+            ServerMessage::Replaced(order) => write!(
+                f,
+                "{}:{}:{}:{}:{}:{}",
+                ServerActions::Replaced,
+                order.order_id,
+                order.product,
+                order.side,
+                order.price,
+                order.quantity
+            ),
+            ServerMessage::Execution {
+                trade_id,
+                order_id,
+                side,
+                product,
+                price,
+                quantity,
+                leaves,
+            } => write!(
+                f,
+                "{}:{}:{}:{}:{}:{}:{}:{}",
+                ServerActions::Execution,
+                trade_id,
+                order_id,
+                side,
+                product,
+                price,
+                quantity,
+                leaves
+            ),
+            ServerMessage::Trade {
+                product,
+                price,
+                quantity,
+            } => write!(
+                f,
+                "{}:{}:{}:{}",
+                ServerActions::Trade,
+                product,
+                price,
+                quantity
+            ),
+            ServerMessage::Reject { error, input } => match input {
+                Some(input) => write!(f, "{}:{}:{}", ServerActions::Reject, error.code(), input),
+                None => write!(f, "{}:{}:{}", ServerActions::Reject, error.code(), error),
+            },
+            ServerMessage::Heartbeat => write!(f, "{}", ServerActions::Heartbeat),
+            ServerMessage::BookSnapshot {
+                product,
+                sequence,
+                bids,
+                asks,
+            } => write!(
+                f,
+                "{}:{}:{}:{}:{}",
+                ServerActions::Book,
+                product,
+                sequence,
+                levels(bids),
+                levels(asks)
+            ),
+            ServerMessage::BookUpdate {
+                product,
+                sequence,
+                update,
+            } => write!(
+                f,
+                "{}:{}:{}:{}:{}:{}:{}",
+                ServerActions::BookUpdate,
+                product,
+                sequence,
+                update.change,
+                update.side,
+                update.price,
+                update.quantity
+            ),
+            ServerMessage::Unsubscribed { product } => {
+                write!(f, "{}:{}", ServerActions::Unsubscribed, product)
+            }
+        }
+    }
+}
+
+/// Levels of a side as `quantity@price`, best first and separated by commas.
+fn levels(levels: &[Level]) -> String {
+    levels
+        .iter()
+        .map(|level| format!("{}@{}", level.quantity, level.price))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Display for Envelope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.sequence {
+            Some(sequence) => write!(f, "{}:{}", sequence, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_reject_echoes_input() {
+        let reject = ServerMessage::Reject {
+            error: ClientError::UnknownProduct,
+            input: Some("buy:GME:1.25".to_string()),
+        };
+        assert_eq!(reject.to_string(), "REJECT:UNKNOWN_PRODUCT:buy:GME:1.25");
+    }
+
+    #[test]
+    fn test_reject_without_input() {
+        let reject = ServerMessage::Reject {
+            error: ClientError::ReplayUnavailable { from: 1, to: 2 },
+            input: None,
+        };
+        assert_eq!(
+            reject.to_string(),
+            "REJECT:REPLAY_UNAVAILABLE:Messages 1 to 2 are no longer available for replay"
+        );
+    }
+
+    #[test]
+    fn test_book_snapshot() {
+        let level = |price: &str, quantity| Level {
+            price: Price::from_str(price).unwrap(),
+            quantity,
+        };
+        let snapshot = ServerMessage::BookSnapshot {
+            product: Product::APPLE,
+            sequence: 3,
+            bids: vec![level("1.25", 10), level("1.2", 5)],
+            asks: Vec::new(),
+        };
+        assert_eq!(snapshot.to_string(), "BOOK:APPLE:3:10@1.25,5@1.2:");
+    }
+
+    #[test]
+    fn test_sequenced_envelope() {
+        let envelope = Envelope {
+            sequence: Some(4),
+            message: ServerMessage::Canceled { order_id: 7 },
+        };
+        assert_eq!(envelope.to_string(), "4:CANCELED:7");
+    }
+}
@@ -0,0 +1,805 @@
+use crate::accounts::AccountId;
+use crate::actions::Side;
+use crate::price::Price;
+use crate::products::Product;
+use crate::time_in_force::TimeInForce;
+use crate::trader::{Quantity, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type OrderId = u64;
+pub type TradeId = u64;
+type Sequence = u64;
+/// FIFO queue of the orders resting at one price, keyed by arrival sequence.
+type PriceLevel = BTreeMap<Sequence, RestingOrder>;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RestingOrder {
+    pub order_id: OrderId,
+    pub trader_id: AccountId,
+    pub side: Side,
+    pub product: Product,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub time_in_force: TimeInForce,
+}
+
+impl RestingOrder {
+    /// Market orders have no price to rest at, so they never make it to the book.
+    pub fn new(order_id: OrderId, transaction: Transaction) -> Option<Self> {
+        Some(Self {
+            order_id,
+            trader_id: transaction.trader_id,
+            side: transaction.side,
+            product: transaction.product,
+            price: transaction.price?,
+            quantity: transaction.quantity,
+            time_in_force: transaction.time_in_force,
+        })
+    }
+
+    fn as_transaction(&self) -> Transaction {
+        Transaction {
+            trader_id: self.trader_id.clone(),
+            side: self.side,
+            product: self.product,
+            price: Some(self.price),
+            quantity: self.quantity,
+            time_in_force: self.time_in_force,
+        }
+    }
+}
+
+/// Hands out increasing ids, starting from 1.
+#[derive(Default, Debug)]
+pub struct IdGenerator(AtomicU64);
+
+impl IdGenerator {
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// The latest id handed out, 0 before the first.
+    pub fn last(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Makes sure `id` is never handed out again.
+    pub fn skip_to(&self, id: u64) {
+        self.0.fetch_max(id, Ordering::Relaxed);
+    }
+}
+
+/// One order's part in a trade; `leaves` is what is still left of it afterwards.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Execution {
+    pub order_id: OrderId,
+    pub trader_id: AccountId,
+    pub side: Side,
+    pub leaves: Quantity,
+}
+
+/// A single fill of an incoming (aggressor) order against a resting one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub trade_id: TradeId,
+    pub product: Product,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub aggressor: Execution,
+    pub resting: Execution,
+    /// When it was made, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+/// Aggregated quantity at one price of the book.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Level {
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct OrderLocation {
+    side: Side,
+    price: Price,
+    sequence: Sequence,
+}
+
+/// Resting orders of a single product, sorted into price levels per side.
+#[derive(Default, Debug)]
+pub struct OrderBook {
+    buys: BTreeMap<Price, PriceLevel>,
+    sells: BTreeMap<Price, PriceLevel>,
+    locations: HashMap<OrderId, OrderLocation>,
+    next_sequence: Sequence,
+}
+
+impl OrderBook {
+    pub fn insert(&mut self, order: RestingOrder) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.locations.insert(
+            order.order_id,
+            OrderLocation {
+                side: order.side,
+                price: order.price,
+                sequence,
+            },
+        );
+        self.side_mut(order.side)
+            .entry(order.price)
+            .or_default()
+            .insert(sequence, order);
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) -> Option<RestingOrder> {
+        let location = self.locations.remove(&order_id)?;
+        let side = self.side_mut(location.side);
+        let level = side.get_mut(&location.price)?;
+        let order = level.remove(&location.sequence);
+        if level.is_empty() {
+            side.remove(&location.price);
+        }
+        order
+    }
+
+    pub fn get(&self, order_id: OrderId) -> Option<&RestingOrder> {
+        let location = self.locations.get(&order_id)?;
+        self.side(location.side)
+            .get(&location.price)?
+            .get(&location.sequence)
+    }
+
+    /// Resting orders of one side, in the order they would be executed.
+    pub fn orders(&self, side: Side) -> Vec<RestingOrder> {
+        self.priority_levels(side)
+            .flat_map(|(_, level)| level.values().cloned())
+            .collect()
+    }
+
+    /// Total quantity resting at each price of one side, best price first.
+    pub fn depth(&self, side: Side) -> Vec<Level> {
+        self.priority_levels(side)
+            .map(|(price, level)| Level {
+                price: *price,
+                quantity: level.values().map(|order| order.quantity).sum(),
+            })
+            .collect()
+    }
+
+    /// Finds the resting order an incoming `transaction` should execute against:
+    /// the best-priced crossing order of another trader, the earliest one on ties.
+    pub fn try_find(&self, transaction: &Transaction) -> Option<RestingOrder> {
+        self.crossing_orders(transaction).next().cloned()
+    }
+
+    /// Quantity an incoming `transaction` could execute right now,
+    /// capped at the quantity it asks for.
+    pub fn available(&self, transaction: &Transaction) -> Quantity {
+        let mut available = 0;
+        for order in self.crossing_orders(transaction) {
+            available += order.quantity;
+            if available >= transaction.quantity {
+                return transaction.quantity;
+            }
+        }
+        available
+    }
+
+    /// Removes every resting order that was only good for the day.
+    pub fn expire_day_orders(&mut self) -> Vec<RestingOrder> {
+        self.cancel_where(|order| order.time_in_force == TimeInForce::Day)
+    }
+
+    /// Pulls every resting order of a trader, from both sides.
+    pub fn cancel_all(&mut self, trader_id: &str) -> Vec<RestingOrder> {
+        self.cancel_where(|order| order.trader_id == trader_id)
+    }
+
+    /// Executes `quantity` against a resting order,
+    /// removing it once it is completely filled.
+    pub fn fill(&mut self, order_id: OrderId, quantity: Quantity) {
+        let location = match self.locations.get(&order_id) {
+            Some(location) => *location,
+            None => return,
+        };
+        let is_filled = match self
+            .side_mut(location.side)
+            .get_mut(&location.price)
+            .and_then(|level| level.get_mut(&location.sequence))
+        {
+            Some(order) => {
+                order.quantity -= quantity;
+                order.quantity == 0
+            }
+            None => return,
+        };
+        if is_filled {
+            self.cancel(order_id);
+        }
+    }
+
+    /// Sweeps the opposite side for as long as the transaction crosses it
+    /// and still has quantity left, producing one trade per fill at `timestamp`.
+    pub fn match_order(
+        &mut self,
+        order_id: OrderId,
+        transaction: &Transaction,
+        trade_ids: &IdGenerator,
+        timestamp: u64,
+    ) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut leaves = transaction.quantity;
+        while leaves > 0 {
+            let remaining = Transaction {
+                quantity: leaves,
+                ..transaction.clone()
+            };
+            let resting = match self.try_find(&remaining) {
+                Some(resting) => resting,
+                None => break,
+            };
+            let quantity = leaves.min(resting.quantity);
+            self.fill(resting.order_id, quantity);
+            leaves -= quantity;
+            trades.push(Trade {
+                trade_id: trade_ids.next(),
+                product: transaction.product,
+                price: resting.price,
+                quantity,
+                aggressor: Execution {
+                    order_id,
+                    trader_id: transaction.trader_id.clone(),
+                    side: transaction.side,
+                    leaves,
+                },
+                resting: Execution {
+                    order_id: resting.order_id,
+                    trader_id: resting.trader_id,
+                    side: resting.side,
+                    leaves: resting.quantity - quantity,
+                },
+                timestamp,
+            });
+        }
+        trades
+    }
+
+    /// Changes the price and remaining quantity of a resting order.
+    /// Reducing the quantity keeps the order's place in its queue, any other
+    /// change sends it to the back; a new price crossing the book executes first.
+    pub fn amend(
+        &mut self,
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+        trade_ids: &IdGenerator,
+        timestamp: u64,
+    ) -> Option<(RestingOrder, Vec<Trade>)> {
+        let location = *self.locations.get(&order_id)?;
+        let order = self
+            .side_mut(location.side)
+            .get_mut(&location.price)?
+            .get_mut(&location.sequence)?;
+        if price == order.price && quantity <= order.quantity {
+            order.quantity = quantity;
+            return Some((order.clone(), Vec::new()));
+        }
+        let order = self.cancel(order_id)?;
+        let amended = RestingOrder {
+            price,
+            quantity,
+            ..order
+        };
+        let trades = self.match_order(order_id, &amended.as_transaction(), trade_ids, timestamp);
+        let amended = RestingOrder {
+            quantity: trades
+                .last()
+                .map_or(quantity, |trade| trade.aggressor.leaves),
+            ..amended
+        };
+        if amended.quantity > 0 {
+            self.insert(amended.clone());
+        }
+        Some((amended, trades))
+    }
+
+    fn cancel_where(&mut self, predicate: impl Fn(&RestingOrder) -> bool) -> Vec<RestingOrder> {
+        let matching: Vec<OrderId> = self
+            .buys
+            .values()
+            .chain(self.sells.values())
+            .flat_map(|level| level.values())
+            .filter(|order| predicate(order))
+            .map(|order| order.order_id)
+            .collect();
+        matching
+            .into_iter()
+            .filter_map(|order_id| self.cancel(order_id))
+            .collect()
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<Price, PriceLevel> {
+        match side {
+            Side::Buy => &self.buys,
+            Side::Sell => &self.sells,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<Price, PriceLevel> {
+        match side {
+            Side::Buy => &mut self.buys,
+            Side::Sell => &mut self.sells,
+        }
+    }
+
+    /// Resting orders of other traders that `transaction` crosses, best first.
+    fn crossing_orders<'a>(
+        &'a self,
+        transaction: &'a Transaction,
+    ) -> impl Iterator<Item = &'a RestingOrder> + 'a {
+        self.priority_levels(transaction.side.opposite())
+            .take_while(|(price, _)| transaction.side.crosses(transaction.price, **price))
+            .flat_map(|(_, level)| level.values())
+            .filter(|order| order.trader_id != transaction.trader_id)
+    }
+
+    /// Price levels of one side, best price first.
+    fn priority_levels(&self, side: Side) -> Box<dyn Iterator<Item = (&Price, &PriceLevel)> + '_> {
+        match side {
+            Side::Buy => Box::new(self.buys.iter().rev()),
+            Side::Sell => Box::new(self.sells.iter()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn price(price: &str) -> Price {
+        Price::from_str(price).unwrap()
+    }
+
+    fn resting(order_id: OrderId, trader_id: &str, side: Side, limit: &str) -> RestingOrder {
+        RestingOrder {
+            order_id,
+            trader_id: trader_id.to_string(),
+            side,
+            product: Product::ONION,
+            price: price(limit),
+            quantity: 1,
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    fn buy(trader_id: &str, limit: &str) -> Transaction {
+        Transaction {
+            trader_id: trader_id.to_string(),
+            side: Side::Buy,
+            product: Product::ONION,
+            price: Some(price(limit)),
+            quantity: 1,
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    #[test]
+    fn test_inserting_to_order_book() {
+        let mut book = OrderBook::default();
+        let order = resting(1, "42", Side::Sell, "1");
+        book.insert(order.clone());
+        assert_eq!(book.locations.len(), 1);
+        assert_eq!(book.get(1), Some(&order));
+        assert_eq!(book.orders(Side::Sell), vec![order]);
+        assert!(book.orders(Side::Buy).is_empty());
+    }
+
+    #[test]
+    fn test_cancelling() {
+        let mut book = OrderBook::default();
+        let order = resting(1, "42", Side::Sell, "1");
+        book.insert(order.clone());
+        assert_eq!(book.cancel(1), Some(order));
+        assert!(book.locations.is_empty());
+        assert!(book.get(1).is_none());
+        assert!(book.sells.is_empty());
+    }
+
+    #[test]
+    fn test_cancelling_unknown_order() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        assert!(book.cancel(2).is_none());
+        assert_eq!(book.locations.len(), 1);
+    }
+
+    #[test]
+    fn test_cancelling_keeps_level_queue() {
+        let mut book = OrderBook::default();
+        for order_id in 1..=3 {
+            book.insert(resting(order_id, "42", Side::Buy, "1"));
+        }
+        book.cancel(2);
+        let order_ids: Vec<OrderId> = book
+            .orders(Side::Buy)
+            .iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(order_ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_filling_partially() {
+        let mut book = OrderBook::default();
+        book.insert(RestingOrder {
+            quantity: 10,
+            ..resting(1, "42", Side::Sell, "1")
+        });
+        book.fill(1, 4);
+        assert_eq!(book.locations.len(), 1);
+        assert_eq!(book.get(1).map(|order| order.quantity), Some(6));
+    }
+
+    #[test]
+    fn test_filling_completely() {
+        let mut book = OrderBook::default();
+        book.insert(RestingOrder {
+            quantity: 10,
+            ..resting(1, "42", Side::Sell, "1")
+        });
+        book.fill(1, 10);
+        assert!(book.locations.is_empty());
+    }
+
+    #[test]
+    fn test_try_find_some() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        match book.try_find(&buy("40", "1")) {
+            Some(order) => {
+                assert_eq!(order.order_id, 1);
+                assert_eq!(order.price, price("1"));
+            }
+            None => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_try_find_first() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        book.insert(resting(2, "43", Side::Sell, "1"));
+        assert_eq!(
+            book.try_find(&buy("40", "1")).map(|order| order.order_id),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_try_find_best_price() {
+        let mut book = OrderBook::default();
+        for (order_id, limit) in [(1, "1.5"), (2, "1.2"), (3, "1.2"), (4, "1.1")] {
+            book.insert(resting(order_id, "42", Side::Sell, limit));
+        }
+        assert_eq!(
+            book.try_find(&buy("40", "1.3")).map(|order| order.order_id),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn test_try_find_best_price_for_seller() {
+        let mut book = OrderBook::default();
+        for (order_id, limit) in [(1, "1.1"), (2, "1.3"), (3, "1.3"), (4, "1.5")] {
+            book.insert(resting(order_id, "42", Side::Buy, limit));
+        }
+        let sell = Transaction {
+            side: Side::Sell,
+            ..buy("40", "1.2")
+        };
+        assert_eq!(book.try_find(&sell).map(|order| order.order_id), Some(4));
+    }
+
+    #[test]
+    fn test_try_find_time_priority_within_price() {
+        let mut book = OrderBook::default();
+        for (order_id, limit) in [(1, "1.3"), (2, "1.2"), (3, "1.2")] {
+            book.insert(resting(order_id, "42", Side::Sell, limit));
+        }
+        assert_eq!(
+            book.try_find(&buy("40", "1.3")).map(|order| order.order_id),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_try_find_none_when_not_crossing() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1.5"));
+        assert!(book.try_find(&buy("40", "1.25")).is_none())
+    }
+
+    #[test]
+    fn test_try_find_some_with_same_trader() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        assert!(book.try_find(&buy("42", "1")).is_none())
+    }
+
+    #[test]
+    fn test_try_find_skips_same_trader() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        book.insert(resting(2, "43", Side::Sell, "1.1"));
+        assert_eq!(
+            book.try_find(&buy("42", "1.2")).map(|order| order.order_id),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_try_find_none() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "40", Side::Buy, "1"));
+        assert!(book.try_find(&buy("42", "1")).is_none())
+    }
+
+    #[test]
+    fn test_match_order_sweeps_levels() {
+        let mut book = OrderBook::default();
+        for (order_id, limit, quantity) in [(1, "1.3", 5), (2, "1.1", 3), (3, "1.2", 4)] {
+            book.insert(RestingOrder {
+                quantity,
+                ..resting(order_id, "42", Side::Sell, limit)
+            });
+        }
+        let transaction = Transaction {
+            quantity: 10,
+            ..buy("40", "1.3")
+        };
+        let trades = book.match_order(9, &transaction, &IdGenerator::default(), 0);
+        let fills: Vec<(Price, Quantity, Quantity)> = trades
+            .iter()
+            .map(|trade| (trade.price, trade.quantity, trade.aggressor.leaves))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (price("1.1"), 3, 7),
+                (price("1.2"), 4, 3),
+                (price("1.3"), 3, 0)
+            ]
+        );
+        assert_eq!(book.locations.len(), 1);
+        assert_eq!(book.get(1).map(|order| order.quantity), Some(2));
+    }
+
+    #[test]
+    fn test_match_order_reports_both_sides() {
+        let mut book = OrderBook::default();
+        book.insert(RestingOrder {
+            quantity: 5,
+            ..resting(1, "42", Side::Sell, "1.1")
+        });
+        let transaction = Transaction {
+            quantity: 3,
+            ..buy("40", "1.3")
+        };
+        let trade_ids = IdGenerator::default();
+        trade_ids.next();
+        let trades = book.match_order(9, &transaction, &trade_ids, 1_709_164_800_000);
+        assert_eq!(
+            trades,
+            vec![Trade {
+                trade_id: 2,
+                product: Product::ONION,
+                price: price("1.1"),
+                quantity: 3,
+                aggressor: Execution {
+                    order_id: 9,
+                    trader_id: "40".to_string(),
+                    side: Side::Buy,
+                    leaves: 0,
+                },
+                resting: Execution {
+                    order_id: 1,
+                    trader_id: "42".to_string(),
+                    side: Side::Sell,
+                    leaves: 2,
+                },
+                timestamp: 1_709_164_800_000,
+            }]
+        );
+    }
+
+    fn queue(book: &OrderBook, side: Side) -> Vec<(OrderId, Quantity)> {
+        book.orders(side)
+            .iter()
+            .map(|order| (order.order_id, order.quantity))
+            .collect()
+    }
+
+    #[test]
+    fn test_amend_lower_quantity_keeps_priority() {
+        let mut book = OrderBook::default();
+        for order_id in 1..=2 {
+            book.insert(RestingOrder {
+                quantity: 10,
+                ..resting(order_id, "42", Side::Buy, "1")
+            });
+        }
+        let (order, trades) = book
+            .amend(1, price("1"), 4, &IdGenerator::default(), 0)
+            .unwrap();
+        assert_eq!(order.quantity, 4);
+        assert!(trades.is_empty());
+        assert_eq!(queue(&book, Side::Buy), vec![(1, 4), (2, 10)]);
+    }
+
+    #[test]
+    fn test_amend_higher_quantity_loses_priority() {
+        let mut book = OrderBook::default();
+        for order_id in 1..=2 {
+            book.insert(RestingOrder {
+                quantity: 10,
+                ..resting(order_id, "42", Side::Buy, "1")
+            });
+        }
+        book.amend(1, price("1"), 12, &IdGenerator::default(), 0)
+            .unwrap();
+        assert_eq!(queue(&book, Side::Buy), vec![(2, 10), (1, 12)]);
+    }
+
+    #[test]
+    fn test_amend_price_loses_priority() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Buy, "1"));
+        book.insert(resting(2, "42", Side::Buy, "1.1"));
+        book.amend(1, price("1.1"), 1, &IdGenerator::default(), 0)
+            .unwrap();
+        assert_eq!(queue(&book, Side::Buy), vec![(2, 1), (1, 1)]);
+        assert_eq!(book.get(1).map(|order| order.price), Some(price("1.1")));
+        assert!(!book.buys.contains_key(&price("1")));
+    }
+
+    #[test]
+    fn test_amend_price_crossing_the_book() {
+        let mut book = OrderBook::default();
+        book.insert(RestingOrder {
+            quantity: 3,
+            ..resting(1, "42", Side::Sell, "1.2")
+        });
+        book.insert(RestingOrder {
+            quantity: 5,
+            ..resting(2, "40", Side::Buy, "1")
+        });
+        let (order, trades) = book
+            .amend(2, price("1.2"), 5, &IdGenerator::default(), 0)
+            .unwrap();
+        assert_eq!(order.quantity, 2);
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].price, trades[0].quantity), (price("1.2"), 3));
+        assert!(book.orders(Side::Sell).is_empty());
+        assert_eq!(queue(&book, Side::Buy), vec![(2, 2)]);
+    }
+
+    #[test]
+    fn test_amend_unknown_order() {
+        let mut book = OrderBook::default();
+        assert!(book
+            .amend(1, price("1"), 1, &IdGenerator::default(), 0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_match_market_order() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        book.insert(resting(2, "42", Side::Sell, "100"));
+        let transaction = Transaction {
+            price: None,
+            quantity: 3,
+            time_in_force: TimeInForce::Ioc,
+            ..buy("40", "1")
+        };
+        let trades = book.match_order(9, &transaction, &IdGenerator::default(), 0);
+        let fills: Vec<(Price, Quantity)> = trades
+            .iter()
+            .map(|trade| (trade.price, trade.quantity))
+            .collect();
+        assert_eq!(fills, vec![(price("1"), 1), (price("100"), 1)]);
+        assert_eq!(trades.last().map(|trade| trade.aggressor.leaves), Some(1));
+    }
+
+    #[test]
+    fn test_available() {
+        let mut book = OrderBook::default();
+        for (order_id, trader_id, limit) in [
+            (1, "42", "1"),
+            (2, "40", "1"),
+            (3, "43", "1.1"),
+            (4, "43", "2"),
+        ] {
+            book.insert(RestingOrder {
+                quantity: 5,
+                ..resting(order_id, trader_id, Side::Sell, limit)
+            });
+        }
+        let transaction = Transaction {
+            quantity: 20,
+            ..buy("40", "1.5")
+        };
+        assert_eq!(book.available(&transaction), 10);
+        let transaction = Transaction {
+            quantity: 7,
+            ..transaction
+        };
+        assert_eq!(book.available(&transaction), 7);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut book = OrderBook::default();
+        for (order_id, limit) in [(1, "1"), (2, "1.1"), (3, "1")] {
+            book.insert(resting(order_id, "42", Side::Buy, limit));
+        }
+        assert_eq!(
+            book.depth(Side::Buy),
+            vec![
+                Level {
+                    price: price("1.1"),
+                    quantity: 1,
+                },
+                Level {
+                    price: price("1"),
+                    quantity: 2,
+                },
+            ]
+        );
+        assert!(book.depth(Side::Sell).is_empty());
+    }
+
+    #[test]
+    fn test_expire_day_orders() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        book.insert(RestingOrder {
+            time_in_force: TimeInForce::Day,
+            ..resting(2, "42", Side::Sell, "1")
+        });
+        book.insert(RestingOrder {
+            time_in_force: TimeInForce::Day,
+            ..resting(3, "42", Side::Buy, "0.5")
+        });
+        let expired: Vec<OrderId> = book
+            .expire_day_orders()
+            .iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(expired.len(), 2);
+        assert!(expired.contains(&2) && expired.contains(&3));
+        assert_eq!(queue(&book, Side::Sell), vec![(1, 1)]);
+        assert!(book.orders(Side::Buy).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_all() {
+        let mut book = OrderBook::default();
+        book.insert(resting(1, "42", Side::Sell, "1"));
+        book.insert(resting(2, "40", Side::Sell, "1"));
+        book.insert(resting(3, "42", Side::Buy, "0.5"));
+        let cancelled: Vec<OrderId> = book
+            .cancel_all("42")
+            .iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(cancelled, vec![3, 1]);
+        assert!(book.orders(Side::Buy).is_empty());
+        assert_eq!(queue(&book, Side::Sell), vec![(2, 1)]);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::consts::PRICE_DECIMALS;
+use crate::errors::ClientError;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Fixed-point price expressed in units of `10^-PRICE_DECIMALS`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(u64);
+
+impl Price {
+    /// Price from its fixed-point units; zero is not a valid price.
+    pub fn from_units(units: u64) -> Result<Price, ClientError> {
+        match units {
+            0 => Err(ClientError::InvalidPrice),
+            units => Ok(Price(units)),
+        }
+    }
+
+    pub fn units(&self) -> u64 {
+        self.0
+    }
+
+    fn scale() -> u64 {
+        10u64.pow(PRICE_DECIMALS)
+    }
+}
+
+impl FromStr for Price {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (whole, fraction) = match s.find('.') {
+            Some(index) => (&s[..index], &s[index + 1..]),
+            None => (s, ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty()
+            || !is_digits(whole)
+            || !is_digits(fraction)
+            || fraction.len() > PRICE_DECIMALS as usize
+        {
+            return Err(ClientError::InvalidPrice);
+        }
+        let whole: u64 = whole.parse().map_err(|_| ClientError::InvalidPrice)?;
+        let fraction: u64 = format!("{:0<width$}", fraction, width = PRICE_DECIMALS as usize)
+            .parse()
+            .map_err(|_| ClientError::InvalidPrice)?;
+        let units = whole
+            .checked_mul(Self::scale())
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or(ClientError::InvalidPrice)?;
+        if units == 0 {
+            return Err(ClientError::InvalidPrice);
+        }
+        Ok(Price(units))
+    }
+}
+
+/// As a decimal string, like the JSON lines protocol.
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let price = String::deserialize(deserializer)?;
+        Price::from_str(&price).map_err(D::Error::custom)
+    }
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let whole = self.0 / Self::scale();
+        let fraction = self.0 % Self::scale();
+        if fraction == 0 {
+            return write!(f, "{}", whole);
+        }
+        let fraction = format!("{:0>width$}", fraction, width = PRICE_DECIMALS as usize);
+        write!(f, "{}.{}", whole, fraction.trim_end_matches('0'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_from_str() {
+        let price = Price::from_str("1.25").unwrap();
+        assert_eq!(price, Price(12_500));
+        assert_eq!(price.to_string(), "1.25");
+    }
+
+    #[test]
+    fn test_price_from_str_whole() {
+        let price = Price::from_str("3").unwrap();
+        assert_eq!(price, Price(30_000));
+        assert_eq!(price.to_string(), "3");
+    }
+
+    #[test]
+    fn test_price_ordering() {
+        assert!(Price::from_str("1.2").unwrap() < Price::from_str("1.25").unwrap());
+    }
+
+    #[test]
+    fn test_price_invalid() {
+        for price in ["", "0", "0.0", "-1", "1.23456", "1,25", "abc", ".5"] {
+            assert!(matches!(
+                Price::from_str(price),
+                Err(ClientError::InvalidPrice)
+            ));
+        }
+    }
+}
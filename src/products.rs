@@ -1,39 +1,92 @@
 use crate::errors::ClientError;
-use std::fmt::{Display, Formatter};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum Product {
-    Apple,
-    Pear,
-    Tomato,
-    Potato,
-    Onion,
+pub const SYMBOL_SIZE: usize = 8;
+
+/// Symbol of an instrument: 1 to 8 uppercase letters or digits. Whether it is
+/// listed for trading is up to the instrument registry.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Product([u8; SYMBOL_SIZE]);
+
+#[cfg(test)]
+impl Product {
+    pub const APPLE: Product = Product::from_symbol("APPLE");
+    pub const PEAR: Product = Product::from_symbol("PEAR");
+    pub const ONION: Product = Product::from_symbol("ONION");
+
+    const fn from_symbol(symbol: &str) -> Product {
+        let mut bytes = [0; SYMBOL_SIZE];
+        let mut i = 0;
+        while i < symbol.len() {
+            bytes[i] = symbol.as_bytes()[i];
+            i += 1;
+        }
+        Product(bytes)
+    }
+}
+
+impl Product {
+    pub fn as_str(&self) -> &str {
+        let end = self.0.iter().position(|&byte| byte == 0);
+        // Only ASCII gets in, see `from_str`
+        std::str::from_utf8(&self.0[..end.unwrap_or(SYMBOL_SIZE)]).unwrap_or_default()
+    }
 }
 
 impl FromStr for Product {
     type Err = ClientError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s {
-            "APPLE" => Ok(Product::Apple),
-            "PEAR" => Ok(Product::Pear),
-            "TOMATO" => Ok(Product::Tomato),
-            "POTATO" => Ok(Product::Potato),
-            "ONION" => Ok(Product::Onion),
-            _ => Err(ClientError::UnknownProduct),
+        let valid = |byte: &u8| byte.is_ascii_uppercase() || byte.is_ascii_digit();
+        if s.is_empty() || s.len() > SYMBOL_SIZE || !s.as_bytes().iter().all(valid) {
+            return Err(ClientError::UnknownProduct);
         }
+        let mut symbol = [0; SYMBOL_SIZE];
+        symbol[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Product(symbol))
     }
 }
 
 impl Display for Product {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match &self {
-            Product::Apple => write!(f, "APPLE"),
-            Product::Pear => write!(f, "PEAR"),
-            Product::Tomato => write!(f, "TOMATO"),
-            Product::Potato => write!(f, "POTATO"),
-            Product::Onion => write!(f, "ONION"),
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Product {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Product {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let symbol = String::deserialize(deserializer)?;
+        Product::from_str(&symbol).map_err(D::Error::custom)
+    }
+}
+
+impl Debug for Product {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Product({})", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol() {
+        let product = Product::from_str("KIWI2").unwrap();
+        assert_eq!(product.to_string(), "KIWI2");
+        assert_eq!(Product::from_str("APPLE").unwrap(), Product::APPLE);
+        assert!(Product::APPLE < Product::ONION);
+        for symbol in ["", "kiwi", "APPLE PIE", "GRAPEFRUIT"] {
+            assert_eq!(Product::from_str(symbol), Err(ClientError::UnknownProduct));
         }
     }
 }
@@ -0,0 +1,277 @@
+use crate::accounts::Credentials;
+use crate::consts::HEARTBEAT_INTERVAL_SECS;
+use crate::errors::{ClientError, Error};
+use crate::instruments::Instruments;
+use crate::json;
+use crate::messages::Envelope;
+use crate::order_book::OrderId;
+use crate::trader::ClientRequest;
+use bytes::BytesMut;
+use futures::SinkExt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, Encoder, Framed, LinesCodec};
+
+/// Wire format of a text connection. Traders pick it with their login message:
+/// a JSON object switches the connection to JSON lines.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// Colon separated text, e.g. `BUY:APPLE:1.25:10`.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl Protocol {
+    pub fn detect(line: &str) -> Protocol {
+        if line.trim_start().starts_with('{') {
+            Protocol::Json
+        } else {
+            Protocol::Text
+        }
+    }
+
+    pub fn decode_login(&self, line: &str) -> Result<Credentials, ClientError> {
+        match self {
+            Protocol::Text => Credentials::new_from(line),
+            Protocol::Json => json::decode_login(line),
+        }
+    }
+
+    pub fn decode_request(
+        &self,
+        trader_id: &str,
+        line: &str,
+        instruments: &Instruments,
+    ) -> Result<ClientRequest, ClientError> {
+        match self {
+            Protocol::Text => ClientRequest::new_from(trader_id, line.to_string(), instruments),
+            Protocol::Json => json::decode_request(trader_id, line, instruments),
+        }
+    }
+
+    pub fn encode(&self, envelope: &Envelope) -> String {
+        match self {
+            Protocol::Text => envelope.to_string(),
+            Protocol::Json => json::encode(envelope),
+        }
+    }
+}
+
+/// What the connection does with a frame once the trader is logged in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// An order entry request, for `decode_request`.
+    Request,
+    /// Dealt with by the codec itself.
+    Handled,
+    /// The connection closes.
+    Logout,
+}
+
+/// Framing plus message format of an order entry connection, so every listener
+/// can share the same session handling.
+pub trait OrderEntryCodec: Decoder<Error = Error> + Encoder<Envelope, Error = Error> {
+    fn decode_login(&mut self, frame: &Self::Item) -> Result<Credentials, ClientError>;
+
+    fn decode_request(
+        &mut self,
+        trader_id: &str,
+        frame: &Self::Item,
+    ) -> Result<ClientRequest, ClientError>;
+
+    /// Offending input echoed back in rejects.
+    fn echo(frame: &Self::Item) -> Option<String>;
+
+    /// The new order last decoded was accepted as `order_id`.
+    fn placed(&mut self, _order_id: OrderId) {}
+
+    /// The trader's session on this connection is over.
+    fn closed(&mut self) {}
+
+    /// Session level frames the codec answers on its own, like FIX heartbeats.
+    /// Replies are written to `dst`.
+    fn control(&mut self, _frame: &Self::Item, _dst: &mut BytesMut) -> Result<Control, Error> {
+        Ok(Control::Request)
+    }
+
+    /// How long a trader can stay quiet before it is sent a heartbeat,
+    /// `None` for no heartbeats.
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(HEARTBEAT_INTERVAL_SECS))
+    }
+}
+
+/// Frame type of a connection's codec.
+pub type Frame<T> = <<T as Connection>::Codec as Decoder>::Item;
+
+/// A trader's connection, whatever the transport: frames of its codec come in,
+/// envelopes go out.
+pub trait Connection: Send {
+    type Codec: OrderEntryCodec;
+
+    fn codec_mut(&mut self) -> &mut Self::Codec;
+
+    /// `None` once the trader has disconnected. Cancel safe.
+    fn next_frame(&mut self) -> impl Future<Output = Option<Result<Frame<Self>, Error>>> + Send;
+
+    fn send(&mut self, envelope: Envelope) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Sends frames the codec already encoded, like answers to session messages.
+    fn send_encoded(&mut self, frames: BytesMut) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// Codec framing over a byte stream, e.g. TCP.
+impl<S, C> Connection for Framed<S, C>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    C: OrderEntryCodec + Send,
+    C::Item: Send,
+{
+    type Codec = C;
+
+    fn codec_mut(&mut self) -> &mut C {
+        Framed::codec_mut(self)
+    }
+
+    async fn next_frame(&mut self) -> Option<Result<C::Item, Error>> {
+        self.next().await
+    }
+
+    async fn send(&mut self, envelope: Envelope) -> Result<(), Error> {
+        SinkExt::send(self, envelope).await
+    }
+
+    async fn send_encoded(&mut self, frames: BytesMut) -> Result<(), Error> {
+        if frames.is_empty() {
+            return Ok(());
+        }
+        self.write_buffer_mut().unsplit(frames);
+        SinkExt::<Envelope>::flush(self).await
+    }
+}
+
+/// Newline delimited text or JSON, whichever the login used.
+#[derive(Debug, Default)]
+pub struct TextCodec {
+    lines: LinesCodec,
+    protocol: Protocol,
+    /// Products orders can be entered for.
+    instruments: Arc<Instruments>,
+}
+
+impl TextCodec {
+    pub fn new(instruments: Arc<Instruments>) -> Self {
+        Self {
+            instruments,
+            ..Default::default()
+        }
+    }
+}
+
+impl Decoder for TextCodec {
+    type Item = String;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<String>, Error> {
+        Ok(self.lines.decode(src)?)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<String>, Error> {
+        Ok(self.lines.decode_eof(src)?)
+    }
+}
+
+impl Encoder<Envelope> for TextCodec {
+    type Error = Error;
+
+    fn encode(&mut self, envelope: Envelope, dst: &mut BytesMut) -> Result<(), Error> {
+        let line = self.protocol.encode(&envelope);
+        Ok(self.lines.encode(line, dst)?)
+    }
+}
+
+impl OrderEntryCodec for TextCodec {
+    fn decode_login(&mut self, line: &String) -> Result<Credentials, ClientError> {
+        self.protocol = Protocol::detect(line);
+        self.protocol.decode_login(line)
+    }
+
+    fn decode_request(
+        &mut self,
+        trader_id: &str,
+        line: &String,
+    ) -> Result<ClientRequest, ClientError> {
+        self.protocol
+            .decode_request(trader_id, line, &self.instruments)
+    }
+
+    fn echo(line: &String) -> Option<String> {
+        Some(line.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::Instrument;
+    use crate::products::Product;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Protocol::detect("LOGIN:alice:wonderland"), Protocol::Text);
+        assert_eq!(
+            Protocol::detect(r#" {"type":"login","name":"alice","secret":"wonderland"}"#),
+            Protocol::Json
+        );
+    }
+
+    #[test]
+    fn test_both_protocols_decode_the_same_request() {
+        let instruments = Instruments::default();
+        let text = Protocol::Text.decode_request("alice", "buy:apple:1.25:10:ioc", &instruments);
+        let json = Protocol::Json.decode_request(
+            "alice",
+            r#"{"type":"order","side":"BUY","product":"APPLE","price":"1.25","quantity":10,"time_in_force":"IOC"}"#,
+            &instruments,
+        );
+        assert!(text.is_ok());
+        assert_eq!(text, json);
+    }
+
+    #[test]
+    fn test_text_codec_checks_the_registry_it_is_given() {
+        let instruments = Arc::new(Instruments::default());
+        let mut codec = TextCodec::new(Arc::clone(&instruments));
+        let order = "buy:kiwi:1.25".to_string();
+        assert_eq!(
+            codec.decode_request("alice", &order),
+            Err(ClientError::UnknownProduct)
+        );
+        let kiwi = Product::from_str("KIWI").unwrap();
+        instruments.list(Instrument::new(kiwi));
+        assert!(codec.decode_request("alice", &order).is_ok());
+    }
+
+    #[test]
+    fn test_text_codec_answers_in_the_login_protocol() {
+        let mut codec = TextCodec::default();
+        let login = r#"{"type":"login","name":"alice","secret":"wonderland"}"#.to_string();
+        assert!(codec.decode_login(&login).is_ok());
+        let mut buffer = BytesMut::new();
+        let envelope = Envelope {
+            sequence: Some(1),
+            message: crate::messages::ServerMessage::Canceled { order_id: 7 },
+        };
+        codec.encode(envelope, &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..],
+            b"{\"seq\":1,\"type\":\"canceled\",\"order_id\":7}\n"
+        );
+    }
+}
@@ -0,0 +1,599 @@
+use crate::accounts::{AccountId, Credentials};
+use crate::actions::Side;
+use crate::errors::{ClientError, Error};
+use crate::instruments::{Instrument, InstrumentSpec};
+use crate::json;
+use crate::order_book::{Level, RestingOrder, Trade};
+use crate::price::Price;
+use crate::products::Product;
+use crate::tls::{Stream, Tls};
+use crate::trader::parse_order_id;
+use crate::transaction_service::{Placement, TransactionService};
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::serve::Listener;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+type Service = Arc<TransactionService>;
+
+/// HTTP endpoints for scripts, on the same books as the trading sessions.
+/// Requests acting for an account authenticate with HTTP Basic credentials.
+pub fn router(transaction_service: Service) -> Router {
+    Router::new()
+        .route("/orders", post(place_order))
+        .route("/orders/{order_id}", delete(cancel_order))
+        .route("/accounts/{account_id}/orders", get(open_orders))
+        .route("/books/{product}", get(book))
+        .route("/trades", get(recent_trades))
+        .route("/instruments", get(instruments))
+        .route(
+            "/instruments/{product}",
+            put(list_instrument).delete(delist_instrument),
+        )
+        .with_state(transaction_service)
+}
+
+/// Connections waiting to be served once past their handshake.
+const HANDSHAKEN_SIZE: usize = 64;
+
+/// Accepts HTTP connections, over TLS when configured. Client certificates are
+/// checked by the handshake, but requests still authenticate with their credentials.
+pub struct HttpListener {
+    listener: TcpListener,
+    tls: Option<Arc<Tls>>,
+    /// Connections past their TLS handshake, which runs in a task of its own.
+    handshaken: Receiver<(Stream, SocketAddr)>,
+    handshakes: Sender<(Stream, SocketAddr)>,
+}
+
+impl HttpListener {
+    pub fn new(listener: TcpListener, tls: Option<Arc<Tls>>) -> Self {
+        let (handshakes, handshaken) = channel(HANDSHAKEN_SIZE);
+        Self {
+            listener,
+            tls,
+            handshaken,
+            handshakes,
+        }
+    }
+}
+
+impl Listener for HttpListener {
+    type Io = Stream;
+    type Addr = SocketAddr;
+
+    /// A client that never finishes its handshake only holds up its own
+    /// connection, not the ones accepted after it.
+    async fn accept(&mut self) -> (Stream, SocketAddr) {
+        let Some(tls) = &self.tls else {
+            let (stream, address) = Listener::accept(&mut self.listener).await;
+            return (Stream::Plain(stream), address);
+        };
+        loop {
+            tokio::select! {
+                (stream, address) = Listener::accept(&mut self.listener) => {
+                    let (tls, handshakes) = (tls.clone(), self.handshakes.clone());
+                    tokio::spawn(async move {
+                        match tls.handshake(stream).await {
+                            Ok(stream) => {
+                                let _ = handshakes.send((stream, address)).await;
+                            }
+                            Err(e) => error!("Error occurred during HTTP TLS handshake! {}", e),
+                        }
+                    });
+                }
+                Some(handshaken) = self.handshaken.recv() => return handshaken,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// The account a request authenticated as.
+struct Account(AccountId);
+
+impl FromRequestParts<Service> for Account {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, service: &Service) -> Result<Self, ApiError> {
+        let credentials = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(basic_credentials)
+            .ok_or(ClientError::InvalidCredentials)?;
+        Ok(Account(service.authenticate(&credentials)?))
+    }
+}
+
+fn basic_credentials(authorization: &str) -> Option<Credentials> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (name, secret) = decoded.split_once(':')?;
+    Some(Credentials {
+        name: name.to_string(),
+        secret: secret.to_string(),
+        last_seen: None,
+    })
+}
+
+/// Answered with the same code and reason as a `REJECT`.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    reason: String,
+}
+
+impl From<ClientError> for ApiError {
+    fn from(error: ClientError) -> Self {
+        let status = match error {
+            ClientError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ClientError::UnknownOrder => StatusCode::NOT_FOUND,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self {
+            status,
+            code: error.code(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::ClientError(error) => error.into(),
+            error => {
+                error!("Error occurred while serving HTTP request! {}", error);
+                Self {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    code: "INTERNAL_ERROR",
+                    reason: error.to_string(),
+                }
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({ "code": self.code, "reason": self.reason }));
+        if self.status == StatusCode::UNAUTHORIZED {
+            let challenge = [(header::WWW_AUTHENTICATE, "Basic realm=\"trading\"")];
+            (self.status, challenge, body).into_response()
+        } else {
+            (self.status, body).into_response()
+        }
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+/// Takes the same order object as the JSON lines protocol, without `type`.
+async fn place_order(
+    State(service): State<Service>,
+    Account(account_id): Account,
+    body: String,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let transaction = json::decode_order(&account_id, &body, service.registry())?;
+    let product = transaction.product;
+    let placement = service.place_order(transaction).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(placement_json(product, &placement)),
+    ))
+}
+
+async fn cancel_order(
+    State(service): State<Service>,
+    Account(account_id): Account,
+    Path(order_id): Path<String>,
+) -> ApiResult {
+    let order_id = parse_order_id(&order_id)?;
+    let order = service.cancel_order(&account_id, order_id)?;
+    info!("cancel order ('{}', {})", account_id, order_id);
+    service.confirm_cancel(&account_id, order_id).await?;
+    Ok(Json(order_json(&order)))
+}
+
+/// Traders can only see their own orders.
+async fn open_orders(
+    State(service): State<Service>,
+    Account(account_id): Account,
+    Path(requested): Path<String>,
+) -> ApiResult {
+    if requested != account_id {
+        return Err(ApiError {
+            status: StatusCode::FORBIDDEN,
+            ..ClientError::InvalidCredentials.into()
+        });
+    }
+    let orders = service.open_orders(&account_id);
+    Ok(Json(orders.iter().map(order_json).collect()))
+}
+
+async fn book(State(service): State<Service>, Path(product): Path<String>) -> ApiResult {
+    let product = Product::from_str(&product.to_uppercase())?;
+    service.instrument(product)?;
+    let levels = |side| -> Vec<Value> {
+        service
+            .depth(product, side)
+            .iter()
+            .map(level_json)
+            .collect()
+    };
+    Ok(Json(json!({
+        "product": product.to_string(),
+        "bids": levels(Side::Buy),
+        "asks": levels(Side::Sell),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct TradesQuery {
+    product: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Newest first, optionally only those of one product.
+async fn recent_trades(
+    State(service): State<Service>,
+    Query(query): Query<TradesQuery>,
+) -> ApiResult {
+    let product = query
+        .product
+        .map(|product| Product::from_str(&product.to_uppercase()))
+        .transpose()?;
+    let trades = service
+        .recent_trades()
+        .into_iter()
+        .filter(|trade| product.is_none_or(|product| trade.product == product))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|trade| trade_json(&trade))
+        .collect();
+    Ok(Json(trades))
+}
+
+async fn instruments(State(service): State<Service>) -> ApiResult {
+    let instruments = service.instruments();
+    Ok(Json(instruments.iter().map(instrument_json).collect()))
+}
+
+/// Lists a product, or changes its rules; the body holds the rules, as in the
+/// config file.
+async fn list_instrument(
+    State(service): State<Service>,
+    Account(account_id): Account,
+    Path(product): Path<String>,
+    body: String,
+) -> ApiResult {
+    check_admin(&service, &account_id)?;
+    let product = Product::from_str(&product.to_uppercase())?;
+    let spec: InstrumentSpec =
+        serde_json::from_str(&body).map_err(|e| ClientError::InvalidJsonMessage(e.to_string()))?;
+    let instrument = spec.into_instrument(product).map_err(|reason| ApiError {
+        status: StatusCode::BAD_REQUEST,
+        code: "INVALID_INSTRUMENT",
+        reason,
+    })?;
+    service.list_instrument(instrument);
+    Ok(Json(instrument_json(&instrument)))
+}
+
+/// Resting orders of a delisted product are cancelled.
+async fn delist_instrument(
+    State(service): State<Service>,
+    Account(account_id): Account,
+    Path(product): Path<String>,
+) -> ApiResult {
+    check_admin(&service, &account_id)?;
+    let product = Product::from_str(&product.to_uppercase())?;
+    let (instrument, orders) = service.delist_instrument(product)?;
+    for order in &orders {
+        info!(
+            "cancel order ('{}', {}, delisted)",
+            order.trader_id, order.order_id
+        );
+        service
+            .confirm_cancel(&order.trader_id, order.order_id)
+            .await?;
+    }
+    let mut body = instrument_json(&instrument);
+    body["cancelled"] = orders.iter().map(|order| order.order_id).collect();
+    Ok(Json(body))
+}
+
+fn check_admin(service: &TransactionService, account_id: &str) -> Result<(), ApiError> {
+    match service.is_admin(account_id) {
+        true => Ok(()),
+        false => Err(ApiError {
+            status: StatusCode::FORBIDDEN,
+            ..ClientError::InvalidCredentials.into()
+        }),
+    }
+}
+
+fn placement_json(product: Product, placement: &Placement) -> Value {
+    let status = match placement {
+        Placement { leaves: 0, .. } => "filled",
+        Placement { resting: true, .. } => "resting",
+        Placement { .. } => "expired",
+    };
+    let fills: Vec<_> = placement
+        .trades
+        .iter()
+        .map(|trade| {
+            json!({
+                "trade_id": trade.trade_id,
+                "price": trade.price.to_string(),
+                "quantity": trade.quantity,
+            })
+        })
+        .collect();
+    json!({
+        "order_id": placement.order_id,
+        "product": product.to_string(),
+        "status": status,
+        "leaves": placement.leaves,
+        "fills": fills,
+    })
+}
+
+fn order_json(order: &RestingOrder) -> Value {
+    json!({
+        "order_id": order.order_id,
+        "side": order.side.to_string(),
+        "product": order.product.to_string(),
+        "price": order.price.to_string(),
+        "quantity": order.quantity,
+        "time_in_force": order.time_in_force.to_string(),
+    })
+}
+
+fn instrument_json(instrument: &Instrument) -> Value {
+    let price = |price: Option<Price>| price.map(|price| price.to_string());
+    json!({
+        "product": instrument.product.to_string(),
+        "tick_size": instrument.tick_size.to_string(),
+        "lot_size": instrument.lot_size,
+        "min_quantity": instrument.min_quantity,
+        "max_quantity": instrument.max_quantity,
+        "min_price": price(instrument.min_price),
+        "max_price": price(instrument.max_price),
+    })
+}
+
+fn level_json(level: &Level) -> Value {
+    json!({
+        "price": level.price.to_string(),
+        "quantity": level.quantity,
+    })
+}
+
+/// Trades are public, so the counterparties are left out.
+fn trade_json(trade: &Trade) -> Value {
+    json!({
+        "trade_id": trade.trade_id,
+        "product": trade.product.to_string(),
+        "price": trade.price.to_string(),
+        "quantity": trade.quantity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::config::Settings;
+    use crate::instruments::Instruments;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder").unwrap();
+        router(Arc::new(TransactionService::new(accounts)))
+    }
+
+    fn request(method: &str, uri: &str, account: Option<&str>, body: &str) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(account) = account {
+            let secret = match account {
+                "alice" => "wonderland",
+                _ => "builder",
+            };
+            let encoded = STANDARD.encode(format!("{}:{}", account, secret));
+            request = request.header(header::AUTHORIZATION, format!("Basic {}", encoded));
+        }
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    async fn call(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let credentials = basic_credentials("Basic YWxpY2U6d29uZGVybGFuZA==").unwrap();
+        assert_eq!(credentials.name, "alice");
+        assert_eq!(credentials.secret, "wonderland");
+        assert!(basic_credentials("Bearer YWxpY2U6d29uZGVybGFuZA==").is_none());
+        assert!(basic_credentials("Basic !!!").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_place_and_trade() {
+        let app = app();
+        let sell = r#"{"side":"SELL","product":"APPLE","price":"1.25","quantity":10,"time_in_force":"GTC"}"#;
+        let (status, body) = call(&app, request("POST", "/orders", Some("alice"), sell)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            body,
+            json!({"order_id": 1, "product": "APPLE", "status": "resting", "leaves": 10, "fills": []})
+        );
+
+        let buy = r#"{"side":"BUY","product":"APPLE","price":"1.3","quantity":4}"#;
+        let (status, body) = call(&app, request("POST", "/orders", Some("bob"), buy)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["status"], "filled");
+        assert_eq!(
+            body["fills"],
+            json!([{"trade_id": 1, "price": "1.25", "quantity": 4}])
+        );
+
+        let (_, body) = call(&app, request("GET", "/books/apple", None, "")).await;
+        assert_eq!(
+            body,
+            json!({"product": "APPLE", "bids": [], "asks": [{"price": "1.25", "quantity": 6}]})
+        );
+        let (_, body) = call(&app, request("GET", "/trades?limit=5", None, "")).await;
+        assert_eq!(
+            body,
+            json!([{"trade_id": 1, "product": "APPLE", "price": "1.25", "quantity": 4}])
+        );
+        let (_, body) = call(&app, request("GET", "/trades?product=PEAR", None, "")).await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_open_orders_and_cancel() {
+        let app = app();
+        let sell =
+            r#"{"side":"SELL","product":"PEAR","price":"2","quantity":3,"time_in_force":"DAY"}"#;
+        call(&app, request("POST", "/orders", Some("alice"), sell)).await;
+
+        let (status, body) = call(
+            &app,
+            request("GET", "/accounts/alice/orders", Some("alice"), ""),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([{"order_id": 1, "side": "SELL", "product": "PEAR", "price": "2", "quantity": 3, "time_in_force": "DAY"}])
+        );
+        let (status, _) = call(
+            &app,
+            request("GET", "/accounts/alice/orders", Some("bob"), ""),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = call(&app, request("DELETE", "/orders/1", Some("bob"), "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "UNKNOWN_ORDER");
+        let (status, body) = call(&app, request("DELETE", "/orders/1", Some("alice"), "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["order_id"], 1);
+        let (_, body) = call(
+            &app,
+            request("GET", "/accounts/alice/orders", Some("alice"), ""),
+        )
+        .await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_rejects() {
+        let app = app();
+        let order = r#"{"side":"BUY","product":"APPLE","price":"1"}"#;
+        let (status, body) = call(&app, request("POST", "/orders", None, order)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "INVALID_CREDENTIALS");
+
+        let order = r#"{"side":"BUY","product":"GME","price":"1"}"#;
+        let (status, body) = call(&app, request("POST", "/orders", Some("bob"), order)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "UNKNOWN_PRODUCT");
+
+        let (status, body) =
+            call(&app, request("POST", "/orders", Some("bob"), "buy:apple:1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_JSON");
+
+        let (_, body) = call(&app, request("DELETE", "/orders/first", Some("bob"), "")).await;
+        assert_eq!(body["code"], "INVALID_ORDER_ID");
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delist_instruments() {
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder").unwrap();
+        let settings = Settings {
+            admins: vec!["alice".to_string()],
+            ..Settings::default()
+        };
+        let service = TransactionService::with_settings(accounts, settings, Instruments::default());
+        let app = router(Arc::new(service));
+        let (_, body) = call(&app, request("GET", "/instruments", None, "")).await;
+        assert_eq!(body.as_array().unwrap().len(), 5);
+
+        let rules = r#"{"tick_size":"0.05","lot_size":10,"max_price":"5"}"#;
+        let (status, _) = call(
+            &app,
+            request("PUT", "/instruments/kiwi", Some("bob"), rules),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = call(
+            &app,
+            request("PUT", "/instruments/kiwi", Some("alice"), rules),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"product": "KIWI", "tick_size": "0.05", "lot_size": 10, "min_quantity": 10,
+                "max_quantity": null, "min_price": null, "max_price": "5"})
+        );
+        let invalid = r#"{"lot_size":0}"#;
+        let (status, body) = call(
+            &app,
+            request("PUT", "/instruments/kiwi", Some("alice"), invalid),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_INSTRUMENT");
+
+        let sell = r#"{"side":"SELL","product":"KIWI","price":"1.01","quantity":10}"#;
+        let (_, body) = call(&app, request("POST", "/orders", Some("bob"), sell)).await;
+        assert_eq!(body["code"], "INVALID_TICK");
+        let sell = r#"{"side":"SELL","product":"KIWI","price":"1.05","quantity":10}"#;
+        let (status, _) = call(&app, request("POST", "/orders", Some("bob"), sell)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = call(
+            &app,
+            request("DELETE", "/instruments/KIWI", Some("alice"), ""),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cancelled"], json!([1]));
+        let (_, body) = call(&app, request("GET", "/books/kiwi", None, "")).await;
+        assert_eq!(body["code"], "UNKNOWN_PRODUCT");
+        let (_, body) = call(&app, request("POST", "/orders", Some("bob"), sell)).await;
+        assert_eq!(body["code"], "UNKNOWN_PRODUCT");
+    }
+}
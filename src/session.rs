@@ -0,0 +1,172 @@
+use crate::consts::{JOURNAL_SIZE, SEQUENCE_BLOCK};
+use crate::errors::ClientError;
+use crate::messages::{Envelope, ServerMessage};
+use std::collections::VecDeque;
+use tokio::sync::mpsc::Sender;
+
+pub type Sequence = u64;
+
+/// Outbound side of an account, kept across its connections: every message
+/// gets the next sequence number and the latest ones are journaled for replay.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Connection the account is logged in from, if any.
+    pub sender: Option<Sender<Envelope>>,
+    /// Number of times the account has logged in, telling its connections apart.
+    pub logins: u64,
+    last_sequence: Sequence,
+    /// Numbering can go up to here before more has to be reserved.
+    reserved: Sequence,
+    journal: VecDeque<Envelope>,
+}
+
+impl Session {
+    /// Carries on numbering after `last_sequence`, e.g. after a restart; the
+    /// messages sent before are not available for replay.
+    pub fn resume(last_sequence: Sequence) -> Self {
+        Self {
+            last_sequence,
+            reserved: last_sequence,
+            ..Default::default()
+        }
+    }
+
+    /// Makes sure the next `count` numbers are reserved, reserving at least a
+    /// block past the last one when they are not. Returns the new reservation,
+    /// which has to be stored before any of its numbers are sent.
+    pub fn reserve(&mut self, count: u64) -> Option<Sequence> {
+        if self.last_sequence + count <= self.reserved {
+            return None;
+        }
+        self.reserved = self.last_sequence + count.max(SEQUENCE_BLOCK);
+        Some(self.reserved)
+    }
+
+    /// Where numbering carries on after a restart.
+    pub fn reserved(&self) -> Sequence {
+        self.reserved
+    }
+
+    /// Numbers and journals a message.
+    pub fn record(&mut self, message: ServerMessage) -> Envelope {
+        self.last_sequence += 1;
+        let envelope = Envelope {
+            sequence: Some(self.last_sequence),
+            message,
+        };
+        if self.journal.len() == JOURNAL_SIZE {
+            self.journal.pop_front();
+        }
+        self.journal.push_back(envelope.clone());
+        envelope
+    }
+
+    pub fn last_sequence(&self) -> Sequence {
+        self.last_sequence
+    }
+
+    /// Journaled messages sent after `last_seen`. When some of them have already
+    /// dropped out of the journal, an error telling which ones comes first.
+    pub fn replay(&self, last_seen: Sequence) -> Vec<Envelope> {
+        let mut messages = Vec::new();
+        let first_journaled = self
+            .journal
+            .front()
+            .and_then(|envelope| envelope.sequence)
+            .unwrap_or(self.last_sequence + 1);
+        if last_seen.saturating_add(1) < first_journaled {
+            let error = ClientError::ReplayUnavailable {
+                from: last_seen + 1,
+                to: first_journaled - 1,
+            };
+            messages.push(Envelope::unsequenced(ServerMessage::Reject {
+                error,
+                input: None,
+            }));
+        }
+        messages.extend(
+            self.journal
+                .iter()
+                .filter(|envelope| envelope.sequence > Some(last_seen))
+                .cloned(),
+        );
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(messages: u64) -> Session {
+        let mut session = Session::default();
+        for order_id in 1..=messages {
+            session.record(ServerMessage::Canceled { order_id });
+        }
+        session
+    }
+
+    fn text(envelopes: Vec<Envelope>) -> Vec<String> {
+        envelopes.iter().map(Envelope::to_string).collect()
+    }
+
+    #[test]
+    fn test_record() {
+        let mut session = Session::default();
+        let envelope = session.record(ServerMessage::Canceled { order_id: 7 });
+        assert_eq!(envelope.to_string(), "1:CANCELED:7");
+        let envelope = session.record(ServerMessage::Canceled { order_id: 8 });
+        assert_eq!(envelope.to_string(), "2:CANCELED:8");
+        assert_eq!(session.last_sequence(), 2);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut session = Session::default();
+        assert_eq!(session.reserve(1), Some(SEQUENCE_BLOCK));
+        assert_eq!(session.reserve(SEQUENCE_BLOCK), None);
+        session.record(ServerMessage::Canceled { order_id: 1 });
+        assert_eq!(session.reserve(SEQUENCE_BLOCK - 1), None);
+        assert_eq!(session.reserve(SEQUENCE_BLOCK), Some(SEQUENCE_BLOCK + 1));
+        assert_eq!(
+            session.reserve(3 * SEQUENCE_BLOCK),
+            Some(3 * SEQUENCE_BLOCK + 1)
+        );
+        // A restart carries on after the reservation
+        let resumed = Session::resume(session.reserved());
+        assert_eq!(resumed.last_sequence(), 3 * SEQUENCE_BLOCK + 1);
+    }
+
+    #[test]
+    fn test_replay_after_last_seen() {
+        let session = session(3);
+        assert_eq!(
+            text(session.replay(1)),
+            vec!["2:CANCELED:2", "3:CANCELED:3"]
+        );
+        assert_eq!(
+            text(session.replay(0)),
+            vec!["1:CANCELED:1", "2:CANCELED:2", "3:CANCELED:3"]
+        );
+        assert!(session.replay(3).is_empty());
+        assert!(session.replay(5).is_empty());
+        assert!(session.replay(Sequence::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_journal_is_bounded() {
+        let session = session(JOURNAL_SIZE as u64 + 2);
+        assert_eq!(session.journal.len(), JOURNAL_SIZE);
+        let replay = session.replay(0);
+        assert_eq!(
+            replay[0].message,
+            ServerMessage::Reject {
+                error: ClientError::ReplayUnavailable { from: 1, to: 2 },
+                input: None,
+            }
+        );
+        assert_eq!(replay[1].to_string(), "3:CANCELED:3");
+        assert_eq!(replay.len(), JOURNAL_SIZE + 1);
+        assert_eq!(session.replay(2).len(), JOURNAL_SIZE);
+    }
+}
@@ -1,183 +1,188 @@
-use crate::products::Product;
-use crate::Transaction;
-use std::sync::RwLock;
+use crate::errors::Error;
+use crate::journal::{Journal, Record, Snapshot};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::order_book::Trade;
+use std::io;
+use std::path::Path;
 
-type Position = usize;
-type ProductInfo = (Product, Position);
+/// Where the books and the trade history are kept. Changes are stored before
+/// they are acknowledged, and loaded back at startup.
+pub trait Storage: Send {
+    /// The latest snapshot and the records after it, as stored before the
+    /// service started; only called once, at startup.
+    fn load(&mut self) -> Result<(Option<Snapshot>, Vec<Record>), Error>;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub struct TransactionInfo {
-    trader_id: u16,
-    product: Product,
+    fn append(&mut self, record: &Record) -> io::Result<()>;
+
+    /// Adds trades to the trade history, skipping the ones it already has.
+    fn record_trades(&mut self, trades: &[Trade]) -> io::Result<()>;
+
+    /// Number of records stored so far.
+    fn offset(&self) -> u64;
+
+    /// Whether there are records the latest snapshot does not cover.
+    fn has_changes(&self) -> bool;
+
+    /// Stores `snapshot`, which covers all records so far, and drops what it
+    /// makes redundant.
+    fn snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()>;
 }
 
-impl From<Transaction> for TransactionInfo {
-    fn from(transaction: Transaction) -> Self {
-        Self {
-            trader_id: transaction.trader_id,
-            product: transaction.product,
-        }
-    }
+/// Keeps everything in memory: nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    snapshot: Option<Snapshot>,
+    /// Records after the snapshot.
+    records: Vec<Record>,
+    trades: Vec<LedgerEntry>,
 }
 
-#[derive(Default, Debug)]
-pub struct TransactionStorage {
-    pub data: RwLock<Vec<TransactionInfo>>,
+/// The journal and snapshots in a directory, and the trade ledger in a file.
+#[derive(Debug)]
+pub struct FileStorage {
+    journal: Journal,
+    ledger: Ledger,
+    /// What the journal had when it was opened, until it is loaded.
+    recovered: Option<(Option<Snapshot>, Vec<Record>)>,
 }
 
-impl TransactionStorage {
-    pub fn add(&self, transaction_info: TransactionInfo) {
-        self.data.write().unwrap().push(transaction_info);
+#[cfg(test)]
+impl MemoryStorage {
+    /// The trade history, oldest first.
+    pub fn trades(&self) -> &[LedgerEntry] {
+        &self.trades
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load(&mut self) -> Result<(Option<Snapshot>, Vec<Record>), Error> {
+        Ok((self.snapshot.clone(), self.records.clone()))
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        self.records.push(record.clone());
+        Ok(())
+    }
+
+    fn record_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        let last_trade_id = self.trades.last().map_or(0, |entry| entry.trade_id);
+        self.trades.extend(
+            trades
+                .iter()
+                .filter(|trade| trade.trade_id > last_trade_id)
+                .map(LedgerEntry::new),
+        );
+        Ok(())
     }
 
-    pub fn try_find(&self, item: Product, author_id: u16) -> Option<ProductInfo> {
-        let transaction_info = TransactionInfo {
-            trader_id: author_id,
-            product: item,
-        };
-        let data = self.data.read().unwrap();
-        data.iter()
-            .position(|tr_info| same_product_diff_traders(tr_info, &transaction_info))
-            .map(|position| {
-                let product = data[position].product;
-                (product, position)
-            })
+    fn offset(&self) -> u64 {
+        let snapshot_offset = self.snapshot.as_ref().map_or(0, |snapshot| snapshot.offset);
+        snapshot_offset + self.records.len() as u64
     }
 
-    pub fn remove_at(&self, position: usize) {
-        self.data.write().unwrap().remove(position);
+    fn has_changes(&self) -> bool {
+        !self.records.is_empty()
+    }
+
+    fn snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.snapshot = Some(snapshot.clone());
+        self.records.clear();
+        Ok(())
     }
 }
 
-fn same_product_diff_traders(
-    transaction_info_a: &TransactionInfo,
-    transaction_info_b: &TransactionInfo,
-) -> bool {
-    transaction_info_a.product == transaction_info_b.product
-        && transaction_info_a.trader_id != transaction_info_b.trader_id
+impl FileStorage {
+    /// Opens the journal in `journal_dir` and the ledger at `ledger`, creating
+    /// them when missing. With `sync`, every write is synced to disk.
+    pub fn open(journal_dir: &Path, ledger: &Path, sync: bool) -> Result<Self, Error> {
+        let (journal, snapshot, records) = Journal::open(journal_dir, sync)?;
+        Ok(Self {
+            journal,
+            ledger: Ledger::open(ledger, sync)?,
+            recovered: Some((snapshot, records)),
+        })
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Storage for FileStorage {
+    fn load(&mut self) -> Result<(Option<Snapshot>, Vec<Record>), Error> {
+        Ok(self.recovered.take().unwrap_or_default())
+    }
 
-    #[test]
-    fn test_adding_to_transaction_storage() {
-        let storage = TransactionStorage::default();
-        let transaction_info = TransactionInfo {
-            trader_id: 42,
-            product: Product::Apple,
-        };
-        storage.add(transaction_info);
-        let data = storage.data.read().unwrap();
-        assert_eq!(data.len(), 1);
-        assert!(data.contains(&transaction_info));
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        self.journal.append(record)
     }
 
-    #[test]
-    fn test_removing_at() {
-        let storage = TransactionStorage::default();
-        let transaction_info = TransactionInfo {
-            trader_id: 42,
-            product: Product::Apple,
-        };
-        storage.add(transaction_info);
-        storage.remove_at(0);
-        let data = storage.data.read().unwrap();
-        assert_eq!(data.len(), 0);
-        assert!(!data.contains(&transaction_info));
+    fn record_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        self.ledger.append(trades)
     }
 
-    #[test]
-    fn test_try_find_some() {
-        let product = Product::Onion;
-        let trader_id = 40;
-        let storage = TransactionStorage::default();
-        for transaction_info in [
-            TransactionInfo {
-                trader_id,
-                product: Product::Apple,
-            },
-            TransactionInfo {
-                trader_id: 42,
-                product,
-            },
-        ] {
-            storage.add(transaction_info)
-        }
-        match storage.try_find(product, trader_id) {
-            Some((matched_product, pos)) => {
-                assert_eq!(matched_product, product);
-                assert_eq!(pos, 1);
-            }
-            None => assert!(false),
-        }
+    fn offset(&self) -> u64 {
+        self.journal.offset()
     }
 
-    #[test]
-    fn test_try_find_first() {
-        let product = Product::Onion;
-        let trader_id = 40;
-        let storage = TransactionStorage::default();
-        for transaction_info in [
-            TransactionInfo {
-                trader_id,
-                product: Product::Apple,
-            },
-            TransactionInfo {
-                trader_id: 42,
-                product,
-            },
-            TransactionInfo {
-                trader_id: 43,
-                product,
-            },
-        ] {
-            storage.add(transaction_info)
-        }
-        match storage.try_find(product, trader_id) {
-            Some((matched_product, pos)) => {
-                assert_eq!(matched_product, product);
-                assert_eq!(pos, 1);
-            }
-            None => assert!(false),
+    fn has_changes(&self) -> bool {
+        self.journal.has_changes()
+    }
+
+    fn snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.journal.snapshot(snapshot)
+    }
+}
+
+/// In memory, as for tests.
+impl Default for Box<dyn Storage> {
+    fn default() -> Self {
+        Box::new(MemoryStorage::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::tests::TempDir;
+
+    fn snapshot(offset: u64) -> Snapshot {
+        Snapshot {
+            offset,
+            ..Default::default()
         }
     }
 
+    /// Both implementations keep the records after the latest snapshot.
+    fn check_records(storage: &mut dyn Storage) {
+        assert_eq!(storage.load().unwrap(), (None, Vec::new()));
+        assert!(!storage.has_changes());
+        storage.append(&Record::Cancel { order_id: 1 }).unwrap();
+        storage.snapshot(&snapshot(1)).unwrap();
+        assert!(!storage.has_changes());
+        storage.append(&Record::Cancel { order_id: 2 }).unwrap();
+        assert!(storage.has_changes());
+        assert_eq!(storage.offset(), 2);
+    }
+
     #[test]
-    fn test_try_find_some_with_same_trader() {
-        let product = Product::Onion;
-        let trader_id = 42;
-        let storage = TransactionStorage::default();
-        for transaction_info in [
-            TransactionInfo {
-                trader_id: 40,
-                product: Product::Apple,
-            },
-            TransactionInfo { trader_id, product },
-        ] {
-            storage.add(transaction_info)
-        }
-        assert!(storage.try_find(product, trader_id).is_none())
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::default();
+        check_records(&mut storage);
+        assert_eq!(
+            storage.load().unwrap(),
+            (Some(snapshot(1)), vec![Record::Cancel { order_id: 2 }])
+        );
     }
 
     #[test]
-    fn test_try_find_none() {
-        let product = Product::Pear;
-        let trader_id = 42;
-        let storage = TransactionStorage::default();
-        for transaction_info in [
-            TransactionInfo {
-                trader_id: 40,
-                product: Product::Apple,
-            },
-            TransactionInfo {
-                trader_id,
-                product: Product::Onion,
-            },
-        ] {
-            storage.add(transaction_info)
-        }
-        assert!(storage.try_find(product, trader_id).is_none())
+    fn test_file_storage() {
+        let dir = TempDir::new("file_storage");
+        let (journal, ledger) = (dir.0.join("journal"), dir.0.join("trades.jsonl"));
+        let mut storage = FileStorage::open(&journal, &ledger, false).unwrap();
+        check_records(&mut storage);
+        drop(storage);
+        let mut storage = FileStorage::open(&journal, &ledger, false).unwrap();
+        assert_eq!(
+            storage.load().unwrap(),
+            (Some(snapshot(1)), vec![Record::Cancel { order_id: 2 }])
+        );
+        assert_eq!(storage.load().unwrap(), (None, Vec::new()));
     }
 }
@@ -0,0 +1,48 @@
+use crate::errors::ClientError;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    /// Good till cancelled: the remainder rests until filled or cancelled.
+    Gtc,
+    /// Immediate or cancel: the remainder is cancelled right away.
+    Ioc,
+    /// Fill or kill: executes completely or not at all.
+    Fok,
+    /// The remainder rests until the end of the trading day.
+    Day,
+}
+
+impl TimeInForce {
+    pub fn can_rest(&self) -> bool {
+        matches!(self, TimeInForce::Gtc | TimeInForce::Day)
+    }
+}
+
+impl FromStr for TimeInForce {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "GTC" => Ok(TimeInForce::Gtc),
+            "IOC" => Ok(TimeInForce::Ioc),
+            "FOK" => Ok(TimeInForce::Fok),
+            "DAY" => Ok(TimeInForce::Day),
+            _ => Err(ClientError::UnknownTimeInForce),
+        }
+    }
+}
+
+impl Display for TimeInForce {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            TimeInForce::Gtc => write!(f, "GTC"),
+            TimeInForce::Ioc => write!(f, "IOC"),
+            TimeInForce::Fok => write!(f, "FOK"),
+            TimeInForce::Day => write!(f, "DAY"),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::accounts::AccountId;
+use crate::consts::TLS_HANDSHAKE_TIMEOUT_SECS;
+use crate::errors::Error;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// A trader's connection, in the clear or over TLS.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// Server side of TLS, optionally requiring client certificates.
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    clients: Option<ClientAccounts>,
+}
+
+/// Account of each client certificate, keyed by the certificate subject,
+/// e.g. `CN=alice, O=Fruit Traders`.
+#[derive(Debug, Default)]
+pub struct ClientAccounts(HashMap<String, AccountId>);
+
+impl Tls {
+    /// Loads the server certificate chain and key from PEM files. With a client CA,
+    /// clients must present a certificate it signed, mapped to an account in `clients_path`.
+    pub fn load(
+        cert_path: &str,
+        key_path: &str,
+        client_ca_path: Option<&str>,
+        clients_path: &str,
+    ) -> Result<Self, Error> {
+        let client_auth = match client_ca_path {
+            Some(client_ca_path) => Some((
+                read(client_ca_path)?,
+                ClientAccounts::from_str(&read(clients_path)?)?,
+            )),
+            None => None,
+        };
+        Self::from_pem(&read(cert_path)?, &read(key_path)?, client_auth)
+    }
+
+    pub fn from_pem(
+        cert_pem: &str,
+        key_pem: &str,
+        client_auth: Option<(String, ClientAccounts)>,
+    ) -> Result<Self, Error> {
+        let certs = CertificateDer::pem_slice_iter(cert_pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidTlsConfig(format!("invalid certificate: {}", e)))?;
+        let key = PrivateKeyDer::from_pem_slice(key_pem.as_bytes())
+            .map_err(|e| Error::InvalidTlsConfig(format!("invalid key: {}", e)))?;
+        let builder = ServerConfig::builder();
+        let (builder, clients) = match client_auth {
+            Some((ca_pem, clients)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_slice_iter(ca_pem.as_bytes()) {
+                    let cert = cert.map_err(|e| {
+                        Error::InvalidTlsConfig(format!("invalid client CA: {}", e))
+                    })?;
+                    roots.add(cert)?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .map_err(|e| Error::InvalidTlsConfig(e.to_string()))?;
+                (builder.with_client_cert_verifier(verifier), Some(clients))
+            }
+            None => (builder.with_no_client_auth(), None),
+        };
+        let config = builder.with_single_cert(certs, key)?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            clients,
+        })
+    }
+
+    /// Runs the TLS handshake, giving up on clients too slow to finish it.
+    pub async fn handshake(&self, stream: TcpStream) -> Result<Stream, Error> {
+        let timeout = Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS);
+        match tokio::time::timeout(timeout, self.acceptor.accept(stream)).await {
+            Ok(stream) => Ok(Stream::Tls(Box::new(stream?))),
+            Err(_) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out").into())
+            }
+        }
+    }
+
+    /// The account authenticated by the client certificate, if client certificates
+    /// are required. Certificates of unknown subjects are refused.
+    pub fn client_account(&self, stream: &Stream) -> Result<Option<AccountId>, Error> {
+        let (Some(clients), Stream::Tls(stream)) = (&self.clients, stream) else {
+            return Ok(None);
+        };
+        let subject = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| x509_parser::parse_x509_certificate(cert).ok())
+            .map(|(_, cert)| cert.subject().to_string())
+            .unwrap_or_default();
+        match clients.0.get(&subject) {
+            Some(account_id) => Ok(Some(account_id.clone())),
+            None => Err(Error::UnknownClientCertificate(subject)),
+        }
+    }
+}
+
+fn read(path: &str) -> Result<String, Error> {
+    fs::read_to_string(path)
+        .map_err(|e| Error::InvalidTlsConfig(format!("cannot read '{}': {}", path, e)))
+}
+
+impl FromStr for ClientAccounts {
+    type Err = Error;
+
+    /// One `<Name>:<Subject>` per line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut clients = ClientAccounts::default();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, subject)) if !name.trim().is_empty() && !subject.trim().is_empty() => {
+                    clients
+                        .0
+                        .insert(subject.trim().to_string(), name.trim().to_string());
+                }
+                _ => {
+                    return Err(Error::InvalidTlsConfig(format!(
+                        "line {} of the clients file should be <Name>:<Subject>",
+                        number + 1
+                    )))
+                }
+            }
+        }
+        Ok(clients)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::protocol::TextCodec;
+    use crate::rest::{router, HttpListener};
+    use crate::transaction_service::TransactionService;
+    use axum::serve::Listener;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_rustls::client;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    /// A test CA, signing both the server and the client certificates.
+    struct Pki {
+        ca: Issuer<'static, KeyPair>,
+        ca_pem: String,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            let key = KeyPair::generate().unwrap();
+            let ca_pem = params.self_signed(&key).unwrap().pem();
+            Self {
+                ca: Issuer::new(params, key),
+                ca_pem,
+            }
+        }
+
+        /// Certificate and key, as PEM.
+        fn issue(&self, common_name: &str) -> (String, String) {
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name);
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+
+        fn server(&self, clients: Option<&str>) -> Tls {
+            let (cert, key) = self.issue("localhost");
+            let client_auth = clients.map(|clients| {
+                (
+                    self.ca_pem.clone(),
+                    ClientAccounts::from_str(clients).unwrap(),
+                )
+            });
+            Tls::from_pem(&cert, &key, client_auth).unwrap()
+        }
+
+        async fn connect(
+            &self,
+            address: SocketAddr,
+            client_cert: Option<(String, String)>,
+        ) -> BufReader<client::TlsStream<TcpStream>> {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(CertificateDer::from_pem_slice(self.ca_pem.as_bytes()).unwrap())
+                .unwrap();
+            let builder = ClientConfig::builder().with_root_certificates(roots);
+            let config = match client_cert {
+                Some((cert, key)) => builder
+                    .with_client_auth_cert(
+                        vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                        PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                    )
+                    .unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            let stream = TcpStream::connect(address).await.unwrap();
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let stream = TlsConnector::from(Arc::new(config))
+                .connect(server_name, stream)
+                .await
+                .unwrap();
+            BufReader::new(stream)
+        }
+    }
+
+    async fn start(tls: Tls) -> SocketAddr {
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder").unwrap();
+        let service = Arc::new(TransactionService::new(accounts));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(crate::listen(
+            listener,
+            service,
+            Some(Arc::new(tls)),
+            crate::framed(TextCodec::default),
+        ));
+        address
+    }
+
+    /// Sends a line and reads the answer; `None` once the server hung up.
+    async fn request<S>(trader: &mut BufReader<S>, line: &str) -> Option<String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        trader.write_all(line.as_bytes()).await.ok()?;
+        trader.flush().await.ok()?;
+        let mut answer = String::new();
+        match trader.read_line(&mut answer).await {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(answer.trim_end().to_string()),
+        }
+    }
+
+    #[test]
+    fn test_client_accounts_from_str() {
+        let clients =
+            ClientAccounts::from_str("# traders\nalice:CN=alice, O=Fruit Traders\n\nbob : CN=bob")
+                .unwrap();
+        assert_eq!(
+            clients.0.get("CN=alice, O=Fruit Traders"),
+            Some(&"alice".to_string())
+        );
+        assert_eq!(clients.0.get("CN=bob"), Some(&"bob".to_string()));
+        assert!(ClientAccounts::from_str("alice").is_err());
+        assert!(ClientAccounts::from_str("alice:").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_over_tls() {
+        let pki = Pki::new();
+        let address = start(pki.server(None)).await;
+        let mut alice = pki.connect(address, None).await;
+        let answer = request(&mut alice, "LOGIN:alice:wonderland\n").await;
+        assert_eq!(answer.as_deref(), Some("LOGGED_IN:alice:0"));
+        let answer = request(&mut alice, "sell:apple:1.25:10\n").await;
+        assert_eq!(answer.as_deref(), Some("1:ACK:APPLE:1"));
+    }
+
+    #[tokio::test]
+    async fn test_login_with_client_certificate() {
+        let pki = Pki::new();
+        let address = start(pki.server(Some("alice:CN=alice"))).await;
+        let mut alice = pki.connect(address, Some(pki.issue("alice"))).await;
+        let answer = request(&mut alice, "LOGIN:bob:builder\n").await.unwrap();
+        assert!(answer.starts_with("REJECT:INVALID_CREDENTIALS:"));
+        let answer = request(&mut alice, "LOGIN:alice:certified\n").await;
+        assert_eq!(answer.as_deref(), Some("LOGGED_IN:alice:0"));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake_does_not_block_http() {
+        let service = Arc::new(TransactionService::default());
+        let pki = Pki::new();
+        let listener = HttpListener::new(
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            Some(Arc::new(pki.server(None))),
+        );
+        let address = listener.local_addr().unwrap();
+        tokio::task::spawn(async move { axum::serve(listener, router(service)).await });
+        // Never sends a ClientHello
+        let _stalled = TcpStream::connect(address).await.unwrap();
+        let request = async {
+            let mut client = pki.connect(address, None).await;
+            request(
+                &mut client,
+                "GET /instruments HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .await
+        };
+        let answer = tokio::time::timeout(Duration::from_secs(2), request).await;
+        assert_eq!(answer.unwrap().as_deref(), Some("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_required() {
+        let pki = Pki::new();
+        let address = start(pki.server(Some("alice:CN=alice"))).await;
+        let mut anonymous = pki.connect(address, None).await;
+        assert_eq!(
+            request(&mut anonymous, "LOGIN:alice:wonderland\n").await,
+            None
+        );
+        let mut mallory = pki.connect(address, Some(pki.issue("mallory"))).await;
+        assert_eq!(
+            request(&mut mallory, "LOGIN:alice:wonderland\n").await,
+            None
+        );
+    }
+}
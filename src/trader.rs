@@ -1,48 +1,202 @@
-use crate::actions::ClientActions;
+use crate::accounts::AccountId;
+use crate::actions::{ClientActions, Side};
 use crate::errors::ClientError;
+use crate::instruments::Instruments;
+use crate::messages::Envelope;
+use crate::order_book::OrderId;
+use crate::price::Price;
 use crate::products::Product;
+use crate::time_in_force::TimeInForce;
 use crate::utils::split_at_colon;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc::Receiver;
-use tokio_util::codec::{Framed, LinesCodec};
 
-pub struct Trader {
-    pub trader_id: u16,
-    pub lines: Framed<TcpStream, LinesCodec>,
-    pub receiver_ch: Receiver<String>,
+pub struct Trader<T> {
+    pub trader_id: AccountId,
+    pub connection: T,
+    pub receiver_ch: Receiver<Envelope>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+pub type Quantity = u64;
+
+/// Price field value of market orders.
+const MARKET_PRICE: &str = "MKT";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
-    pub trader_id: u16,
-    pub action: ClientActions,
+    pub trader_id: AccountId,
+    pub side: Side,
     pub product: Product,
+    /// Limit price; `None` for market orders.
+    pub price: Option<Price>,
+    pub quantity: Quantity,
+    pub time_in_force: TimeInForce,
+}
+
+/// A single message received from a trader.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientRequest {
+    Order(Transaction),
+    Cancel(OrderId),
+    Amend {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    /// Keeps the session alive, nothing to do.
+    Heartbeat,
+    /// Starts the level-2 feed of a book.
+    Subscribe(Product),
+    Unsubscribe(Product),
+}
+
+impl ClientRequest {
+    pub fn new_from(
+        trader_id: &str,
+        message: String,
+        instruments: &Instruments,
+    ) -> Result<ClientRequest, ClientError> {
+        if ClientActions::from_str(&message.trim().to_uppercase()) == Ok(ClientActions::Heartbeat) {
+            return Ok(ClientRequest::Heartbeat);
+        }
+        let (action, details) =
+            split_at_colon(&message).ok_or(ClientError::InvalidTransactionMessage)?;
+        match ClientActions::from_str(&action.to_uppercase())? {
+            ClientActions::Buy => {
+                Transaction::new_from(trader_id, Side::Buy, &details, instruments)
+                    .map(ClientRequest::Order)
+            }
+            ClientActions::Sell => {
+                Transaction::new_from(trader_id, Side::Sell, &details, instruments)
+                    .map(ClientRequest::Order)
+            }
+            ClientActions::Cancel => parse_order_id(&details).map(ClientRequest::Cancel),
+            ClientActions::Amend => parse_amendment(&details),
+            ClientActions::Subscribe => parse_product(&details).map(ClientRequest::Subscribe),
+            ClientActions::Unsubscribe => parse_product(&details).map(ClientRequest::Unsubscribe),
+            ClientActions::Login => Err(ClientError::AlreadyLoggedIn),
+            ClientActions::Heartbeat => Err(ClientError::InvalidTransactionMessage),
+        }
+    }
 }
 
 impl Transaction {
-    pub fn new_from(trader_id: u16, message: String) -> Result<Transaction, ClientError> {
-        let (action, product) =
-            split_at_colon(&*message).ok_or(ClientError::InvalidTransactionMessage)?;
-        let action = ClientActions::from_str(&*action.to_uppercase())?;
-        let product = Product::from_str(&*product.to_uppercase())?;
+    /// Fills in the defaults for missing fields: a quantity of 1,
+    /// GTC for limit orders and IOC for market orders.
+    pub fn new(
+        trader_id: &str,
+        side: Side,
+        product: Product,
+        price: Option<Price>,
+        quantity: Option<Quantity>,
+        time_in_force: Option<TimeInForce>,
+    ) -> Result<Transaction, ClientError> {
+        let quantity = check_quantity(quantity.unwrap_or(1))?;
+        let time_in_force = match (time_in_force, price) {
+            (Some(time_in_force), _) => time_in_force,
+            (None, Some(_)) => TimeInForce::Gtc,
+            (None, None) => TimeInForce::Ioc,
+        };
+        if price.is_none() && time_in_force.can_rest() {
+            return Err(ClientError::MarketOrderCannotRest);
+        }
         Ok(Self {
-            trader_id,
-            action,
+            trader_id: trader_id.to_string(),
+            side,
             product,
+            price,
+            quantity,
+            time_in_force,
         })
     }
+
+    /// The product has to be listed in `instruments`.
+    pub fn new_from(
+        trader_id: &str,
+        side: Side,
+        order: &str,
+        instruments: &Instruments,
+    ) -> Result<Transaction, ClientError> {
+        let fields: Vec<&str> = order.split(':').map(str::trim).collect();
+        let (product, price, quantity, time_in_force) = match fields[..] {
+            [product, price] => (product, price, None, None),
+            [product, price, quantity] => (product, price, Some(quantity), None),
+            [product, price, quantity, time_in_force] => {
+                (product, price, Some(quantity), Some(time_in_force))
+            }
+            _ => return Err(ClientError::InvalidTransactionMessage),
+        };
+        let product = instruments.listed(product)?;
+        let price = parse_limit(price)?;
+        let quantity = quantity.map(parse_quantity).transpose()?;
+        let time_in_force = time_in_force
+            .map(|time_in_force| TimeInForce::from_str(&time_in_force.to_uppercase()))
+            .transpose()?;
+        Self::new(trader_id, side, product, price, quantity, time_in_force)
+    }
+}
+
+/// Limit price of an order, `None` for market orders.
+pub fn parse_limit(price: &str) -> Result<Option<Price>, ClientError> {
+    match price.to_uppercase().as_str() {
+        MARKET_PRICE => Ok(None),
+        price => Ok(Some(Price::from_str(price)?)),
+    }
+}
+
+fn parse_quantity(quantity: &str) -> Result<Quantity, ClientError> {
+    quantity
+        .parse::<Quantity>()
+        .map_err(|_| ClientError::InvalidQuantity)
+        .and_then(check_quantity)
+}
+
+pub fn check_quantity(quantity: Quantity) -> Result<Quantity, ClientError> {
+    match quantity {
+        0 => Err(ClientError::InvalidQuantity),
+        quantity => Ok(quantity),
+    }
+}
+
+pub fn parse_order_id(order_id: &str) -> Result<OrderId, ClientError> {
+    order_id
+        .parse::<OrderId>()
+        .map_err(|_| ClientError::InvalidOrderId)
+}
+
+fn parse_product(product: &str) -> Result<Product, ClientError> {
+    Product::from_str(&product.trim().to_uppercase())
+}
+
+fn parse_amendment(amendment: &str) -> Result<ClientRequest, ClientError> {
+    let fields: Vec<&str> = amendment.split(':').map(str::trim).collect();
+    match fields[..] {
+        [order_id, price, quantity] => Ok(ClientRequest::Amend {
+            order_id: parse_order_id(order_id)?,
+            price: Price::from_str(price)?,
+            quantity: parse_quantity(quantity)?,
+        }),
+        _ => Err(ClientError::InvalidTransactionMessage),
+    }
 }
 
 impl Display for Transaction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let price = match self.price {
+            Some(price) => price.to_string(),
+            None => MARKET_PRICE.to_string(),
+        };
         write!(
             f,
-            "new {} order ('{}', {})",
-            self.action.to_string().to_lowercase(),
+            "new {} order ('{}', {} {} @ {}, {})",
+            self.side.to_string().to_lowercase(),
             self.trader_id,
-            self.product
+            self.quantity,
+            self.product,
+            price,
+            self.time_in_force
         )
     }
 }
@@ -52,23 +206,102 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_transaction_from_str() {
-        let trader_id = 0;
-        let buy_order = "buy:onion".to_string();
+        let trader_id = "0";
+        let buy_order = "buy:onion:1.25".to_string();
         let expected_result = Transaction {
-            trader_id,
-            action: ClientActions::Buy,
-            product: Product::Onion,
+            trader_id: trader_id.to_string(),
+            side: Side::Buy,
+            product: Product::ONION,
+            price: Some(Price::from_str("1.25").unwrap()),
+            quantity: 1,
+            time_in_force: TimeInForce::Gtc,
         };
-        match Transaction::new_from(trader_id, buy_order) {
-            Ok(result) => assert_eq!(result, expected_result),
+        match ClientRequest::new_from(trader_id, buy_order, &Instruments::default()) {
+            Ok(result) => assert_eq!(result, ClientRequest::Order(expected_result)),
             Err(_) => assert!(false),
         }
     }
+    #[test]
+    fn test_transaction_with_quantity_from_str() {
+        let sell_order = "sell:apple:2:15".to_string();
+        match ClientRequest::new_from("0", sell_order, &Instruments::default()) {
+            Ok(ClientRequest::Order(result)) => {
+                assert_eq!(result.side, Side::Sell);
+                assert_eq!(result.price, Some(Price::from_str("2").unwrap()));
+                assert_eq!(result.quantity, 15);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_transaction_incorrect_quantity() {
+        for sell_order in ["sell:apple:2:0", "sell:apple:2:-3", "sell:apple:2:many"] {
+            let result =
+                ClientRequest::new_from("0", sell_order.to_string(), &Instruments::default());
+            assert!(matches!(result, Err(ClientError::InvalidQuantity)));
+        }
+    }
+
+    #[test]
+    fn test_transaction_too_many_fields() {
+        let sell_order = "sell:apple:2:15:ioc:now".to_string();
+        let result = ClientRequest::new_from("0", sell_order, &Instruments::default());
+        assert!(matches!(
+            result,
+            Err(ClientError::InvalidTransactionMessage)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_with_time_in_force_from_str() {
+        for (sell_order, expected) in [
+            ("sell:apple:2:15:gtc", TimeInForce::Gtc),
+            ("sell:apple:2:15:ioc", TimeInForce::Ioc),
+            ("sell:apple:2:15:FOK", TimeInForce::Fok),
+            ("sell:apple:2:15:day", TimeInForce::Day),
+        ] {
+            match ClientRequest::new_from("0", sell_order.to_string(), &Instruments::default()) {
+                Ok(ClientRequest::Order(result)) => assert_eq!(result.time_in_force, expected),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transaction_incorrect_time_in_force() {
+        let sell_order = "sell:apple:2:15:forever".to_string();
+        let result = ClientRequest::new_from("0", sell_order, &Instruments::default());
+        assert!(matches!(result, Err(ClientError::UnknownTimeInForce)));
+    }
+
+    #[test]
+    fn test_market_order_from_str() {
+        let buy_order = "buy:apple:mkt:15".to_string();
+        match ClientRequest::new_from("0", buy_order, &Instruments::default()) {
+            Ok(ClientRequest::Order(result)) => {
+                assert_eq!(result.price, None);
+                assert_eq!(result.time_in_force, TimeInForce::Ioc);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_market_order_cannot_rest() {
+        for buy_order in ["buy:apple:mkt:15:gtc", "buy:apple:mkt:15:day"] {
+            let result =
+                ClientRequest::new_from("0", buy_order.to_string(), &Instruments::default());
+            assert!(matches!(result, Err(ClientError::MarketOrderCannotRest)));
+        }
+    }
+
     #[test]
     fn test_transaction_incorrect_message() {
-        let buy_order = "buy onion".to_string();
-        let result = Transaction::new_from(0, buy_order);
+        let buy_order = "buy onion 1.25".to_string();
+        let result = ClientRequest::new_from("0", buy_order, &Instruments::default());
         assert!(matches!(
             result,
             Err(ClientError::InvalidTransactionMessage)
@@ -77,15 +310,143 @@ mod tests {
 
     #[test]
     fn test_transaction_incorrect_product() {
-        let buy_order = "buy:GME".to_string();
-        let result = Transaction::new_from(0, buy_order);
+        let buy_order = "buy:GME:1.25".to_string();
+        let result = ClientRequest::new_from("0", buy_order, &Instruments::default());
         assert!(matches!(result, Err(ClientError::UnknownProduct)));
     }
 
     #[test]
     fn test_transaction_incorrect_action() {
-        let buy_order = "buyy:APPLE".to_string();
-        let result = Transaction::new_from(0, buy_order);
+        let buy_order = "buyy:APPLE:1.25".to_string();
+        let result = ClientRequest::new_from("0", buy_order, &Instruments::default());
         assert!(matches!(result, Err(ClientError::UnknownAction)));
     }
+
+    #[test]
+    fn test_errors_list_every_action() {
+        let result =
+            ClientRequest::new_from("0", "hold:APPLE".to_string(), &Instruments::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown action. Choose between: BUY, SELL, CANCEL, AMEND, HEARTBEAT, SUBSCRIBE or UNSUBSCRIBE"
+        );
+        let result = ClientRequest::new_from("0", "subscribe".to_string(), &Instruments::default());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid transaction message. Should be <Action>:<Item>:<Price|MKT>[:<Quantity>[:<TimeInForce>]], \
+             CANCEL:<OrderId>, AMEND:<OrderId>:<Price>:<Quantity>, HEARTBEAT, SUBSCRIBE:<Item> or UNSUBSCRIBE:<Item>"
+        );
+    }
+
+    #[test]
+    fn test_transaction_missing_price() {
+        let buy_order = "buy:APPLE".to_string();
+        let result = ClientRequest::new_from("0", buy_order, &Instruments::default());
+        assert!(matches!(
+            result,
+            Err(ClientError::InvalidTransactionMessage)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_incorrect_price() {
+        let buy_order = "buy:APPLE:cheap".to_string();
+        let result = ClientRequest::new_from("0", buy_order, &Instruments::default());
+        assert!(matches!(result, Err(ClientError::InvalidPrice)));
+    }
+
+    #[test]
+    fn test_cancel_from_str() {
+        let cancel = "cancel:17".to_string();
+        match ClientRequest::new_from("0", cancel, &Instruments::default()) {
+            Ok(result) => assert_eq!(result, ClientRequest::Cancel(17)),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_cancel_incorrect_order_id() {
+        for cancel in ["cancel:", "cancel:first", "cancel:-1"] {
+            let result = ClientRequest::new_from("0", cancel.to_string(), &Instruments::default());
+            assert!(matches!(result, Err(ClientError::InvalidOrderId)));
+        }
+    }
+
+    #[test]
+    fn test_amend_from_str() {
+        let amend = "amend:17:1.5:20".to_string();
+        match ClientRequest::new_from("0", amend, &Instruments::default()) {
+            Ok(result) => assert_eq!(
+                result,
+                ClientRequest::Amend {
+                    order_id: 17,
+                    price: Price::from_str("1.5").unwrap(),
+                    quantity: 20,
+                }
+            ),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_amend_missing_quantity() {
+        let amend = "amend:17:1.5".to_string();
+        let result = ClientRequest::new_from("0", amend, &Instruments::default());
+        assert!(matches!(
+            result,
+            Err(ClientError::InvalidTransactionMessage)
+        ));
+    }
+
+    #[test]
+    fn test_heartbeat_from_str() {
+        let result =
+            ClientRequest::new_from("0", " heartbeat ".to_string(), &Instruments::default());
+        assert_eq!(result, Ok(ClientRequest::Heartbeat));
+        let result =
+            ClientRequest::new_from("0", "heartbeat:1".to_string(), &Instruments::default());
+        assert!(matches!(
+            result,
+            Err(ClientError::InvalidTransactionMessage)
+        ));
+    }
+
+    #[test]
+    fn test_subscribe_from_str() {
+        let result =
+            ClientRequest::new_from("0", "subscribe:apple".to_string(), &Instruments::default());
+        assert_eq!(result, Ok(ClientRequest::Subscribe(Product::APPLE)));
+        let result = ClientRequest::new_from(
+            "0",
+            "UNSUBSCRIBE:APPLE".to_string(),
+            &Instruments::default(),
+        );
+        assert_eq!(result, Ok(ClientRequest::Unsubscribe(Product::APPLE)));
+        let result = ClientRequest::new_from(
+            "0",
+            "subscribe:apple:pear".to_string(),
+            &Instruments::default(),
+        );
+        assert!(matches!(result, Err(ClientError::UnknownProduct)));
+    }
+
+    #[test]
+    fn test_login_twice() {
+        let result = ClientRequest::new_from(
+            "0",
+            "login:alice:wonderland".to_string(),
+            &Instruments::default(),
+        );
+        assert!(matches!(result, Err(ClientError::AlreadyLoggedIn)));
+    }
+
+    #[test]
+    fn test_amend_incorrect_fields() {
+        let result =
+            ClientRequest::new_from("0", "amend:x:1.5:20".to_string(), &Instruments::default());
+        assert!(matches!(result, Err(ClientError::InvalidOrderId)));
+        let result =
+            ClientRequest::new_from("0", "amend:17:1.5:0".to_string(), &Instruments::default());
+        assert!(matches!(result, Err(ClientError::InvalidQuantity)));
+    }
 }
@@ -1,235 +1,2069 @@
-use crate::actions::{ClientActions, ServerActions};
-use crate::errors::Error;
+use crate::accounts::{AccountId, Accounts, Credentials, DisconnectPolicy};
+use crate::actions::{ServerActions, Side};
+use crate::config::Settings;
+use crate::consts::RECENT_TRADES;
+use crate::errors::{ClientError, Error};
+use crate::instruments::{Instrument, Instruments};
+use crate::journal::{Record, Snapshot};
+use crate::market_data::MarketData;
+use crate::messages::{Envelope, ServerMessage};
+use crate::order_book::{Execution, IdGenerator, Level, OrderBook, OrderId, RestingOrder, Trade};
+use crate::price::Price;
 use crate::products::Product;
-use crate::storage::TransactionStorage;
-use crate::trader::Transaction;
-use std::collections::HashMap;
-use std::sync::RwLock;
+use crate::session::{Sequence, Session};
+use crate::storage::Storage;
+use crate::time_in_force::TimeInForce;
+use crate::trader::{Quantity, Transaction};
+use crate::utils::millis_since_epoch;
+use log::{error, info};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 use tokio::sync::mpsc::Sender;
 
 #[derive(Default)]
 pub struct TransactionService {
-    traders: RwLock<HashMap<u16, Sender<String>>>,
-    sells: TransactionStorage,
-    buys: TransactionStorage,
+    accounts: Accounts,
+    traders: RwLock<HashMap<AccountId, Session>>,
+    books: RwLock<HashMap<Product, OrderBook>>,
+    /// Latest trades, newest first.
+    recent_trades: RwLock<VecDeque<Trade>>,
+    order_ids: IdGenerator,
+    trade_ids: IdGenerator,
+    settings: Settings,
+    instruments: Arc<Instruments>,
+    /// Products listed, or delisted when `None`, since the server started from
+    /// its config.
+    listings: RwLock<BTreeMap<Product, Option<Instrument>>>,
+    /// Held while the books change, so the changes are stored in order.
+    storage: Mutex<Box<dyn Storage>>,
+    /// Level-2 feeds of the books someone subscribed to.
+    market_data: Mutex<HashMap<Product, MarketData>>,
+}
+
+/// What became of a new order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub order_id: OrderId,
+    pub trades: Vec<Trade>,
+    /// Quantity left once the order stopped trading.
+    pub leaves: Quantity,
+    /// Whether the leaves rest in the book; otherwise they expired.
+    pub resting: bool,
 }
 
 impl TransactionService {
-    pub async fn inform_all(&self, product: Product) -> Result<(), Error> {
-        let message = Self::inform_about_trade(product);
-        for trader_send in self.get_all_trader_addrs() {
-            trader_send.send(message.clone()).await?;
+    /// With the default settings.
+    #[cfg(test)]
+    pub fn new(accounts: Accounts) -> Self {
+        Self {
+            accounts,
+            ..Default::default()
         }
-        Ok(())
     }
 
-    pub async fn confirm(&self, trader_id: u16, product: Product) -> Result<(), Error> {
-        if let Some(trader_send) = self.get_trader_addr(trader_id) {
-            let message = Self::ack_order(product);
-            trader_send.send(message).await?;
+    pub fn with_settings(accounts: Accounts, settings: Settings, instruments: Instruments) -> Self {
+        Self {
+            accounts,
+            settings,
+            instruments: Arc::new(instruments),
+            ..Default::default()
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Trading rules of a listed product.
+    pub fn instrument(&self, product: Product) -> Result<Instrument, ClientError> {
+        self.instruments.get(product)
+    }
+
+    pub fn instruments(&self) -> Vec<Instrument> {
+        self.instruments.all()
+    }
+
+    /// The registry itself, for parsing requests against what is listed now.
+    pub fn registry(&self) -> &Arc<Instruments> {
+        &self.instruments
+    }
+
+    pub fn is_admin(&self, account_id: &str) -> bool {
+        self.settings.admins.iter().any(|admin| admin == account_id)
+    }
+
+    /// Lists a product, or changes its rules; resting orders are kept as they are.
+    pub fn list_instrument(&self, instrument: Instrument) -> Option<Instrument> {
+        info!("list instrument ({}, {})", instrument.product, instrument);
+        let mut storage = self.storage.lock().unwrap();
+        Self::store(&mut storage, &Record::List { instrument });
+        self.change_listing(instrument.product, Some(instrument))
+    }
+
+    /// Stops trading a product, returning the resting orders it had; they are
+    /// cancelled.
+    pub fn delist_instrument(
+        &self,
+        product: Product,
+    ) -> Result<(Instrument, Vec<RestingOrder>), ClientError> {
+        let mut storage = self.storage.lock().unwrap();
+        let instrument = self.instruments.get(product)?;
+        info!("delist instrument ({})", product);
+        let orders = match self.books.write().unwrap().remove(&product) {
+            Some(book) => {
+                let mut orders = book.orders(Side::Buy);
+                orders.extend(book.orders(Side::Sell));
+                orders
+            }
+            None => Vec::new(),
         };
-        Ok(())
+        Self::store_cancels(&mut storage, &orders);
+        Self::store(&mut storage, &Record::Delist { product });
+        self.change_listing(product, None);
+        self.publish_depth([product]);
+        Ok((instrument, orders))
+    }
+
+    /// Lists or delists a product on top of the configured instruments,
+    /// returning the rules it had; the storage has to be locked.
+    fn change_listing(&self, product: Product, listing: Option<Instrument>) -> Option<Instrument> {
+        self.listings.write().unwrap().insert(product, listing);
+        match listing {
+            Some(instrument) => self.instruments.list(instrument),
+            None => self.instruments.delist(product).ok(),
+        }
     }
 
-    pub async fn send_error(&self, trader_id: u16, error_msg: String) -> Result<(), Error> {
-        if let Some(trader_send) = self.get_trader_addr(trader_id) {
-            trader_send.send(error_msg).await?;
+    /// Publishes the anonymous trade tape to everyone.
+    pub async fn inform_all(&self, trade: &Trade) -> Result<(), Error> {
+        let message = Self::inform_about_trade(trade);
+        let deliveries: Vec<_> = {
+            let mut storage = self.storage.lock().unwrap();
+            let mut traders = self.traders.write().unwrap();
+            let sequences = traders
+                .iter_mut()
+                .filter_map(|(account_id, session)| Some((account_id.clone(), session.reserve(1)?)))
+                .collect();
+            Self::store_sequences(&mut storage, sequences);
+            traders
+                .values_mut()
+                .map(|session| (session.record(message.clone()), session.sender.clone()))
+                .collect()
         };
+        for (message, sender) in deliveries {
+            if let Some(trader_send) = sender {
+                trader_send.send(message).await?;
+            }
+        }
         Ok(())
     }
 
-    pub fn remove_trader(&self, trader_id: u16) {
-        self.traders.write().unwrap().remove(&trader_id);
+    /// Reports the trades to their counterparties and on the public tape.
+    pub async fn publish_trades(&self, trades: &[Trade]) -> Result<(), Error> {
+        for trade in trades {
+            info!("{}", Self::log_trade(trade));
+            self.record_trade(trade);
+            self.report_executions(trade).await?;
+            self.inform_all(trade).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends each counterparty the private report of its side of the trade.
+    pub async fn report_executions(&self, trade: &Trade) -> Result<(), Error> {
+        for execution in [&trade.aggressor, &trade.resting] {
+            let message = Self::report_execution(trade, execution);
+            self.deliver(&execution.trader_id, message).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn confirm(
+        &self,
+        trader_id: &str,
+        product: Product,
+        order_id: OrderId,
+    ) -> Result<(), Error> {
+        self.deliver(trader_id, Self::ack_order(product, order_id))
+            .await
+    }
+
+    pub async fn confirm_cancel(&self, trader_id: &str, order_id: OrderId) -> Result<(), Error> {
+        self.deliver(trader_id, Self::ack_cancel(order_id)).await
+    }
+
+    pub async fn confirm_replace(&self, order: &RestingOrder) -> Result<(), Error> {
+        self.deliver(&order.trader_id, Self::ack_replace(order))
+            .await
     }
 
-    pub fn register_trader(&self, trader_id: u16, stream_addr: Sender<String>) {
-        self.traders.write().unwrap().insert(trader_id, stream_addr);
+    /// Tells the trader why `input` was rejected.
+    pub async fn reject(
+        &self,
+        trader_id: &str,
+        error: ClientError,
+        input: Option<String>,
+    ) -> Result<(), Error> {
+        self.deliver(trader_id, ServerMessage::Reject { error, input })
+            .await
     }
 
-    pub fn register_order(&self, transaction: Transaction) {
-        match transaction.action {
-            ClientActions::Buy => self.buys.add(transaction.into()),
-            ClientActions::Sell => self.sells.add(transaction.into()),
+    /// Drops the trader's connection, keeping its session so it can resume later.
+    /// Returns the login the connection belonged to.
+    pub fn remove_trader(&self, trader_id: &str) -> u64 {
+        for market_data in self.market_data.lock().unwrap().values_mut() {
+            market_data.unsubscribe(trader_id);
+        }
+        match self.traders.write().unwrap().get_mut(trader_id) {
+            Some(session) => {
+                session.sender = None;
+                session.logins
+            }
+            None => 0,
+        }
+    }
+
+    /// The account's own policy, or else the server-wide one.
+    pub fn disconnect_policy(&self, trader_id: &str) -> DisconnectPolicy {
+        self.accounts
+            .disconnect_policy(trader_id)
+            .unwrap_or(self.settings.disconnect_policy)
+    }
+
+    /// Pulls all resting orders of a disconnected trader,
+    /// unless it has logged in again since `login` ended.
+    pub fn cancel_on_disconnect(&self, trader_id: &str, login: u64) -> Vec<RestingOrder> {
+        let still_away = self
+            .traders
+            .read()
+            .unwrap()
+            .get(trader_id)
+            .is_some_and(|session| session.sender.is_none() && session.logins == login);
+        if !still_away {
+            return Vec::new();
+        }
+        let mut storage = self.storage.lock().unwrap();
+        let orders: Vec<_> = self
+            .books
+            .write()
+            .unwrap()
+            .values_mut()
+            .flat_map(|book| book.cancel_all(trader_id))
+            .collect();
+        Self::store_cancels(&mut storage, &orders);
+        self.publish_depth(orders.iter().map(|order| order.product));
+        orders
+    }
+
+    /// Checks the credentials and registers the trader's connection under its account.
+    /// An account can only be logged in from one connection at a time.
+    /// Returns the `LOGGED_IN` message followed by the messages to replay.
+    pub fn login(
+        &self,
+        credentials: &Credentials,
+        stream_addr: Sender<Envelope>,
+    ) -> Result<(AccountId, Vec<Envelope>), ClientError> {
+        let account_id = self.accounts.authenticate(credentials)?;
+        self.open_session(account_id, credentials.last_seen, stream_addr)
+    }
+
+    /// Registers the connection of an account authenticated some other way,
+    /// e.g. by its client certificate.
+    pub fn open_session(
+        &self,
+        account_id: AccountId,
+        last_seen: Option<Sequence>,
+        stream_addr: Sender<Envelope>,
+    ) -> Result<(AccountId, Vec<Envelope>), ClientError> {
+        let mut traders = self.traders.write().unwrap();
+        let session = traders.entry(account_id.clone()).or_default();
+        if session.sender.is_some() {
+            return Err(ClientError::AlreadyLoggedIn);
+        }
+        // Nothing past the last sequence number has been sent
+        if last_seen > Some(session.last_sequence()) {
+            return Err(ClientError::InvalidSequence);
+        }
+        session.sender = Some(stream_addr);
+        session.logins += 1;
+        let mut messages = vec![Envelope::unsequenced(Self::ack_login(
+            &account_id,
+            session.last_sequence(),
+        ))];
+        if let Some(last_seen) = last_seen {
+            messages.extend(session.replay(last_seen));
+        }
+        Ok((account_id, messages))
+    }
+
+    /// Checks credentials without logging in, for requests outside of a session.
+    pub fn authenticate(&self, credentials: &Credentials) -> Result<AccountId, ClientError> {
+        self.accounts.authenticate(credentials)
+    }
+
+    /// Acknowledges and matches a new order, then rests what is left of it
+    /// or expires it when its time in force does not allow resting.
+    pub async fn place_order(&self, transaction: Transaction) -> Result<Placement, Error> {
+        self.instrument(transaction.product)?
+            .check(transaction.price, transaction.quantity)?;
+        self.settings
+            .risk
+            .check(transaction.price, transaction.quantity)?;
+        info!("{}", transaction);
+        let trader_id = transaction.trader_id.clone();
+        let order_id = self.new_order_id();
+        let placement = {
+            let mut storage = self.storage.lock().unwrap();
+            let timestamp = millis_since_epoch(SystemTime::now());
+            let placement = self.execute(order_id, transaction.clone(), timestamp);
+            Self::store(
+                &mut storage,
+                &Record::Order {
+                    order_id,
+                    transaction: transaction.clone(),
+                    trades: placement.trades.clone(),
+                    sequences: self.reserve_for(&trader_id, &placement.trades),
+                },
+            );
+            Self::store_trades(&mut storage, &placement.trades);
+            self.publish_depth([transaction.product]);
+            placement
         };
+        self.confirm(&trader_id, transaction.product, order_id)
+            .await?;
+        self.publish_trades(&placement.trades).await?;
+        if !placement.resting && placement.leaves > 0 {
+            let time_in_force = transaction.time_in_force;
+            info!(
+                "cancel order ('{}', {}, {} left, {})",
+                trader_id, order_id, placement.leaves, time_in_force
+            );
+            self.confirm_expiry(&trader_id, order_id, placement.leaves, time_in_force)
+                .await?;
+        }
+        Ok(placement)
     }
 
-    pub fn try_trade_with(&self, transaction: Transaction) -> Option<Product> {
-        match transaction.action {
-            ClientActions::Buy => self.find_seller(transaction.product, transaction.trader_id),
-            ClientActions::Sell => self.find_buyer(transaction.product, transaction.trader_id),
+    /// Matches a new order and rests what is left of it, when it can rest.
+    fn execute(&self, order_id: OrderId, transaction: Transaction, timestamp: u64) -> Placement {
+        let trades = self.try_trade_with(order_id, transaction.clone(), timestamp);
+        let leaves = trades
+            .last()
+            .map_or(transaction.quantity, |trade| trade.aggressor.leaves);
+        let resting = leaves > 0 && transaction.time_in_force.can_rest();
+        if resting {
+            self.register_order(
+                order_id,
+                Transaction {
+                    quantity: leaves,
+                    ..transaction
+                },
+            );
+        }
+        Placement {
+            order_id,
+            trades,
+            leaves,
+            resting,
         }
     }
 
-    pub fn log_trade(product: Product) -> String {
-        format!("{} ({})", ServerActions::Trade, product)
+    pub fn new_order_id(&self) -> OrderId {
+        self.order_ids.next()
     }
 
-    fn inform_about_trade(product: Product) -> String {
-        format!("{}:{}", ServerActions::Trade, product)
+    pub async fn confirm_expiry(
+        &self,
+        trader_id: &str,
+        order_id: OrderId,
+        quantity: Quantity,
+        time_in_force: TimeInForce,
+    ) -> Result<(), Error> {
+        self.deliver(
+            trader_id,
+            Self::ack_expiry(order_id, quantity, time_in_force),
+        )
+        .await
     }
 
-    fn ack_order(product: Product) -> String {
-        format!("{}:{}", ServerActions::Ack, product)
+    pub fn register_order(&self, order_id: OrderId, transaction: Transaction) {
+        let product = transaction.product;
+        if let Some(order) = RestingOrder::new(order_id, transaction) {
+            self.books
+                .write()
+                .unwrap()
+                .entry(product)
+                .or_default()
+                .insert(order);
+        }
     }
 
-    fn get_trader_addr(&self, trader_id: u16) -> Option<Sender<String>> {
-        self.traders.write().unwrap().get(&trader_id).cloned()
+    /// Withdraws a resting order; traders can only cancel their own orders.
+    pub fn cancel_order(
+        &self,
+        trader_id: &str,
+        order_id: OrderId,
+    ) -> Result<RestingOrder, ClientError> {
+        let mut storage = self.storage.lock().unwrap();
+        let order = self
+            .books
+            .write()
+            .unwrap()
+            .values_mut()
+            .find(|book| {
+                book.get(order_id)
+                    .is_some_and(|order| order.trader_id == trader_id)
+            })
+            .and_then(|book| book.cancel(order_id))
+            .ok_or(ClientError::UnknownOrder)?;
+        Self::store(&mut storage, &Record::Cancel { order_id });
+        self.publish_depth([order.product]);
+        Ok(order)
     }
 
-    fn get_all_trader_addrs(&self) -> Vec<Sender<String>> {
-        let traders = self.traders.write().unwrap();
-        traders.values().cloned().into_iter().collect()
+    /// Atomically replaces price and remaining quantity of a trader's resting order,
+    /// returning its new state and any trades the new price executed.
+    pub fn amend_order(
+        &self,
+        trader_id: &str,
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<(RestingOrder, Vec<Trade>), ClientError> {
+        self.settings.risk.check(Some(price), quantity)?;
+        let mut storage = self.storage.lock().unwrap();
+        let mut books = self.books.write().unwrap();
+        let (product, book) = books
+            .iter_mut()
+            .find(|(_, book)| {
+                book.get(order_id)
+                    .is_some_and(|order| order.trader_id == trader_id)
+            })
+            .ok_or(ClientError::UnknownOrder)?;
+        self.instrument(*product)?.check(Some(price), quantity)?;
+        let timestamp = millis_since_epoch(SystemTime::now());
+        let (order, trades) = book
+            .amend(order_id, price, quantity, &self.trade_ids, timestamp)
+            .ok_or(ClientError::UnknownOrder)?;
+        let record = Record::Amend {
+            order_id,
+            price,
+            quantity,
+            trades: trades.clone(),
+            sequences: self.reserve_for(trader_id, &trades),
+        };
+        Self::store(&mut storage, &record);
+        Self::store_trades(&mut storage, &trades);
+        drop(books);
+        self.publish_depth([order.product]);
+        Ok((order, trades))
     }
 
-    fn find_seller(&self, product: Product, author_id: u16) -> Option<Product> {
-        self.sells
-            .try_find(product, author_id)
-            .map(|(matched_product, position)| {
-                self.sells.remove_at(position);
-                matched_product
+    /// Reserves sequence numbers for what the owner and the counterparties of an
+    /// order are told about it: an acknowledgement and an expiry, then an
+    /// execution report and a line of the tape per trade. The storage has to be
+    /// locked, so the reservations go into the order's record.
+    fn reserve_for(&self, trader_id: &str, trades: &[Trade]) -> BTreeMap<AccountId, Sequence> {
+        let count = 2 + 2 * trades.len() as u64;
+        let mut traders = self.traders.write().unwrap();
+        let counterparties = trades
+            .iter()
+            .flat_map(|trade| [&trade.aggressor.trader_id, &trade.resting.trader_id]);
+        std::iter::once(&trader_id.to_string())
+            .chain(counterparties)
+            .filter_map(|account_id| {
+                let reserved = traders.get_mut(account_id)?.reserve(count)?;
+                Some((account_id.clone(), reserved))
             })
+            .collect()
     }
 
-    fn find_buyer(&self, product: Product, author_id: u16) -> Option<Product> {
-        self.buys
-            .try_find(product, author_id)
-            .map(|(matched_product, position)| {
-                self.buys.remove_at(position);
-                matched_product
+    /// Fill-or-kill transactions only trade when they can be filled completely.
+    /// Trades are made at `timestamp`.
+    pub fn try_trade_with(
+        &self,
+        order_id: OrderId,
+        transaction: Transaction,
+        timestamp: u64,
+    ) -> Vec<Trade> {
+        match self.books.write().unwrap().get_mut(&transaction.product) {
+            Some(book)
+                if transaction.time_in_force == TimeInForce::Fok
+                    && book.available(&transaction) < transaction.quantity =>
+            {
+                Vec::new()
+            }
+            Some(book) => book.match_order(order_id, &transaction, &self.trade_ids, timestamp),
+            None => Vec::new(),
+        }
+    }
+
+    /// Expires all day orders, returning them so their owners can be told.
+    pub fn end_of_day(&self) -> Vec<RestingOrder> {
+        let mut storage = self.storage.lock().unwrap();
+        let orders: Vec<_> = self
+            .books
+            .write()
+            .unwrap()
+            .values_mut()
+            .flat_map(|book| book.expire_day_orders())
+            .collect();
+        Self::store_cancels(&mut storage, &orders);
+        self.publish_depth(orders.iter().map(|order| order.product));
+        orders
+    }
+
+    /// Starts sending the trader the level-2 feed of a listed product: a
+    /// snapshot of its book, then each change to a price level.
+    pub fn subscribe(&self, trader_id: &str, product: Product) -> Result<(), ClientError> {
+        self.instrument(product)?;
+        let sender = self
+            .traders
+            .read()
+            .unwrap()
+            .get(trader_id)
+            .and_then(|session| session.sender.clone())
+            .ok_or(ClientError::NotLoggedIn)?;
+        let mut market_data = self.market_data.lock().unwrap();
+        market_data
+            .entry(product)
+            .or_insert_with(|| {
+                MarketData::new(
+                    self.depth(product, Side::Buy),
+                    self.depth(product, Side::Sell),
+                )
             })
+            .subscribe(product, trader_id, sender);
+        Ok(())
+    }
+
+    /// Stops the feed of a product; unsubscribing twice does no harm.
+    pub async fn unsubscribe(&self, trader_id: &str, product: Product) -> Result<(), Error> {
+        if let Some(market_data) = self.market_data.lock().unwrap().get_mut(&product) {
+            market_data.unsubscribe(trader_id);
+        }
+        let sender = self
+            .traders
+            .read()
+            .unwrap()
+            .get(trader_id)
+            .and_then(|session| session.sender.clone());
+        if let Some(sender) = sender {
+            let message = ServerMessage::Unsubscribed { product };
+            sender.send(Envelope::unsequenced(message)).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends subscribers what changed in the books of `products`. Called with
+    /// the storage locked, so updates go out in the order the books changed.
+    fn publish_depth(&self, products: impl IntoIterator<Item = Product>) {
+        let products: BTreeSet<_> = products.into_iter().collect();
+        let mut market_data = self.market_data.lock().unwrap();
+        for product in products {
+            if let Some(feed) = market_data.get_mut(&product) {
+                feed.update(
+                    product,
+                    self.depth(product, Side::Buy),
+                    self.depth(product, Side::Sell),
+                );
+            }
+        }
+    }
+
+    /// Rebuilds the books, the trade history and the account sequences from
+    /// what `storage` has kept, returning the number of records replayed after
+    /// its latest snapshot. Changes are stored in it from then on.
+    pub fn recover(&self, mut storage: Box<dyn Storage>) -> Result<usize, Error> {
+        let (snapshot, records) = storage.load()?;
+        // Replaying adds the trades missing from the trade history
+        *self.storage.lock().unwrap() = storage;
+        if let Some(snapshot) = snapshot {
+            info!(
+                "restore snapshot ({} journal records, {} resting orders)",
+                snapshot.offset,
+                snapshot.orders.len()
+            );
+            self.restore(snapshot);
+        }
+        for record in &records {
+            self.replay(record)?;
+        }
+        Ok(records.len())
+    }
+
+    /// Snapshots everything the stored records rebuild, so recovery only has to
+    /// replay what changed afterwards. Returns the number of records the snapshot
+    /// covers, or `None` when nothing changed since the last one.
+    pub fn snapshot(&self) -> Result<Option<u64>, Error> {
+        let mut storage = self.storage.lock().unwrap();
+        if !storage.has_changes() {
+            return Ok(None);
+        }
+        let snapshot = self.capture(storage.offset());
+        storage.snapshot(&snapshot)?;
+        Ok(Some(snapshot.offset))
+    }
+
+    /// The state as of `offset` records; the storage has to be locked.
+    fn capture(&self, offset: u64) -> Snapshot {
+        let books = self.books.read().unwrap();
+        let mut products: Vec<_> = books.keys().collect();
+        products.sort();
+        let orders = products
+            .into_iter()
+            .flat_map(|product| {
+                let book = &books[product];
+                let mut orders = book.orders(Side::Buy);
+                orders.extend(book.orders(Side::Sell));
+                orders
+            })
+            .collect();
+        let sequences = self
+            .traders
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(account_id, session)| (account_id.clone(), session.reserved()))
+            .collect();
+        Snapshot {
+            offset,
+            last_order_id: self.order_ids.last(),
+            last_trade_id: self.trade_ids.last(),
+            orders,
+            recent_trades: self.recent_trades(),
+            sequences,
+            listings: self.listings.read().unwrap().clone(),
+        }
+    }
+
+    /// Carries on numbering the accounts' messages after their reservations.
+    fn resume_sessions(&self, sequences: &BTreeMap<AccountId, Sequence>) {
+        let mut traders = self.traders.write().unwrap();
+        for (account_id, reserved) in sequences {
+            traders.insert(account_id.clone(), Session::resume(*reserved));
+        }
+    }
+
+    fn restore(&self, snapshot: Snapshot) {
+        self.order_ids.skip_to(snapshot.last_order_id);
+        self.trade_ids.skip_to(snapshot.last_trade_id);
+        let mut books = self.books.write().unwrap();
+        // Orders come in execution order, so they keep their priority
+        for order in snapshot.orders {
+            books.entry(order.product).or_default().insert(order);
+        }
+        *self.recent_trades.write().unwrap() = snapshot.recent_trades.into();
+        self.resume_sessions(&snapshot.sequences);
+        for (product, listing) in snapshot.listings {
+            self.change_listing(product, listing);
+        }
+    }
+
+    /// Matching is deterministic, so replaying has to make the same trades again.
+    fn replay(&self, record: &Record) -> Result<(), Error> {
+        let (order_id, trades, journaled) = match record {
+            Record::Order {
+                order_id,
+                transaction,
+                trades,
+                sequences,
+            } => {
+                self.resume_sessions(sequences);
+                self.order_ids.skip_to(*order_id);
+                let placement =
+                    self.execute(*order_id, transaction.clone(), Self::execution_time(trades));
+                (*order_id, placement.trades, trades)
+            }
+            Record::Cancel { order_id } => {
+                self.books
+                    .write()
+                    .unwrap()
+                    .values_mut()
+                    .find_map(|book| book.cancel(*order_id))
+                    .ok_or_else(|| {
+                        Error::InvalidJournal(format!(
+                            "cancelled order {} is not in the books",
+                            order_id
+                        ))
+                    })?;
+                return Ok(());
+            }
+            Record::Amend {
+                order_id,
+                price,
+                quantity,
+                trades,
+                sequences,
+            } => {
+                self.resume_sessions(sequences);
+                let (_, amended) = self
+                    .books
+                    .write()
+                    .unwrap()
+                    .values_mut()
+                    .find_map(|book| {
+                        let timestamp = Self::execution_time(trades);
+                        book.amend(*order_id, *price, *quantity, &self.trade_ids, timestamp)
+                    })
+                    .ok_or_else(|| {
+                        Error::InvalidJournal(format!(
+                            "amended order {} is not in the books",
+                            order_id
+                        ))
+                    })?;
+                (*order_id, amended, trades)
+            }
+            Record::List { instrument } => {
+                self.change_listing(instrument.product, Some(*instrument));
+                return Ok(());
+            }
+            Record::Delist { product } => {
+                // Its orders were cancelled by the records before
+                self.books.write().unwrap().remove(product);
+                self.change_listing(*product, None);
+                return Ok(());
+            }
+            Record::Sequences { sequences } => {
+                self.resume_sessions(sequences);
+                return Ok(());
+            }
+        };
+        if &trades != journaled {
+            return Err(Error::InvalidJournal(format!(
+                "order {} trades differently than journaled",
+                order_id
+            )));
+        }
+        for trade in &trades {
+            self.record_trade(trade);
+        }
+        // A crash right after storing the record can leave its trades out
+        Self::store_trades(&mut self.storage.lock().unwrap(), &trades);
+        Ok(())
+    }
+
+    /// Nothing can be acknowledged once the storage fails, so the server stops.
+    fn store(storage: &mut Box<dyn Storage>, record: &Record) {
+        if let Err(e) = storage.append(record) {
+            error!("Error occurred while storing the books, stopping! {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    /// Like the books, trades have to be stored before they are reported.
+    fn store_trades(storage: &mut Box<dyn Storage>, trades: &[Trade]) {
+        if trades.is_empty() {
+            return;
+        }
+        if let Err(e) = storage.record_trades(trades) {
+            error!("Error occurred while storing trades, stopping! {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    /// When journaled trades were made, so replaying makes them at the same time.
+    fn execution_time(trades: &[Trade]) -> u64 {
+        trades.first().map_or(0, |trade| trade.timestamp)
+    }
+
+    /// Reservations of sequence numbers are stored before the numbers are sent,
+    /// so a restart carries on after them.
+    fn store_sequences(storage: &mut Box<dyn Storage>, sequences: BTreeMap<AccountId, Sequence>) {
+        if !sequences.is_empty() {
+            Self::store(storage, &Record::Sequences { sequences });
+        }
+    }
+
+    fn store_cancels(storage: &mut Box<dyn Storage>, orders: &[RestingOrder]) {
+        for order in orders {
+            let order_id = order.order_id;
+            Self::store(storage, &Record::Cancel { order_id });
+        }
+    }
+
+    /// Resting orders of a trader across all books, oldest first.
+    pub fn open_orders(&self, trader_id: &str) -> Vec<RestingOrder> {
+        let books = self.books.read().unwrap();
+        let mut orders: Vec<_> = books
+            .values()
+            .flat_map(|book| {
+                let mut orders = book.orders(Side::Buy);
+                orders.extend(book.orders(Side::Sell));
+                orders
+            })
+            .filter(|order| order.trader_id == trader_id)
+            .collect();
+        orders.sort_by_key(|order| order.order_id);
+        orders
+    }
+
+    /// Aggregated price levels of one side of a book, best price first.
+    pub fn depth(&self, product: Product, side: Side) -> Vec<Level> {
+        self.books
+            .read()
+            .unwrap()
+            .get(&product)
+            .map_or_else(Vec::new, |book| book.depth(side))
+    }
+
+    /// The latest trades, newest first.
+    pub fn recent_trades(&self) -> Vec<Trade> {
+        self.recent_trades.read().unwrap().iter().cloned().collect()
+    }
+
+    fn record_trade(&self, trade: &Trade) {
+        let mut recent_trades = self.recent_trades.write().unwrap();
+        recent_trades.push_front(trade.clone());
+        recent_trades.truncate(RECENT_TRADES);
+    }
+
+    pub fn log_trade(trade: &Trade) -> String {
+        let (buyer, seller) = match trade.aggressor.side {
+            Side::Buy => (&trade.aggressor, &trade.resting),
+            Side::Sell => (&trade.resting, &trade.aggressor),
+        };
+        format!(
+            "{} {} ({} {} @ {}, '{}' bought from '{}')",
+            ServerActions::Trade,
+            trade.trade_id,
+            trade.quantity,
+            trade.product,
+            trade.price,
+            buyer.trader_id,
+            seller.trader_id
+        )
+    }
+
+    fn inform_about_trade(trade: &Trade) -> ServerMessage {
+        ServerMessage::Trade {
+            product: trade.product,
+            price: trade.price,
+            quantity: trade.quantity,
+        }
+    }
+
+    fn report_execution(trade: &Trade, execution: &Execution) -> ServerMessage {
+        ServerMessage::Execution {
+            trade_id: trade.trade_id,
+            order_id: execution.order_id,
+            side: execution.side,
+            product: trade.product,
+            price: trade.price,
+            quantity: trade.quantity,
+            leaves: execution.leaves,
+        }
+    }
+
+    fn ack_login(account_id: &str, last_sequence: Sequence) -> ServerMessage {
+        ServerMessage::LoggedIn {
+            account_id: account_id.to_string(),
+            last_sequence,
+        }
+    }
+
+    fn ack_order(product: Product, order_id: OrderId) -> ServerMessage {
+        ServerMessage::Ack { product, order_id }
+    }
+
+    fn ack_cancel(order_id: OrderId) -> ServerMessage {
+        ServerMessage::Canceled { order_id }
+    }
+
+    fn ack_expiry(
+        order_id: OrderId,
+        quantity: Quantity,
+        time_in_force: TimeInForce,
+    ) -> ServerMessage {
+        ServerMessage::Expired {
+            order_id,
+            quantity,
+            time_in_force,
+        }
+    }
+
+    fn ack_replace(order: &RestingOrder) -> ServerMessage {
+        ServerMessage::Replaced(order.clone())
+    }
+
+    /// Sequences and journals a message for the trader, sending it right away
+    /// when the trader is connected.
+    async fn deliver(&self, trader_id: &str, message: ServerMessage) -> Result<(), Error> {
+        let (message, sender) = {
+            let mut storage = self.storage.lock().unwrap();
+            let mut traders = self.traders.write().unwrap();
+            let Some(session) = traders.get_mut(trader_id) else {
+                return Ok(());
+            };
+            if let Some(reserved) = session.reserve(1) {
+                let sequences = BTreeMap::from([(trader_id.to_string(), reserved)]);
+                Self::store_sequences(&mut storage, sequences);
+            }
+            (session.record(message), session.sender.clone())
+        };
+        if let Some(trader_send) = sender {
+            trader_send.send(message).await?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Transaction;
+    use crate::actions::Side;
+    use crate::config::RiskLimits;
+    use crate::consts::SEQUENCE_BLOCK;
+    use crate::journal::tests::TempDir;
+    use crate::storage::{FileStorage, MemoryStorage};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn price(price: &str) -> Price {
+        Price::from_str(price).unwrap()
+    }
+
+    fn order(trader_id: &str, side: Side, limit: &str) -> Transaction {
+        sized_order(trader_id, side, limit, 1)
+    }
+
+    fn sized_order(trader_id: &str, side: Side, limit: &str, quantity: Quantity) -> Transaction {
+        Transaction {
+            trader_id: trader_id.to_string(),
+            side,
+            product: Product::APPLE,
+            price: Some(price(limit)),
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    /// Price, quantity and aggressor leaves of each trade.
+    fn fills(trades: &[Trade]) -> Vec<(Price, Quantity, Quantity)> {
+        trades
+            .iter()
+            .map(|trade| (trade.price, trade.quantity, trade.aggressor.leaves))
+            .collect()
+    }
+
+    fn sample_trade() -> Trade {
+        Trade {
+            trade_id: 11,
+            product: Product::APPLE,
+            price: price("1.25"),
+            quantity: 5,
+            aggressor: Execution {
+                order_id: 7,
+                trader_id: "40".to_string(),
+                side: Side::Sell,
+                leaves: 3,
+            },
+            resting: Execution {
+                order_id: 2,
+                trader_id: "42".to_string(),
+                side: Side::Buy,
+                leaves: 0,
+            },
+            timestamp: 0,
+        }
+    }
+
+    fn credentials(name: &str, secret: &str) -> Credentials {
+        Credentials {
+            name: name.to_string(),
+            secret: secret.to_string(),
+            last_seen: None,
+        }
+    }
+
+    /// The journal in the directory, and the trade ledger in `trades.jsonl` next to it.
+    fn file_storage(dir: &TempDir) -> Box<dyn Storage> {
+        let ledger = dir.0.join("trades.jsonl");
+        Box::new(FileStorage::open(&dir.0, &ledger, false).unwrap())
+    }
+
+    /// Stores in memory, where the test can still look after handing it over.
+    #[derive(Default, Clone)]
+    struct SharedStorage(Arc<Mutex<MemoryStorage>>);
+
+    impl Storage for SharedStorage {
+        fn load(&mut self) -> Result<(Option<Snapshot>, Vec<Record>), Error> {
+            self.0.lock().unwrap().load()
+        }
+
+        fn append(&mut self, record: &Record) -> std::io::Result<()> {
+            self.0.lock().unwrap().append(record)
+        }
+
+        fn record_trades(&mut self, trades: &[Trade]) -> std::io::Result<()> {
+            self.0.lock().unwrap().record_trades(trades)
+        }
+
+        fn offset(&self) -> u64 {
+            self.0.lock().unwrap().offset()
+        }
+
+        fn has_changes(&self) -> bool {
+            self.0.lock().unwrap().has_changes()
+        }
+
+        fn snapshot(&mut self, snapshot: &Snapshot) -> std::io::Result<()> {
+            self.0.lock().unwrap().snapshot(snapshot)
+        }
+    }
+
+    fn resting(tr_service: &TransactionService, side: Side) -> Vec<RestingOrder> {
+        tr_service
+            .books
+            .read()
+            .unwrap()
+            .get(&Product::APPLE)
+            .map_or_else(Vec::new, |book| book.orders(side))
+    }
 
     #[test]
     fn test_register_buying_trader() {
         let tr_service = TransactionService::default();
-        let transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Buy,
-            product: Product::Apple,
-        };
-        tr_service.register_order(transaction);
-        let buys = tr_service.buys.data.read().unwrap();
-        let sells = tr_service.sells.data.read().unwrap();
-        assert_eq!(buys.len(), 1);
-        assert!(buys.contains(&transaction.into()));
+        let transaction = order("0", Side::Buy, "1");
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, transaction.clone());
+        let buys = resting(&tr_service, Side::Buy);
+        let sells = resting(&tr_service, Side::Sell);
+        assert_eq!(
+            buys,
+            vec![RestingOrder::new(order_id, transaction).unwrap()]
+        );
         assert_eq!(sells.len(), 0);
     }
 
     #[test]
     fn test_register_selling_trader() {
         let tr_service = TransactionService::default();
-        let transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Sell,
-            product: Product::Apple,
-        };
-        tr_service.register_order(transaction);
-        let buys = tr_service.buys.data.read().unwrap();
-        let sells = tr_service.sells.data.read().unwrap();
-        assert_eq!(sells.len(), 1);
-        assert!(sells.contains(&transaction.into()));
+        let transaction = order("0", Side::Sell, "1");
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, transaction.clone());
+        let buys = resting(&tr_service, Side::Buy);
+        let sells = resting(&tr_service, Side::Sell);
+        assert_eq!(
+            sells,
+            vec![RestingOrder::new(order_id, transaction).unwrap()]
+        );
         assert_eq!(buys.len(), 0);
     }
 
+    #[test]
+    fn test_new_order_id_is_unique() {
+        let tr_service = TransactionService::default();
+        let first = tr_service.new_order_id();
+        let second = tr_service.new_order_id();
+        assert_ne!(first, second);
+    }
+
     #[test]
     fn test_try_trade_with_seller() {
         let tr_service = TransactionService::default();
-        let buy_transaction = Transaction {
-            trader_id: 1,
-            action: ClientActions::Buy,
-            product: Product::Apple,
-        };
-        tr_service.register_order(buy_transaction);
-        let sell_transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Sell,
-            product: Product::Apple,
-        };
-        let result = tr_service.try_trade_with(sell_transaction);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Product::Apple);
+        tr_service.register_order(tr_service.new_order_id(), order("1", Side::Buy, "1.25"));
+        let result =
+            tr_service.try_trade_with(tr_service.new_order_id(), order("0", Side::Sell, "1.2"), 0);
+        assert_eq!(fills(&result), vec![(price("1.25"), 1, 0)]);
     }
 
     #[test]
     fn test_try_trade_with_seller_faild() {
         let tr_service = TransactionService::default();
-        let sell_transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Sell,
-            product: Product::Apple,
-        };
-        let result = tr_service.try_trade_with(sell_transaction);
-        assert!(result.is_none());
+        let result =
+            tr_service.try_trade_with(tr_service.new_order_id(), order("0", Side::Sell, "1"), 0);
+        assert!(result.is_empty());
     }
 
     #[test]
     fn test_try_trade_with_buyer() {
         let tr_service = TransactionService::default();
-        let sell_transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Sell,
-            product: Product::Apple,
-        };
-        tr_service.register_order(sell_transaction);
-        let buy_transaction = Transaction {
-            trader_id: 1,
-            action: ClientActions::Buy,
-            product: Product::Apple,
-        };
-        let result = tr_service.try_trade_with(buy_transaction);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Product::Apple);
+        tr_service.register_order(tr_service.new_order_id(), order("0", Side::Sell, "1.2"));
+        let result =
+            tr_service.try_trade_with(tr_service.new_order_id(), order("1", Side::Buy, "1.25"), 0);
+        assert_eq!(fills(&result), vec![(price("1.2"), 1, 0)]);
+    }
+
+    #[test]
+    fn test_try_trade_with_lowest_seller_first() {
+        let tr_service = TransactionService::default();
+        for (trader_id, limit) in [("2", "1.3"), ("3", "1.1"), ("4", "1.2")] {
+            tr_service.register_order(
+                tr_service.new_order_id(),
+                order(trader_id, Side::Sell, limit),
+            );
+        }
+        for expected in ["1.1", "1.2", "1.3"] {
+            let result = tr_service.try_trade_with(
+                tr_service.new_order_id(),
+                order("1", Side::Buy, "1.5"),
+                0,
+            );
+            let prices: Vec<Price> = result.iter().map(|trade| trade.price).collect();
+            assert_eq!(prices, vec![price(expected)]);
+        }
+        assert!(resting(&tr_service, Side::Sell).is_empty());
+    }
+
+    #[test]
+    fn test_try_trade_with_sweeps_several_sellers() {
+        let tr_service = TransactionService::default();
+        for (trader_id, limit, quantity) in [("2", "1.3", 5), ("3", "1.1", 3), ("4", "1.2", 4)] {
+            tr_service.register_order(
+                tr_service.new_order_id(),
+                sized_order(trader_id, Side::Sell, limit, quantity),
+            );
+        }
+        let result = tr_service.try_trade_with(
+            tr_service.new_order_id(),
+            sized_order("1", Side::Buy, "1.3", 10),
+            0,
+        );
+        assert_eq!(
+            fills(&result),
+            vec![
+                (price("1.1"), 3, 7),
+                (price("1.2"), 4, 3),
+                (price("1.3"), 3, 0)
+            ]
+        );
+        let sells = resting(&tr_service, Side::Sell);
+        assert_eq!(sells.len(), 1);
+        assert_eq!(sells[0].quantity, 2);
+    }
+
+    #[test]
+    fn test_try_trade_with_leaves_remainder() {
+        let tr_service = TransactionService::default();
+        tr_service.register_order(
+            tr_service.new_order_id(),
+            sized_order("2", Side::Buy, "1.2", 4),
+        );
+        tr_service.register_order(
+            tr_service.new_order_id(),
+            sized_order("3", Side::Buy, "1", 4),
+        );
+        let result = tr_service.try_trade_with(
+            tr_service.new_order_id(),
+            sized_order("1", Side::Sell, "1.1", 10),
+            0,
+        );
+        assert_eq!(fills(&result), vec![(price("1.2"), 4, 6)]);
+        assert_eq!(resting(&tr_service, Side::Buy).len(), 1);
+    }
+
+    #[test]
+    fn test_try_trade_with_price_not_crossing() {
+        let tr_service = TransactionService::default();
+        tr_service.register_order(tr_service.new_order_id(), order("0", Side::Sell, "1.3"));
+        let result =
+            tr_service.try_trade_with(tr_service.new_order_id(), order("1", Side::Buy, "1.25"), 0);
+        assert!(result.is_empty());
+        assert_eq!(resting(&tr_service, Side::Sell).len(), 1);
     }
 
     #[test]
     fn test_try_trade_with_just_buyer() {
         let tr_service = TransactionService::default();
-        let transaction = Transaction {
-            trader_id: 1,
-            action: ClientActions::Buy,
-            product: Product::Apple,
-        };
-        tr_service.register_order(transaction);
-        let result = tr_service.try_trade_with(transaction);
-        assert!(result.is_none());
+        let transaction = order("1", Side::Buy, "1");
+        tr_service.register_order(tr_service.new_order_id(), transaction.clone());
+        let result = tr_service.try_trade_with(tr_service.new_order_id(), transaction, 0);
+        assert!(result.is_empty());
     }
 
     #[test]
     fn test_try_trade_with_buyer_failed() {
         let tr_service = TransactionService::default();
-        let transaction = Transaction {
-            trader_id: 0,
-            action: ClientActions::Buy,
-            product: Product::Apple,
-        };
-        let result = tr_service.try_trade_with(transaction);
-        assert!(result.is_none());
+        let result =
+            tr_service.try_trade_with(tr_service.new_order_id(), order("0", Side::Buy, "1"), 0);
+        assert!(result.is_empty());
     }
 
     #[test]
     fn test_inform_about_trade() {
-        let expected_result = "TRADE:APPLE".to_string();
-        let result = TransactionService::inform_about_trade(Product::Apple);
+        let expected_result = "TRADE:APPLE:1.25:5".to_string();
+        let result = TransactionService::inform_about_trade(&sample_trade()).to_string();
+        assert_eq!(expected_result, result)
+    }
+
+    #[test]
+    fn test_report_execution() {
+        let trade = sample_trade();
+        let expected_result = "EXEC:11:7:SELL:APPLE:1.25:5:3".to_string();
+        let result = TransactionService::report_execution(&trade, &trade.aggressor).to_string();
+        assert_eq!(expected_result, result);
+        let expected_result = "EXEC:11:2:BUY:APPLE:1.25:5:0".to_string();
+        let result = TransactionService::report_execution(&trade, &trade.resting).to_string();
         assert_eq!(expected_result, result)
     }
 
     #[test]
     fn test_log_trade() {
-        let expected_result = "TRADE (APPLE)".to_string();
-        let result = TransactionService::log_trade(Product::Apple);
+        let expected_result = "TRADE 11 (5 APPLE @ 1.25, '42' bought from '40')".to_string();
+        let result = TransactionService::log_trade(&sample_trade());
         assert_eq!(expected_result, result)
     }
 
+    #[tokio::test]
+    async fn test_report_executions_only_to_counterparties() {
+        let tr_service = TransactionService::default();
+        let mut receivers = Vec::new();
+        for trader_id in ["40", "41", "42"] {
+            let (sender, receiver) = tokio::sync::mpsc::channel(10);
+            let mut session = Session::default();
+            session.sender = Some(sender);
+            tr_service
+                .traders
+                .write()
+                .unwrap()
+                .insert(trader_id.to_string(), session);
+            receivers.push(receiver);
+        }
+        tr_service.report_executions(&sample_trade()).await.unwrap();
+        assert_eq!(
+            receivers[0]
+                .try_recv()
+                .ok()
+                .map(|envelope| envelope.to_string()),
+            Some("1:EXEC:11:7:SELL:APPLE:1.25:5:3".to_string())
+        );
+        assert!(receivers[1].try_recv().is_err());
+        assert_eq!(
+            receivers[2]
+                .try_recv()
+                .ok()
+                .map(|envelope| envelope.to_string()),
+            Some("1:EXEC:11:2:BUY:APPLE:1.25:5:0".to_string())
+        );
+    }
+
     #[test]
     fn test_ack_order() {
-        let expected_result = "ACK:APPLE".to_string();
-        let result = TransactionService::ack_order(Product::Apple);
+        let expected_result = "ACK:APPLE:7".to_string();
+        let result = TransactionService::ack_order(Product::APPLE, 7).to_string();
+        assert_eq!(expected_result, result)
+    }
+
+    #[test]
+    fn test_ack_cancel() {
+        let expected_result = "CANCELED:7".to_string();
+        let result = TransactionService::ack_cancel(7).to_string();
+        assert_eq!(expected_result, result)
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        let transaction = order("0", Side::Buy, "1");
+        tr_service.register_order(order_id, transaction.clone());
+        let result = tr_service.cancel_order("0", order_id);
+        assert_eq!(
+            result.ok(),
+            Some(RestingOrder::new(order_id, transaction).unwrap())
+        );
+        assert!(resting(&tr_service, Side::Buy).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_order_twice() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, order("0", Side::Buy, "1"));
+        assert!(tr_service.cancel_order("0", order_id).is_ok());
+        let result = tr_service.cancel_order("0", order_id);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+    }
+
+    #[test]
+    fn test_cancel_unknown_order() {
+        let tr_service = TransactionService::default();
+        let result = tr_service.cancel_order("0", 42);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+    }
+
+    #[test]
+    fn test_cancel_filled_order() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, order("0", Side::Sell, "1"));
+        tr_service.try_trade_with(tr_service.new_order_id(), order("1", Side::Buy, "1"), 0);
+        let result = tr_service.cancel_order("0", order_id);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+    }
+
+    #[test]
+    fn test_cancel_order_of_other_trader() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, order("0", Side::Buy, "1"));
+        let result = tr_service.cancel_order("1", order_id);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+        assert_eq!(resting(&tr_service, Side::Buy).len(), 1);
+    }
+
+    #[test]
+    fn test_ack_replace() {
+        let expected_result = "REPLACED:7:APPLE:SELL:1.5:3".to_string();
+        let result = TransactionService::ack_replace(
+            &RestingOrder::new(7, sized_order("0", Side::Sell, "1.5", 3)).unwrap(),
+        )
+        .to_string();
         assert_eq!(expected_result, result)
     }
+
+    #[test]
+    fn test_amend_order() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, sized_order("0", Side::Buy, "1", 10));
+        let result = tr_service.amend_order("0", order_id, price("1.1"), 5);
+        let expected = RestingOrder::new(order_id, sized_order("0", Side::Buy, "1.1", 5)).unwrap();
+        assert_eq!(result.ok(), Some((expected.clone(), Vec::new())));
+        assert_eq!(resting(&tr_service, Side::Buy), vec![expected]);
+    }
+
+    #[test]
+    fn test_amend_order_crossing_the_book() {
+        let tr_service = TransactionService::default();
+        tr_service.register_order(
+            tr_service.new_order_id(),
+            sized_order("1", Side::Sell, "1.1", 4),
+        );
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, sized_order("0", Side::Buy, "1", 10));
+        let (order, trades) = tr_service
+            .amend_order("0", order_id, price("1.1"), 10)
+            .unwrap();
+        assert_eq!(order.quantity, 6);
+        assert_eq!(fills(&trades), vec![(price("1.1"), 4, 6)]);
+    }
+
+    #[test]
+    fn test_amend_order_of_other_trader() {
+        let tr_service = TransactionService::default();
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, order("0", Side::Buy, "1"));
+        let result = tr_service.amend_order("1", order_id, price("2"), 1);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+        assert_eq!(resting(&tr_service, Side::Buy)[0].price, price("1"));
+    }
+
+    #[test]
+    fn test_amend_unknown_order() {
+        let tr_service = TransactionService::default();
+        let result = tr_service.amend_order("0", 42, price("2"), 1);
+        assert!(matches!(result, Err(ClientError::UnknownOrder)));
+    }
+
+    #[test]
+    fn test_ack_expiry() {
+        let expected_result = "CANCELED:7:3:IOC".to_string();
+        let result = TransactionService::ack_expiry(7, 3, TimeInForce::Ioc).to_string();
+        assert_eq!(expected_result, result)
+    }
+
+    #[test]
+    fn test_register_market_order() {
+        let tr_service = TransactionService::default();
+        let transaction = Transaction {
+            price: None,
+            time_in_force: TimeInForce::Ioc,
+            ..order("0", Side::Buy, "1")
+        };
+        tr_service.register_order(tr_service.new_order_id(), transaction.clone());
+        assert!(resting(&tr_service, Side::Buy).is_empty());
+    }
+
+    #[test]
+    fn test_try_trade_with_fill_or_kill() {
+        let tr_service = TransactionService::default();
+        tr_service.register_order(
+            tr_service.new_order_id(),
+            sized_order("1", Side::Sell, "1", 4),
+        );
+        tr_service.register_order(
+            tr_service.new_order_id(),
+            sized_order("2", Side::Sell, "1.5", 4),
+        );
+        let transaction = Transaction {
+            time_in_force: TimeInForce::Fok,
+            ..sized_order("0", Side::Buy, "1.2", 5)
+        };
+        assert!(tr_service
+            .try_trade_with(tr_service.new_order_id(), transaction.clone(), 0)
+            .is_empty());
+        assert_eq!(resting(&tr_service, Side::Sell).len(), 2);
+        let transaction = Transaction {
+            quantity: 4,
+            ..transaction
+        };
+        assert_eq!(
+            tr_service
+                .try_trade_with(tr_service.new_order_id(), transaction, 0)
+                .len(),
+            1
+        );
+        assert_eq!(resting(&tr_service, Side::Sell).len(), 1);
+    }
+
+    #[test]
+    fn test_end_of_day() {
+        let tr_service = TransactionService::default();
+        let day_order = Transaction {
+            time_in_force: TimeInForce::Day,
+            ..order("0", Side::Buy, "1")
+        };
+        let order_id = tr_service.new_order_id();
+        tr_service.register_order(order_id, day_order.clone());
+        tr_service.register_order(tr_service.new_order_id(), order("0", Side::Buy, "1"));
+        let expired = tr_service.end_of_day();
+        assert_eq!(
+            expired,
+            vec![RestingOrder::new(order_id, day_order).unwrap()]
+        );
+        assert_eq!(resting(&tr_service, Side::Buy).len(), 1);
+    }
+
+    fn logged_in(tr_service: &TransactionService, name: &str) -> bool {
+        tr_service
+            .traders
+            .read()
+            .unwrap()
+            .get(name)
+            .is_some_and(|session| session.sender.is_some())
+    }
+
+    #[test]
+    fn test_login() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        let (account_id, messages) = tr_service
+            .login(&credentials("alice", "wonderland"), sender)
+            .unwrap();
+        assert_eq!(account_id, "alice");
+        assert_eq!(
+            messages,
+            vec![Envelope::unsequenced(ServerMessage::LoggedIn {
+                account_id: "alice".to_string(),
+                last_sequence: 0,
+            })]
+        );
+        assert!(logged_in(&tr_service, "alice"));
+    }
+
+    #[test]
+    fn test_login_invalid_credentials() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        let result = tr_service.login(&credentials("alice", "looking-glass"), sender);
+        assert!(matches!(result, Err(ClientError::InvalidCredentials)));
+        assert!(!logged_in(&tr_service, "alice"));
+    }
+
+    #[test]
+    fn test_login_twice() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        assert!(tr_service
+            .login(&credentials("alice", "wonderland"), sender.clone())
+            .is_ok());
+        let result = tr_service.login(&credentials("alice", "wonderland"), sender.clone());
+        assert!(matches!(result, Err(ClientError::AlreadyLoggedIn)));
+        tr_service.remove_trader("alice");
+        assert!(!logged_in(&tr_service, "alice"));
+        assert!(tr_service
+            .login(&credentials("alice", "wonderland"), sender)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_messages() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        tr_service
+            .login(&credentials("alice", "wonderland"), sender)
+            .unwrap();
+        tr_service
+            .confirm("alice", Product::APPLE, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            receiver
+                .try_recv()
+                .ok()
+                .map(|envelope| envelope.to_string()),
+            Some("1:ACK:APPLE:1".to_string())
+        );
+        tr_service.remove_trader("alice");
+        tr_service.confirm_cancel("alice", 1).await.unwrap();
+        tr_service.inform_all(&sample_trade()).await.unwrap();
+        assert!(receiver.try_recv().is_err());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        let resume = Credentials {
+            last_seen: Some(1),
+            ..credentials("alice", "wonderland")
+        };
+        let (_, messages) = tr_service.login(&resume, sender).unwrap();
+        let messages: Vec<String> = messages.iter().map(Envelope::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "LOGGED_IN:alice:3".to_string(),
+                "2:CANCELED:1".to_string(),
+                "3:TRADE:APPLE:1.25:5".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resume_past_last_sequence() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        let resume = Credentials {
+            last_seen: Some(Sequence::MAX),
+            ..credentials("alice", "wonderland")
+        };
+        let result = tr_service.login(&resume, sender);
+        assert!(matches!(result, Err(ClientError::InvalidSequence)));
+        assert!(!logged_in(&tr_service, "alice"));
+    }
+
+    #[test]
+    fn test_cancel_on_disconnect() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        tr_service
+            .login(&credentials("alice", "wonderland"), sender.clone())
+            .unwrap();
+        tr_service.register_order(tr_service.new_order_id(), order("alice", Side::Buy, "1"));
+        tr_service.register_order(tr_service.new_order_id(), order("alice", Side::Sell, "2"));
+        tr_service.register_order(tr_service.new_order_id(), order("bob", Side::Sell, "3"));
+        let login = tr_service.remove_trader("alice");
+        let cancelled = tr_service.cancel_on_disconnect("alice", login);
+        assert_eq!(cancelled.len(), 2);
+        assert!(resting(&tr_service, Side::Buy).is_empty());
+        assert_eq!(resting(&tr_service, Side::Sell).len(), 1);
+    }
+
+    #[test]
+    fn test_server_wide_disconnect_policy() {
+        let cancel = DisconnectPolicy::Cancel {
+            grace: std::time::Duration::from_secs(5),
+        };
+        let settings = Settings {
+            disconnect_policy: cancel,
+            ..Default::default()
+        };
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder:keep").unwrap();
+        let tr_service = TransactionService::with_settings(accounts, settings, Default::default());
+        assert_eq!(tr_service.disconnect_policy("alice"), cancel);
+        assert_eq!(tr_service.disconnect_policy("bob"), DisconnectPolicy::Keep);
+    }
+
+    #[test]
+    fn test_cancel_on_disconnect_after_coming_back() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, _receiver) = tokio::sync::mpsc::channel(10);
+        tr_service
+            .login(&credentials("alice", "wonderland"), sender.clone())
+            .unwrap();
+        tr_service.register_order(tr_service.new_order_id(), order("alice", Side::Buy, "1"));
+        let login = tr_service.remove_trader("alice");
+        tr_service
+            .login(&credentials("alice", "wonderland"), sender.clone())
+            .unwrap();
+        assert!(tr_service.cancel_on_disconnect("alice", login).is_empty());
+        tr_service.remove_trader("alice");
+        assert!(tr_service.cancel_on_disconnect("alice", login).is_empty());
+        assert_eq!(resting(&tr_service, Side::Buy).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_market_data_subscription() {
+        let tr_service = TransactionService::new(Accounts::from_str("alice:wonderland").unwrap());
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        tr_service
+            .login(&credentials("alice", "wonderland"), sender)
+            .unwrap();
+        tr_service.register_order(tr_service.new_order_id(), order("bob", Side::Buy, "1"));
+        assert_eq!(
+            tr_service.subscribe("alice", Product::from_str("GME").unwrap()),
+            Err(ClientError::UnknownProduct)
+        );
+        tr_service.subscribe("alice", Product::APPLE).unwrap();
+        tr_service
+            .place_order(sized_order("bob", Side::Buy, "1", 2))
+            .await
+            .unwrap();
+        tr_service
+            .place_order(sized_order("bob", Side::Sell, "1.5", 4))
+            .await
+            .unwrap();
+        let order_id = resting(&tr_service, Side::Sell)[0].order_id;
+        tr_service.cancel_order("bob", order_id).unwrap();
+        tr_service
+            .unsubscribe("alice", Product::APPLE)
+            .await
+            .unwrap();
+        tr_service
+            .place_order(sized_order("bob", Side::Sell, "2", 1))
+            .await
+            .unwrap();
+
+        let messages: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|envelope| envelope.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "BOOK:APPLE:0:1@1:",
+                "BOOK_UPDATE:APPLE:1:MODIFY:BUY:1:3",
+                "BOOK_UPDATE:APPLE:2:ADD:SELL:1.5:4",
+                "BOOK_UPDATE:APPLE:3:DELETE:SELL:1.5:0",
+                "UNSUBSCRIBED:APPLE",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_place_order() {
+        let tr_service = TransactionService::default();
+        let placement = tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 5))
+            .await
+            .unwrap();
+        assert_eq!((placement.order_id, placement.leaves), (1, 5));
+        assert!(placement.resting);
+        let placement = tr_service
+            .place_order(Transaction {
+                time_in_force: TimeInForce::Ioc,
+                ..sized_order("bob", Side::Buy, "1", 7)
+            })
+            .await
+            .unwrap();
+        assert_eq!(fills(&placement.trades), vec![(price("1"), 5, 2)]);
+        assert_eq!(placement.leaves, 2);
+        assert!(!placement.resting);
+        assert!(resting(&tr_service, Side::Buy).is_empty());
+        assert_eq!(tr_service.recent_trades(), placement.trades);
+    }
+
+    #[test]
+    fn test_open_orders() {
+        let tr_service = TransactionService::default();
+        tr_service.register_order(tr_service.new_order_id(), order("alice", Side::Sell, "2"));
+        tr_service.register_order(tr_service.new_order_id(), order("bob", Side::Sell, "1"));
+        tr_service.register_order(tr_service.new_order_id(), order("alice", Side::Buy, "0.5"));
+        let order_ids: Vec<OrderId> = tr_service
+            .open_orders("alice")
+            .iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(order_ids, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_risk_limits() {
+        let settings = Settings {
+            risk: RiskLimits {
+                max_order_quantity: Some(10),
+                max_order_value: Some(price("20")),
+            },
+            ..Settings::default()
+        };
+        let tr_service = TransactionService::with_settings(
+            Accounts::default(),
+            settings,
+            Instruments::default(),
+        );
+        let result = tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 11))
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ClientError(ClientError::QuantityLimitExceeded(10)))
+        ));
+        let result = tr_service
+            .place_order(sized_order("alice", Side::Sell, "2.5", 10))
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ClientError(ClientError::ValueLimitExceeded(_)))
+        ));
+        assert!(resting(&tr_service, Side::Sell).is_empty());
+        let placement = tr_service
+            .place_order(sized_order("alice", Side::Sell, "2", 10))
+            .await
+            .unwrap();
+        assert_eq!(
+            tr_service.amend_order("alice", placement.order_id, price("2.01"), 10),
+            Err(ClientError::ValueLimitExceeded(price("20")))
+        );
+        // Market orders have no value, only their quantity counts
+        let market = Transaction {
+            price: None,
+            time_in_force: TimeInForce::Ioc,
+            ..sized_order("bob", Side::Buy, "1", 10)
+        };
+        assert_eq!(tr_service.place_order(market).await.unwrap().leaves, 0);
+    }
+
+    #[tokio::test]
+    async fn test_instrument_rules() {
+        let instruments = Instruments::new(vec![Instrument {
+            lot_size: 10,
+            min_quantity: 10,
+            ..Instrument::new(Product::APPLE)
+        }]);
+        let tr_service = TransactionService::with_settings(
+            Accounts::default(),
+            Settings::default(),
+            instruments,
+        );
+        let result = tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 15))
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ClientError(ClientError::InvalidLot(10)))
+        ));
+        let result = tr_service
+            .place_order(Transaction {
+                product: Product::PEAR,
+                ..order("alice", Side::Sell, "1")
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ClientError(ClientError::UnknownProduct))
+        ));
+        let placement = tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 20))
+            .await
+            .unwrap();
+        assert_eq!(
+            tr_service.amend_order("alice", placement.order_id, price("1"), 5),
+            Err(ClientError::InvalidLot(10))
+        );
+        // Only the first order got an id
+        assert_eq!(tr_service.new_order_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delist() {
+        let tr_service = TransactionService::default();
+        let kiwi = Product::from_str("KIWI").unwrap();
+        let kiwi_order = Transaction {
+            product: kiwi,
+            ..order("alice", Side::Sell, "1")
+        };
+        assert!(tr_service.place_order(kiwi_order.clone()).await.is_err());
+        assert_eq!(tr_service.list_instrument(Instrument::new(kiwi)), None);
+        let placement = tr_service.place_order(kiwi_order.clone()).await.unwrap();
+        assert!(placement.resting);
+
+        let (instrument, orders) = tr_service.delist_instrument(kiwi).unwrap();
+        assert_eq!(instrument, Instrument::new(kiwi));
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].order_id, placement.order_id);
+        assert!(tr_service.open_orders("alice").is_empty());
+        assert!(tr_service.place_order(kiwi_order).await.is_err());
+        assert_eq!(
+            tr_service
+                .delist_instrument(kiwi)
+                .map(|(instrument, _)| instrument),
+            Err(ClientError::UnknownProduct)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recover_from_journal() {
+        let dir = TempDir::new("recover");
+        let tr_service = TransactionService::default();
+        assert_eq!(tr_service.recover(file_storage(&dir)).unwrap(), 0);
+        for transaction in [
+            sized_order("alice", Side::Sell, "1", 5),
+            sized_order("alice", Side::Sell, "1.5", 5),
+            sized_order("alice", Side::Sell, "2", 5),
+            sized_order("bob", Side::Buy, "1.5", 7),
+            Transaction {
+                time_in_force: TimeInForce::Ioc,
+                ..sized_order("bob", Side::Buy, "0.5", 1)
+            },
+        ] {
+            tr_service.place_order(transaction).await.unwrap();
+        }
+        tr_service.cancel_order("alice", 3).unwrap();
+        tr_service.amend_order("alice", 2, price("1.5"), 2).unwrap();
+        drop(tr_service);
+        // The server crashed halfway through journaling an order
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.0.join("journal-00000000000000000000.jsonl"))
+            .unwrap();
+        std::io::Write::write_all(&mut journal, br#"{"type":"order","order_id":6,"tr"#).unwrap();
+
+        let recovered = TransactionService::default();
+        assert_eq!(recovered.recover(file_storage(&dir)).unwrap(), 7);
+        let orders: Vec<_> = recovered
+            .open_orders("alice")
+            .iter()
+            .map(|order| (order.order_id, order.price, order.quantity))
+            .collect();
+        assert_eq!(orders, vec![(2, price("1.5"), 2)]);
+        assert_eq!(
+            fills(&recovered.recent_trades()),
+            vec![(price("1.5"), 2, 0), (price("1"), 5, 2)]
+        );
+        assert_eq!(recovered.new_order_id(), 6);
+        // Trading carries on where it stopped
+        let placement = recovered
+            .place_order(sized_order("bob", Side::Buy, "2", 2))
+            .await
+            .unwrap();
+        assert_eq!(placement.trades[0].trade_id, 3);
+        drop(recovered);
+        let again = TransactionService::default();
+        assert_eq!(again.recover(file_storage(&dir)).unwrap(), 8);
+        assert!(again.open_orders("alice").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recover_listings() {
+        let dir = TempDir::new("listings");
+        let tr_service = TransactionService::default();
+        tr_service.recover(file_storage(&dir)).unwrap();
+        let kiwi = Product::from_str("KIWI").unwrap();
+        let mango = Product::from_str("MANGO").unwrap();
+        let lots = Instrument {
+            lot_size: 5,
+            ..Instrument::new(kiwi)
+        };
+        tr_service.list_instrument(lots);
+        let placement = tr_service
+            .place_order(Transaction {
+                product: kiwi,
+                ..sized_order("alice", Side::Sell, "1", 5)
+            })
+            .await
+            .unwrap();
+        assert!(placement.resting);
+        tr_service.delist_instrument(Product::APPLE).unwrap();
+        assert_eq!(tr_service.snapshot().unwrap(), Some(3));
+        tr_service.list_instrument(Instrument::new(mango));
+        tr_service.delist_instrument(mango).unwrap();
+        tr_service.list_instrument(Instrument::new(mango));
+        let expected = tr_service.instruments();
+        drop(tr_service);
+
+        let recovered = TransactionService::default();
+        assert_eq!(recovered.recover(file_storage(&dir)).unwrap(), 3);
+        assert_eq!(recovered.instruments(), expected);
+        assert_eq!(recovered.instrument(kiwi), Ok(lots));
+        assert_eq!(
+            recovered.instrument(Product::APPLE),
+            Err(ClientError::UnknownProduct)
+        );
+        assert_eq!(recovered.open_orders("alice").len(), 1);
+    }
+
+    #[test]
+    fn test_recover_from_inconsistent_journal() {
+        let dir = TempDir::new("inconsistent");
+        std::fs::create_dir_all(&dir.0).unwrap();
+        std::fs::write(
+            dir.0.join("journal-00000000000000000000.jsonl"),
+            "{\"type\":\"cancel\",\"order_id\":1}\n",
+        )
+        .unwrap();
+        let tr_service = TransactionService::default();
+        assert!(matches!(
+            tr_service.recover(file_storage(&dir)),
+            Err(Error::InvalidJournal(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recover_from_snapshot() {
+        let dir = TempDir::new("snapshot");
+        let tr_service = TransactionService::new(Accounts::from_str("alice:a\nbob:b").unwrap());
+        tr_service.recover(file_storage(&dir)).unwrap();
+        assert_eq!(tr_service.snapshot().unwrap(), None);
+        let (sender, _receiver) = tokio::sync::mpsc::channel(100);
+        tr_service
+            .login(&credentials("alice", "a"), sender)
+            .unwrap();
+        for transaction in [
+            sized_order("alice", Side::Sell, "1", 5),
+            sized_order("alice", Side::Sell, "1", 3),
+            sized_order("alice", Side::Sell, "2", 5),
+            sized_order("bob", Side::Buy, "1", 2),
+        ] {
+            tr_service.place_order(transaction).await.unwrap();
+        }
+        assert_eq!(tr_service.snapshot().unwrap(), Some(4));
+        assert_eq!(tr_service.snapshot().unwrap(), None);
+        tr_service.cancel_order("alice", 3).unwrap();
+        assert_eq!(tr_service.snapshot().unwrap(), Some(5));
+        tr_service
+            .place_order(sized_order("bob", Side::Buy, "1", 4))
+            .await
+            .unwrap();
+        assert_eq!(
+            dir.files(),
+            vec![
+                "journal-00000000000000000005.jsonl",
+                "snapshot-00000000000000000005.json",
+                "trades.jsonl"
+            ]
+        );
+        let expected = tr_service.open_orders("alice");
+        let sequence = tr_service.traders.read().unwrap()["alice"].last_sequence();
+        drop(tr_service);
+
+        let recovered = TransactionService::new(Accounts::from_str("alice:a").unwrap());
+        assert_eq!(recovered.recover(file_storage(&dir)).unwrap(), 1);
+        // The second order keeps its place behind the first one
+        assert_eq!(recovered.open_orders("alice"), expected);
+        assert_eq!(
+            fills(&recovered.recent_trades()),
+            vec![(price("1"), 1, 0), (price("1"), 3, 1), (price("1"), 2, 0)]
+        );
+        assert_eq!(recovered.new_order_id(), 6);
+        // Numbering carries on after the messages sent since the snapshot
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
+        recovered.login(&credentials("alice", "a"), sender).unwrap();
+        recovered.confirm_cancel("alice", 2).await.unwrap();
+        let next = receiver.try_recv().unwrap().sequence.unwrap();
+        assert!(
+            next > sequence,
+            "{} reuses a number up to {}",
+            next,
+            sequence
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trades_go_to_the_ledger() {
+        let dir = TempDir::new("service_ledger");
+        let ledger = dir.0.join("trades.jsonl");
+        let tr_service = TransactionService::default();
+        tr_service.recover(file_storage(&dir)).unwrap();
+        tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 5))
+            .await
+            .unwrap();
+        tr_service
+            .place_order(sized_order("bob", Side::Buy, "1", 2))
+            .await
+            .unwrap();
+        tr_service.amend_order("alice", 1, price("0.5"), 4).unwrap();
+        drop(tr_service);
+        // Everything but the timestamp of each trade
+        let exported = || {
+            let mut csv = Vec::new();
+            crate::ledger::export_csv(&ledger, 0, 1_000_000, &mut csv).unwrap();
+            String::from_utf8(csv)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| {
+                    let mut fields: Vec<_> = line.split(',').collect();
+                    fields.remove(1);
+                    fields.join(",")
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(exported(), vec!["1,APPLE,1,2,bob,alice,BUY"]);
+
+        // A crash right after journaling an order leaves its trades out of the ledger
+        let written = std::fs::read_to_string(&ledger).unwrap();
+        std::fs::write(&ledger, "").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let recovered = TransactionService::default();
+        recovered.recover(file_storage(&dir)).unwrap();
+        assert_eq!(exported(), vec!["1,APPLE,1,2,bob,alice,BUY"]);
+        // Recovered trades keep the time they were made
+        assert_eq!(std::fs::read_to_string(&ledger).unwrap(), written);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_reservations() {
+        let storage = SharedStorage::default();
+        let tr_service = TransactionService::new(Accounts::from_str("alice:a").unwrap());
+        tr_service.recover(Box::new(storage.clone())).unwrap();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(100);
+        tr_service
+            .login(&credentials("alice", "a"), sender)
+            .unwrap();
+        // Nothing else is stored for a reject, so it reserves a block itself
+        tr_service
+            .reject("alice", ClientError::UnknownOrder, None)
+            .await
+            .unwrap();
+        tr_service
+            .reject("alice", ClientError::UnknownOrder, None)
+            .await
+            .unwrap();
+        let reserved = BTreeMap::from([("alice".to_string(), SEQUENCE_BLOCK)]);
+        let stored = storage.0.lock().unwrap().load().unwrap().1;
+        assert_eq!(
+            stored,
+            vec![Record::Sequences {
+                sequences: reserved.clone()
+            }]
+        );
+        // The order's record reserves what is left to tell about it
+        tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 5))
+            .await
+            .unwrap();
+        let stored = storage.0.lock().unwrap().load().unwrap().1;
+        assert_eq!(stored.len(), 2);
+        assert!(matches!(&stored[1], Record::Order { sequences, .. } if sequences.is_empty()));
+
+        let recovered = TransactionService::default();
+        recovered.recover(Box::new(storage)).unwrap();
+        assert_eq!(
+            recovered.traders.read().unwrap()["alice"].last_sequence(),
+            SEQUENCE_BLOCK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_injected_storage() {
+        let storage = SharedStorage::default();
+        let tr_service = TransactionService::default();
+        tr_service.recover(Box::new(storage.clone())).unwrap();
+        tr_service
+            .place_order(sized_order("alice", Side::Sell, "1", 5))
+            .await
+            .unwrap();
+        tr_service
+            .place_order(sized_order("bob", Side::Buy, "1", 2))
+            .await
+            .unwrap();
+        tr_service.cancel_order("alice", 1).unwrap();
+        let stored = storage.0.lock().unwrap().load().unwrap().1;
+        assert_eq!(stored.len(), 3);
+        assert_eq!(stored[2], Record::Cancel { order_id: 1 });
+        let trades: Vec<_> = storage
+            .0
+            .lock()
+            .unwrap()
+            .trades()
+            .iter()
+            .map(|entry| (entry.trade_id, entry.buyer.clone(), entry.seller.clone()))
+            .collect();
+        assert_eq!(trades, vec![(1, "bob".to_string(), "alice".to_string())]);
+
+        assert_eq!(tr_service.snapshot().unwrap(), Some(3));
+        let recovered = TransactionService::default();
+        assert_eq!(recovered.recover(Box::new(storage)).unwrap(), 0);
+        assert!(recovered.open_orders("alice").is_empty());
+        assert_eq!(fills(&recovered.recent_trades()), vec![(price("1"), 2, 0)]);
+    }
 }
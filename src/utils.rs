@@ -1,17 +1,33 @@
-use crate::consts::{SHOW_LOG_LEVEL, SHOW_LOG_TARGET};
+use crate::config::{LogConfig, LogFormat};
+use crate::consts::END_OF_DAY_UTC;
 use crate::errors::Error;
 use env_logger::{Builder, Target};
-use log::LevelFilter;
+use serde_json::json;
+use std::io::Write;
 use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn init_logs() {
-    Builder::from_default_env()
-        .filter_level(LevelFilter::Info)
-        .target(Target::Stdout)
-        .format_target(SHOW_LOG_TARGET)
-        .format_level(SHOW_LOG_LEVEL)
-        .format_timestamp_secs()
-        .init()
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub fn init_logs(config: &LogConfig) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(config.level).target(Target::Stdout);
+    match config.format {
+        LogFormat::Text => builder
+            .format_target(config.show_target)
+            .format_level(config.show_level)
+            .format_timestamp_secs(),
+        LogFormat::Json => builder.format(|buf, record| {
+            let line = json!({
+                "time": buf.timestamp_seconds().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        }),
+    };
+    builder.init()
 }
 
 pub fn get_greeting_message(ip: IpAddr, port: u16) -> Result<String, Error> {
@@ -29,11 +45,79 @@ pub fn split_at_colon(message: &str) -> Option<(String, String)> {
     })
 }
 
+/// Milliseconds since the Unix epoch, how trades are timestamped.
+pub fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub fn time_until_end_of_day(now: SystemTime) -> Duration {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let seconds_today = now % SECONDS_PER_DAY;
+    match (END_OF_DAY_UTC + SECONDS_PER_DAY - seconds_today) % SECONDS_PER_DAY {
+        0 => Duration::from_secs(SECONDS_PER_DAY),
+        seconds => Duration::from_secs(seconds),
+    }
+}
+
+/// Gregorian date of a day count since 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Day count since 1970-01-01 of a Gregorian date, the inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Day count since 1970-01-01 of a `YYYY-MM-DD` date.
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let invalid = || format!("'{}' should be a date, as YYYY-MM-DD", date);
+    let mut parts = date.splitn(3, '-');
+    let mut part = |length: usize| {
+        parts
+            .next()
+            .filter(|part| part.len() == length && part.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|part| part.parse::<u32>().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (part(4)?, part(2)?, part(2)?);
+    let days = days_from_civil(i64::from(year), month, day);
+    // Rejects days past the end of their month, e.g. 2023-02-29
+    if !(1..=12).contains(&month) || civil_from_days(days) != (i64::from(year), month, day) {
+        return Err(invalid());
+    }
+    Ok(days)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_split_at_colon() {
         let s = "buy:apple";
         match split_at_colon(s) {
@@ -46,6 +130,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_split_at_colon_with_spaces() {
         let s = "buy : apple  ";
         match split_at_colon(s) {
@@ -58,6 +143,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_split_at_colon_with_newline() {
         let s = "buy : apple  \n onion";
         match split_at_colon(s) {
@@ -82,6 +168,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_split_at_colon_no_action_newline_before_colon() {
         let s = "buy:\n apple";
         match split_at_colon(s) {
@@ -92,4 +179,43 @@ mod tests {
             None => assert!(false),
         }
     }
+
+    #[test]
+    fn test_time_until_end_of_day() {
+        let midnight = UNIX_EPOCH + Duration::from_secs(SECONDS_PER_DAY * 10_000);
+        assert_eq!(
+            time_until_end_of_day(midnight),
+            Duration::from_secs(END_OF_DAY_UTC)
+        );
+        let one_second_before = midnight + Duration::from_secs(END_OF_DAY_UTC - 1);
+        assert_eq!(
+            time_until_end_of_day(one_second_before),
+            Duration::from_secs(1)
+        );
+        let end_of_day = midnight + Duration::from_secs(END_OF_DAY_UTC);
+        assert_eq!(
+            time_until_end_of_day(end_of_day),
+            Duration::from_secs(SECONDS_PER_DAY)
+        );
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2024-02-29"), Ok(19_782));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(parse_date("1969-12-31"), Ok(-1));
+        for date in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-1-01",
+            "24-01-01",
+            "2024-01-01T00",
+        ] {
+            assert_eq!(
+                parse_date(date),
+                Err(format!("'{}' should be a date, as YYYY-MM-DD", date))
+            );
+        }
+    }
 }
@@ -0,0 +1,182 @@
+use crate::errors::Error;
+use crate::messages::Envelope;
+use crate::protocol::{Connection, TextCodec};
+use crate::tls::Stream;
+use bytes::BytesMut;
+use futures::SinkExt;
+use std::collections::VecDeque;
+use tokio_stream::StreamExt;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Text or JSON lines over WebSocket, for browsers. A message can hold several
+/// lines; every message we send holds one.
+pub struct WebSocket {
+    socket: WebSocketStream<Stream>,
+    codec: TextCodec,
+    /// Received lines not handed out yet.
+    lines: VecDeque<String>,
+}
+
+impl WebSocket {
+    pub async fn accept(stream: Stream, codec: TextCodec) -> Result<Self, Error> {
+        Ok(Self {
+            socket: accept_async(stream).await?,
+            codec,
+            lines: VecDeque::new(),
+        })
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let mut buffer = BytesMut::from(payload);
+        while let Some(line) = self.codec.decode(&mut buffer)? {
+            self.lines.push_back(line);
+        }
+        if let Some(line) = self.codec.decode_eof(&mut buffer)? {
+            self.lines.push_back(line);
+        }
+        Ok(())
+    }
+}
+
+impl Connection for WebSocket {
+    type Codec = TextCodec;
+
+    fn codec_mut(&mut self) -> &mut TextCodec {
+        &mut self.codec
+    }
+
+    async fn next_frame(&mut self) -> Option<Result<String, Error>> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Some(Ok(line));
+            }
+            let decoded = match self.socket.next().await? {
+                Ok(Message::Text(text)) => self.decode(text.as_bytes()),
+                Ok(Message::Binary(bytes)) => self.decode(&bytes),
+                Ok(Message::Close(_)) => return None,
+                // Pings are answered by the socket itself
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = decoded {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    async fn send(&mut self, envelope: Envelope) -> Result<(), Error> {
+        let mut buffer = BytesMut::new();
+        self.codec.encode(envelope, &mut buffer)?;
+        self.send_encoded(buffer).await
+    }
+
+    async fn send_encoded(&mut self, frames: BytesMut) -> Result<(), Error> {
+        for line in String::from_utf8_lossy(&frames).lines() {
+            self.socket.feed(Message::text(line)).await?;
+        }
+        Ok(self.socket.flush().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::transaction_service::TransactionService;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{connect_async, MaybeTlsStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// WebSocket and text listeners trading on the same book.
+    async fn start() -> (SocketAddr, SocketAddr) {
+        let accounts = Accounts::from_str("alice:wonderland\nbob:builder").unwrap();
+        let service = Arc::new(TransactionService::new(accounts));
+        let websocket_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let text_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addresses = (
+            websocket_listener.local_addr().unwrap(),
+            text_listener.local_addr().unwrap(),
+        );
+        let websocket_service = Arc::clone(&service);
+        tokio::task::spawn(crate::listen(
+            websocket_listener,
+            websocket_service,
+            None,
+            |stream| WebSocket::accept(stream, TextCodec::default()),
+        ));
+        tokio::task::spawn(crate::listen(
+            text_listener,
+            service,
+            None,
+            crate::framed(TextCodec::default),
+        ));
+        addresses
+    }
+
+    async fn receive(client: &mut Client) -> String {
+        let message = tokio::time::timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("no WebSocket message within a second")
+            .unwrap()
+            .unwrap();
+        message.into_text().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_websocket_trader_trades_with_tcp_trader() {
+        let (websocket_address, text_address) = start().await;
+        let (mut alice, _) = connect_async(format!("ws://{}", websocket_address))
+            .await
+            .unwrap();
+        alice
+            .send(Message::text("LOGIN:alice:wonderland\nsell:apple:1.25:10"))
+            .await
+            .unwrap();
+        assert_eq!(receive(&mut alice).await, "LOGGED_IN:alice:0");
+        assert_eq!(receive(&mut alice).await, "1:ACK:APPLE:1");
+
+        let mut bob = BufReader::new(TcpStream::connect(text_address).await.unwrap());
+        bob.write_all(b"LOGIN:bob:builder\nbuy:apple:1.25:4\n")
+            .await
+            .unwrap();
+        let mut line = String::new();
+        bob.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "LOGGED_IN:bob:0\n");
+
+        assert_eq!(receive(&mut alice).await, "2:EXEC:1:1:SELL:APPLE:1.25:4:6");
+        assert_eq!(receive(&mut alice).await, "3:TRADE:APPLE:1.25:4");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_json() {
+        let (websocket_address, _) = start().await;
+        let (mut alice, _) = connect_async(format!("ws://{}", websocket_address))
+            .await
+            .unwrap();
+        alice
+            .send(Message::text(
+                r#"{"type":"login","name":"alice","secret":"wonderland"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&mut alice).await,
+            r#"{"type":"logged_in","account":"alice","last_sequence":0}"#
+        );
+        alice
+            .send(Message::text(r#"{"type":"cancel","order_id":7}"#))
+            .await
+            .unwrap();
+        assert!(receive(&mut alice)
+            .await
+            .contains(r#""code":"UNKNOWN_ORDER""#));
+    }
+}